@@ -20,18 +20,44 @@ pub enum ReplError {
     #[error("value error: {0}")]
     ValueError(String),
 
+    #[error("attribute error: {0}")]
+    AttributeError(String),
+
     #[error("resource limit exceeded: {0}")]
     ResourceLimitExceeded(String),
 
     #[error("runtime error: {0}")]
     RuntimeError(String),
 
-    #[error("SystemExit")]
-    SystemExit,
+    /// `raise SystemExit` / `sys.exit([code])`, with the code or message when one was given.
+    #[error("SystemExit{}", exit_suffix(.0))]
+    SystemExit(Option<String>),
+}
+
+fn exit_suffix(code: &Option<String>) -> String {
+    code.as_deref()
+        .map(|c| format!(": {c}"))
+        .unwrap_or_default()
 }
 
 impl ReplError {
+    /// Stable variant name, so callers can branch on the error type without parsing text.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ReplError::ParseError(_) => "ParseError",
+            ReplError::ForbiddenSyntax(_) => "ForbiddenSyntax",
+            ReplError::ForbiddenName(_) => "ForbiddenName",
+            ReplError::NameError(_) => "NameError",
+            ReplError::TypeError(_) => "TypeError",
+            ReplError::ValueError(_) => "ValueError",
+            ReplError::AttributeError(_) => "AttributeError",
+            ReplError::ResourceLimitExceeded(_) => "ResourceLimitExceeded",
+            ReplError::RuntimeError(_) => "RuntimeError",
+            ReplError::SystemExit(_) => "SystemExit",
+        }
+    }
+
     pub fn subset_hint() -> &'static str {
-        "Note: this REPL is a restricted Python subset; some constructs are unsupported by design (e.g., while, complex comprehensions, type(), with, class, lambda, reflection builtins)."
+        "Note: this REPL is a restricted Python subset; some constructs are unsupported by design (e.g., complex comprehensions, type(), with, class, lambda, reflection builtins)."
     }
 }
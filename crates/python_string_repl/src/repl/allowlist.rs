@@ -2,7 +2,9 @@ use crate::error::ReplError;
 
 use rustpython_parser::ast;
 
+use super::builtins::MODULE_NAMES;
 use super::parse::Program;
+use super::{ImportPolicy, ReplConfig};
 
 const FORBIDDEN_NAMES: &[&str] = &[
     "__import__",
@@ -17,157 +19,414 @@ const FORBIDDEN_NAMES: &[&str] = &[
     "delattr",
 ];
 
-pub fn validate(program: &Program) -> Result<(), ReplError> {
+/// Exception names an `except` clause may name. Every runtime error counts as `Exception`;
+/// `ImportError` only exists so defensive conditional imports validate.
+const HANDLED_EXCEPTIONS: &[&str] = &["Exception", "ImportError"];
+
+pub fn validate(program: &Program, cfg: &ReplConfig) -> Result<(), ReplError> {
+    let mut v = Validator {
+        depth: 0,
+        max_depth: cfg.max_nesting_depth,
+        loop_depth: 0,
+        import_policy: cfg.import_policy,
+        allowed_attributes: cfg.allowed_attributes.as_deref(),
+    };
     for stmt in program {
-        validate_stmt(stmt)?;
+        v.validate_stmt(stmt)?;
     }
     Ok(())
 }
 
-fn validate_stmt(stmt: &ast::Stmt) -> Result<(), ReplError> {
-    use ast::Stmt::*;
-    match stmt {
-        Assign(s) => {
-            for t in &s.targets {
-                match t {
+/// Walks the AST, tracking how deeply statements and expressions nest so adversarial input
+/// (thousands of nested brackets) is rejected before the recursive evaluator sees it.
+struct Validator<'a> {
+    depth: usize,
+    max_depth: usize,
+    /// Enclosing `for` bodies in the current function (or top level), for `break`/`continue`.
+    loop_depth: usize,
+    import_policy: ImportPolicy,
+    /// `ReplConfig::allowed_attributes`; `None` permits any non-underscore attribute.
+    allowed_attributes: Option<&'a [String]>,
+}
+
+impl Validator<'_> {
+    fn enter(&mut self) -> Result<(), ReplError> {
+        self.depth += 1;
+        if self.depth > self.max_depth {
+            return Err(ReplError::ForbiddenSyntax("nesting too deep".into()));
+        }
+        Ok(())
+    }
+
+    fn validate_stmt(&mut self, stmt: &ast::Stmt) -> Result<(), ReplError> {
+        self.enter()?;
+        let res = self.check_stmt(stmt);
+        self.depth -= 1;
+        res
+    }
+
+    fn validate_expr(&mut self, expr: &ast::Expr) -> Result<(), ReplError> {
+        self.enter()?;
+        let res = self.check_expr(expr);
+        self.depth -= 1;
+        res
+    }
+
+    fn check_stmt(&mut self, stmt: &ast::Stmt) -> Result<(), ReplError> {
+        use ast::Stmt::*;
+        match stmt {
+            Assign(s) => {
+                for t in &s.targets {
+                    match t {
+                        ast::Expr::Name(n) => validate_name(n.id.as_str())?,
+                        // Item assignment on a plain name: `buf[i] = v`.
+                        ast::Expr::Subscript(sub) => match sub.value.as_ref() {
+                            ast::Expr::Name(n) => {
+                                validate_name(n.id.as_str())?;
+                                self.validate_expr(&sub.slice)?;
+                            }
+                            _ => return Err(ReplError::ForbiddenSyntax("assign target".into())),
+                        },
+                        ast::Expr::Tuple(t) => validate_unpack_target(&t.elts, "assign target")?,
+                        ast::Expr::List(t) => validate_unpack_target(&t.elts, "assign target")?,
+                        _ => return Err(ReplError::ForbiddenSyntax("assign target".into())),
+                    }
+                }
+                self.validate_expr(&s.value)?;
+                Ok(())
+            }
+            AugAssign(s) => {
+                // Allow `x += expr` and similar on simple names only.
+                match s.target.as_ref() {
                     ast::Expr::Name(n) => validate_name(n.id.as_str())?,
-                    _ => return Err(ReplError::ForbiddenSyntax("assign target".into())),
+                    _ => return Err(ReplError::ForbiddenSyntax("augassign target".into())),
                 }
+                self.validate_expr(&s.value)?;
+                Ok(())
             }
-            validate_expr(&s.value)?;
-            Ok(())
-        }
-        AugAssign(s) => {
-            // Allow `x += expr` and similar on simple names only.
-            match s.target.as_ref() {
-                ast::Expr::Name(n) => validate_name(n.id.as_str())?,
-                _ => return Err(ReplError::ForbiddenSyntax("augassign target".into())),
+            Expr(s) => self.validate_expr(&s.value),
+            If(s) => {
+                self.validate_expr(&s.test)?;
+                for st in &s.body {
+                    self.validate_stmt(st)?;
+                }
+                for st in &s.orelse {
+                    self.validate_stmt(st)?;
+                }
+                Ok(())
             }
-            validate_expr(&s.value)?;
-            Ok(())
-        }
-        Expr(s) => validate_expr(&s.value),
-        If(s) => {
-            validate_expr(&s.test)?;
-            for st in &s.body {
-                validate_stmt(st)?;
+            Pass(_) => Ok(()),
+            For(s) => {
+                match s.target.as_ref() {
+                    ast::Expr::Name(n) => validate_name(n.id.as_str())?,
+                    ast::Expr::Tuple(t) => validate_unpack_target(&t.elts, "for target")?,
+                    ast::Expr::List(t) => validate_unpack_target(&t.elts, "for target")?,
+                    _ => return Err(ReplError::ForbiddenSyntax("for target".into())),
+                };
+                self.validate_expr(&s.iter)?;
+                self.loop_depth += 1;
+                let body = s.body.iter().try_for_each(|st| self.validate_stmt(st));
+                self.loop_depth -= 1;
+                body?;
+                for st in &s.orelse {
+                    self.validate_stmt(st)?;
+                }
+                Ok(())
             }
-            for st in &s.orelse {
-                validate_stmt(st)?;
+            While(s) => {
+                self.validate_expr(&s.test)?;
+                self.loop_depth += 1;
+                let body = s.body.iter().try_for_each(|st| self.validate_stmt(st));
+                self.loop_depth -= 1;
+                body?;
+                for st in &s.orelse {
+                    self.validate_stmt(st)?;
+                }
+                Ok(())
             }
-            Ok(())
-        }
-        Pass(_) => Ok(()),
-        For(s) => {
-            match s.target.as_ref() {
-                ast::Expr::Name(n) => validate_name(n.id.as_str())?,
-                ast::Expr::Tuple(t) => {
-                    for el in &t.elts {
-                        match el {
-                            ast::Expr::Name(n) => validate_name(n.id.as_str())?,
-                            _ => return Err(ReplError::ForbiddenSyntax("for target".into())),
-                        }
-                    }
+            Try(s) => {
+                for st in &s.body {
+                    self.validate_stmt(st)?;
                 }
-                ast::Expr::List(t) => {
-                    for el in &t.elts {
-                        match el {
-                            ast::Expr::Name(n) => validate_name(n.id.as_str())?,
-                            _ => return Err(ReplError::ForbiddenSyntax("for target".into())),
+                for h in &s.handlers {
+                    self.validate_handler(h)?;
+                }
+                for st in &s.orelse {
+                    self.validate_stmt(st)?;
+                }
+                for st in &s.finalbody {
+                    self.validate_stmt(st)?;
+                }
+                Ok(())
+            }
+            FunctionDef(s) => {
+                validate_name(s.name.as_str())?;
+                validate_args(&s.args)?;
+                // A function body can't break out of a loop around its definition.
+                let outer_loops = std::mem::take(&mut self.loop_depth);
+                let body = s.body.iter().try_for_each(|st| self.validate_stmt(st));
+                self.loop_depth = outer_loops;
+                body
+            }
+            Return(s) => {
+                if let Some(v) = &s.value {
+                    self.validate_expr(v)?;
+                }
+                Ok(())
+            }
+            Break(_) if self.loop_depth == 0 => {
+                Err(ReplError::ParseError("'break' outside loop".into()))
+            }
+            Continue(_) if self.loop_depth == 0 => Err(ReplError::ParseError(
+                "'continue' not properly in loop".into(),
+            )),
+            Break(_) => Ok(()),
+            Continue(_) => Ok(()),
+            Raise(s) => {
+                if let Some(exc) = &s.exc {
+                    // Allow `raise SystemExit` and `raise Exception(...)`-ish.
+                    match exc.as_ref() {
+                        ast::Expr::Name(n) if n.id.as_str() == "SystemExit" => Ok(()),
+                        ast::Expr::Call(c) => {
+                            if let ast::Expr::Name(n) = c.func.as_ref() {
+                                if n.id.as_str() == "Exception" {
+                                    for a in &c.args {
+                                        self.validate_expr(a)?;
+                                    }
+                                    return Ok(());
+                                }
+                            }
+                            Err(ReplError::ForbiddenSyntax("raise".into()))
                         }
+                        _ => Err(ReplError::ForbiddenSyntax("raise".into())),
                     }
+                } else {
+                    Ok(())
+                }
+            }
+
+            // Imports are treated as no-ops (or as bindings to pre-injected modules) by the evaluator.
+            // This avoids spurious failures when the model "reflexively" writes `import ...`.
+            // Strict embedders opt into rejecting unknown modules up front.
+            Import(s) if self.import_policy == ImportPolicy::ForbidUnknown => {
+                for a in &s.names {
+                    check_known_module(a.name.as_str())?;
                 }
-                _ => return Err(ReplError::ForbiddenSyntax("for target".into())),
-            };
-            validate_expr(&s.iter)?;
-            for st in &s.body {
-                validate_stmt(st)?;
+                Ok(())
             }
-            for st in &s.orelse {
-                validate_stmt(st)?;
+            ImportFrom(s) if self.import_policy == ImportPolicy::ForbidUnknown => {
+                let level = s.level.map(|l| l.to_u32()).unwrap_or(0);
+                match &s.module {
+                    Some(m) if level == 0 => check_known_module(m.as_str()),
+                    _ => Err(ReplError::ForbiddenSyntax("relative import".into())),
+                }
             }
-            Ok(())
+            Import(_) | ImportFrom(_) => Ok(()),
+            With(_) | ClassDef(_) | AsyncFunctionDef(_) | AsyncFor(_) | AsyncWith(_) => {
+                Err(ReplError::ForbiddenSyntax(format!("{:?}", stmt)))
+            }
+            _ => Err(ReplError::ForbiddenSyntax(format!("{:?}", stmt))),
         }
-        Try(s) => {
-            for st in &s.body {
-                validate_stmt(st)?;
+    }
+
+    fn validate_handler(&mut self, h: &ast::ExceptHandler) -> Result<(), ReplError> {
+        match h {
+            ast::ExceptHandler::ExceptHandler(eh) => {
+                if let Some(t) = &eh.type_ {
+                    match t.as_ref() {
+                        ast::Expr::Name(n) if HANDLED_EXCEPTIONS.contains(&n.id.as_str()) => {}
+                        _ => return Err(ReplError::ForbiddenSyntax("except type".into())),
+                    }
+                }
+                if let Some(name) = &eh.name {
+                    validate_name(name.as_str())?;
+                }
+                for st in &eh.body {
+                    self.validate_stmt(st)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn check_expr(&mut self, expr: &ast::Expr) -> Result<(), ReplError> {
+        use ast::Expr::*;
+        match expr {
+            Constant(_) => Ok(()),
+            Name(n) => validate_name(n.id.as_str()),
+            BinOp(e) => {
+                // `a + b + c + ...` parses as a left-leaning tree; walk that spine in a loop so
+                // a long flat chain doesn't count as nesting. Only the right operands recurse.
+                let mut spine = vec![e];
+                while let BinOp(inner) = spine[spine.len() - 1].left.as_ref() {
+                    spine.push(inner);
+                }
+                self.validate_expr(&spine[spine.len() - 1].left)?;
+                for b in spine.iter().rev() {
+                    self.validate_expr(&b.right)?;
+                }
+                Ok(())
             }
-            for h in &s.handlers {
-                validate_handler(h)?;
+            UnaryOp(e) => self.validate_expr(&e.operand),
+            IfExp(e) => {
+                self.validate_expr(&e.test)?;
+                self.validate_expr(&e.body)?;
+                self.validate_expr(&e.orelse)?;
+                Ok(())
             }
-            for st in &s.orelse {
-                validate_stmt(st)?;
+            Compare(e) => {
+                self.validate_expr(&e.left)?;
+                for c in &e.comparators {
+                    self.validate_expr(c)?;
+                }
+                Ok(())
             }
-            for st in &s.finalbody {
-                validate_stmt(st)?;
+            BoolOp(e) => {
+                for v in &e.values {
+                    self.validate_expr(v)?;
+                }
+                Ok(())
             }
-            Ok(())
-        }
-        FunctionDef(s) => {
-            validate_name(s.name.as_str())?;
-            validate_args(&s.args)?;
-            for st in &s.body {
-                validate_stmt(st)?;
+            Call(e) => {
+                self.validate_expr(&e.func)?;
+                for a in &e.args {
+                    self.validate_expr(a)?;
+                }
+                for k in &e.keywords {
+                    if let Some(arg) = &k.arg {
+                        validate_name(arg.as_str())?;
+                    }
+                    self.validate_expr(&k.value)?;
+                }
+                Ok(())
             }
-            Ok(())
-        }
-        Return(s) => {
-            if let Some(v) = &s.value {
-                validate_expr(v)?;
+            Attribute(e) => {
+                self.validate_expr(&e.value)?;
+                validate_attr(e.attr.as_str())?;
+                if let Some(allowed) = self.allowed_attributes {
+                    if !allowed.iter().any(|a| a == e.attr.as_str()) {
+                        return Err(ReplError::ForbiddenName(e.attr.to_string()));
+                    }
+                }
+                Ok(())
             }
-            Ok(())
-        }
-        Break(_) => Ok(()),
-        Continue(_) => Ok(()),
-        Raise(s) => {
-            if let Some(exc) = &s.exc {
-                // Allow `raise SystemExit` and `raise Exception(...)`-ish.
-                match exc.as_ref() {
-                    ast::Expr::Name(n) if n.id.as_str() == "SystemExit" => Ok(()),
-                    ast::Expr::Call(c) => {
-                        if let ast::Expr::Name(n) = c.func.as_ref() {
-                            if n.id.as_str() == "Exception" {
-                                for a in &c.args {
-                                    validate_expr(a)?;
+            Subscript(e) => {
+                self.validate_expr(&e.value)?;
+                self.validate_expr(&e.slice)?;
+                Ok(())
+            }
+            JoinedStr(e) => {
+                for v in &e.values {
+                    self.validate_expr(v)?;
+                }
+                Ok(())
+            }
+            FormattedValue(e) => {
+                self.validate_expr(&e.value)?;
+                if let Some(spec) = &e.format_spec {
+                    self.validate_expr(spec)?;
+                }
+                Ok(())
+            }
+            Slice(e) => {
+                if let Some(v) = &e.lower {
+                    self.validate_expr(v)?;
+                }
+                if let Some(v) = &e.upper {
+                    self.validate_expr(v)?;
+                }
+                if let Some(v) = &e.step {
+                    self.validate_expr(v)?;
+                }
+                Ok(())
+            }
+            List(e) => {
+                for v in &e.elts {
+                    self.validate_expr(v)?;
+                }
+                Ok(())
+            }
+            Dict(e) => {
+                // Allow dict literals with string keys only.
+                for k in &e.keys {
+                    match k {
+                        Some(ast::Expr::Constant(c)) => {
+                            // Only allow string keys
+                            match &c.value {
+                                ast::Constant::Str(_) => {}
+                                _ => {
+                                    return Err(ReplError::ForbiddenSyntax(
+                                        "dict key must be str literal".into(),
+                                    ))
                                 }
-                                return Ok(());
                             }
                         }
-                        Err(ReplError::ForbiddenSyntax("raise".into()))
+                        None => return Err(ReplError::ForbiddenSyntax("dict unpack".into())),
+                        _ => {
+                            return Err(ReplError::ForbiddenSyntax(
+                                "dict key must be str literal".into(),
+                            ))
+                        }
                     }
-                    _ => Err(ReplError::ForbiddenSyntax("raise".into())),
                 }
-            } else {
+                for v in &e.values {
+                    self.validate_expr(v)?;
+                }
                 Ok(())
             }
-        }
-
-        // Imports are treated as no-ops (or as bindings to pre-injected modules) by the evaluator.
-        // This avoids spurious failures when the model "reflexively" writes `import ...`.
-        Import(_) | ImportFrom(_) => Ok(()),
-        While(_) | With(_) | ClassDef(_) | AsyncFunctionDef(_) | AsyncFor(_) | AsyncWith(_) => {
-            Err(ReplError::ForbiddenSyntax(format!("{:?}", stmt)))
-        }
-        _ => Err(ReplError::ForbiddenSyntax(format!("{:?}", stmt))),
-    }
-}
-
-fn validate_handler(h: &ast::ExceptHandler) -> Result<(), ReplError> {
-    match h {
-        ast::ExceptHandler::ExceptHandler(eh) => {
-            if let Some(t) = &eh.type_ {
-                match t.as_ref() {
-                    ast::Expr::Name(n) if n.id.as_str() == "Exception" => {}
-                    _ => return Err(ReplError::ForbiddenSyntax("except type".into())),
+            Tuple(e) => {
+                for v in &e.elts {
+                    self.validate_expr(v)?;
                 }
+                Ok(())
             }
-            if let Some(name) = &eh.name {
-                validate_name(name.as_str())?;
+            Set(e) => {
+                for v in &e.elts {
+                    self.validate_expr(v)?;
+                }
+                Ok(())
             }
-            for st in &eh.body {
-                validate_stmt(st)?;
+            ListComp(e) => {
+                // [elt for name in iterable if cond for name2 in iterable2 ...]
+                self.validate_expr(&e.elt)?;
+                for gen in &e.generators {
+                    match &gen.target {
+                        ast::Expr::Name(n) => validate_name(n.id.as_str())?,
+                        _ => return Err(ReplError::ForbiddenSyntax("listcomp target".into())),
+                    }
+                    self.validate_expr(&gen.iter)?;
+                    for if_expr in &gen.ifs {
+                        self.validate_expr(if_expr)?;
+                    }
+                    if gen.is_async {
+                        return Err(ReplError::ForbiddenSyntax("async listcomp".into()));
+                    }
+                }
+                Ok(())
+            }
+            DictComp(e) => {
+                // Restrict to a single generator: {key: value for name in iterable if cond}
+                if e.generators.len() != 1 {
+                    return Err(ReplError::ForbiddenSyntax("dictcomp generators".into()));
+                }
+                self.validate_expr(&e.key)?;
+                self.validate_expr(&e.value)?;
+                let gen = &e.generators[0];
+                match &gen.target {
+                    ast::Expr::Name(n) => validate_name(n.id.as_str())?,
+                    _ => return Err(ReplError::ForbiddenSyntax("dictcomp target".into())),
+                }
+                self.validate_expr(&gen.iter)?;
+                for if_expr in &gen.ifs {
+                    self.validate_expr(if_expr)?;
+                }
+                if gen.is_async {
+                    return Err(ReplError::ForbiddenSyntax("async dictcomp".into()));
+                }
+                Ok(())
             }
-            Ok(())
+            // Not currently needed by observed surface
+            _ => Err(ReplError::ForbiddenSyntax(format!("{:?}", expr))),
         }
     }
 }
@@ -188,141 +447,44 @@ fn validate_args(args: &ast::Arguments) -> Result<(), ReplError> {
     Ok(())
 }
 
-fn validate_expr(expr: &ast::Expr) -> Result<(), ReplError> {
-    use ast::Expr::*;
-    match expr {
-        Constant(_) => Ok(()),
-        Name(n) => validate_name(n.id.as_str()),
-        BinOp(e) => {
-            validate_expr(&e.left)?;
-            validate_expr(&e.right)?;
-            Ok(())
-        }
-        UnaryOp(e) => validate_expr(&e.operand),
-        IfExp(e) => {
-            validate_expr(&e.test)?;
-            validate_expr(&e.body)?;
-            validate_expr(&e.orelse)?;
-            Ok(())
-        }
-        Compare(e) => {
-            validate_expr(&e.left)?;
-            for c in &e.comparators {
-                validate_expr(c)?;
-            }
-            Ok(())
-        }
-        BoolOp(e) => {
-            for v in &e.values {
-                validate_expr(v)?;
-            }
-            Ok(())
-        }
-        Call(e) => {
-            validate_expr(&e.func)?;
-            for a in &e.args {
-                validate_expr(a)?;
-            }
-            for k in &e.keywords {
-                if let Some(arg) = &k.arg {
-                    validate_name(arg.as_str())?;
-                }
-                validate_expr(&k.value)?;
-            }
-            Ok(())
-        }
-        Attribute(e) => {
-            validate_expr(&e.value)?;
-            validate_attr(e.attr.as_str())?;
-            Ok(())
-        }
-        Subscript(e) => {
-            validate_expr(&e.value)?;
-            validate_expr(&e.slice)?;
-            Ok(())
-        }
-        Slice(e) => {
-            if let Some(v) = &e.lower {
-                validate_expr(v)?;
-            }
-            if let Some(v) = &e.upper {
-                validate_expr(v)?;
-            }
-            if let Some(v) = &e.step {
-                validate_expr(v)?;
-            }
-            Ok(())
-        }
-        List(e) => {
-            for v in &e.elts {
-                validate_expr(v)?;
-            }
-            Ok(())
-        }
-        Dict(e) => {
-            // Allow dict literals with string keys only.
-            for k in &e.keys {
-                match k {
-                    Some(ast::Expr::Constant(c)) => {
-                        // Only allow string keys
-                        match &c.value {
-                            ast::Constant::Str(_) => {}
-                            _ => {
-                                return Err(ReplError::ForbiddenSyntax(
-                                    "dict key must be str literal".into(),
-                                ))
-                            }
-                        }
-                    }
-                    None => return Err(ReplError::ForbiddenSyntax("dict unpack".into())),
-                    _ => {
-                        return Err(ReplError::ForbiddenSyntax(
-                            "dict key must be str literal".into(),
-                        ))
-                    }
+fn check_known_module(name: &str) -> Result<(), ReplError> {
+    if MODULE_NAMES.contains(&name) {
+        Ok(())
+    } else {
+        Err(ReplError::ForbiddenName(name.to_string()))
+    }
+}
+
+/// `a, b` / `[a, b]` / `first, *rest` targets: plain names, at most one of them starred.
+fn validate_unpack_target(elts: &[ast::Expr], what: &str) -> Result<(), ReplError> {
+    let mut starred = 0;
+    for el in elts {
+        let name = match el {
+            ast::Expr::Name(n) => n,
+            ast::Expr::Starred(st) => match st.value.as_ref() {
+                ast::Expr::Name(n) => {
+                    starred += 1;
+                    n
                 }
-            }
-            for v in &e.values {
-                validate_expr(v)?;
-            }
-            Ok(())
-        }
-        Tuple(e) => {
-            for v in &e.elts {
-                validate_expr(v)?;
-            }
-            Ok(())
-        }
-        ListComp(e) => {
-            // Restrict to a single generator: [elt for name in iterable if cond]
-            if e.generators.len() != 1 {
-                return Err(ReplError::ForbiddenSyntax("listcomp generators".into()));
-            }
-            validate_expr(&e.elt)?;
-            let gen = &e.generators[0];
-            match &gen.target {
-                ast::Expr::Name(n) => validate_name(n.id.as_str())?,
-                _ => return Err(ReplError::ForbiddenSyntax("listcomp target".into())),
-            }
-            validate_expr(&gen.iter)?;
-            for if_expr in &gen.ifs {
-                validate_expr(if_expr)?;
-            }
-            if gen.is_async {
-                return Err(ReplError::ForbiddenSyntax("async listcomp".into()));
-            }
-            Ok(())
-        }
-        // Not currently needed by observed surface
-        _ => Err(ReplError::ForbiddenSyntax(format!("{:?}", expr))),
+                _ => return Err(ReplError::ForbiddenSyntax(what.into())),
+            },
+            _ => return Err(ReplError::ForbiddenSyntax(what.into())),
+        };
+        validate_name(name.id.as_str())?;
     }
+    if starred > 1 {
+        return Err(ReplError::ParseError(
+            "multiple starred expressions in assignment".into(),
+        ));
+    }
+    Ok(())
 }
 
 fn validate_name(name: &str) -> Result<(), ReplError> {
     if name.starts_with('_') || name.contains("__") {
         return Err(ReplError::ForbiddenName(name.to_string()));
     }
-    if FORBIDDEN_NAMES.iter().any(|&n| n == name) {
+    if FORBIDDEN_NAMES.contains(&name) {
         return Err(ReplError::ForbiddenName(name.to_string()));
     }
     Ok(())
@@ -69,14 +69,12 @@ impl PrintSink {
         self.push_echo_line(s)?;
 
         // Persist the printed output (like RestrictedPython's `_print.txt` join).
-        if self.print_state_chars < self.max_print_state_chars {
-            for ch in s.chars().chain(std::iter::once('\n')) {
-                if self.print_state_chars >= self.max_print_state_chars {
-                    break;
-                }
-                self.print_state.push(ch);
-                self.print_state_chars += 1;
-            }
+        let remaining = self
+            .max_print_state_chars
+            .saturating_sub(self.print_state_chars);
+        for ch in s.chars().chain(std::iter::once('\n')).take(remaining) {
+            self.print_state.push(ch);
+            self.print_state_chars += 1;
         }
         Ok(())
     }
@@ -95,38 +93,76 @@ impl PrintSink {
     }
 }
 
+/// Safe modules pre-injected into every env; `import` only rebinds these.
+pub const MODULE_NAMES: &[&str] = &["re", "json", "base64", "binascii", "zlib", "random", "sys"];
+
+/// Builtin functions dispatched by `call_name`, in the order prompts advertise them. A system
+/// test calls each one, so adding a dispatch arm without listing it here (or vice versa) shows.
+pub const BUILTIN_FUNCTIONS: &[&str] = &[
+    "print",
+    "len",
+    "max",
+    "min",
+    "sorted",
+    "sum",
+    "zip",
+    "any",
+    "all",
+    "reversed",
+    "range",
+    "int",
+    "bool",
+    "str",
+    "repr",
+    "list",
+    "dict",
+    "set",
+    "bytearray",
+    "ord",
+    "chr",
+    "format",
+    "parse_csv",
+    "percent",
+    "ratio_bps",
+    "pluck",
+    "get_in",
+    "rank_documents",
+];
+
+/// `str` methods dispatched by `call_str_method`.
+pub const STR_METHODS: &[&str] = &[
+    "strip",
+    "lstrip",
+    "rstrip",
+    "lower",
+    "upper",
+    "casefold",
+    "find",
+    "replace",
+    "split",
+    "join",
+    "count",
+    "format",
+    "startswith",
+    "endswith",
+    "ljust",
+    "rjust",
+    "center",
+    "encode",
+];
+
 pub fn make_initial_env(max_zlib_output_bytes: usize, context: &str, query: &str) -> Env {
     let mut globals: HashMap<String, Value> = HashMap::new();
     globals.insert("context".to_string(), Value::Str(context.to_string()));
     globals.insert("query".to_string(), Value::Str(query.to_string()));
-    globals.insert(
-        "re".to_string(),
-        Value::Module(Module { name: "re".into() }),
-    );
-    globals.insert(
-        "json".to_string(),
-        Value::Module(Module {
-            name: "json".into(),
-        }),
-    );
-    globals.insert(
-        "base64".to_string(),
-        Value::Module(Module {
-            name: "base64".into(),
-        }),
-    );
-    globals.insert(
-        "binascii".to_string(),
-        Value::Module(Module {
-            name: "binascii".into(),
-        }),
-    );
-    globals.insert(
-        "zlib".to_string(),
-        Value::Module(Module {
-            name: "zlib".into(),
-        }),
-    );
+    for name in MODULE_NAMES {
+        globals.insert(
+            name.to_string(),
+            Value::Module(Module {
+                name: name.to_string(),
+            }),
+        );
+    }
     // range(...) is a builtin function implemented by the evaluator.
 
     Env::new(globals, max_zlib_output_bytes)
@@ -1,25 +1,63 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 use crate::error::ReplError;
+use crate::text::{casefold, snap_window, Boundary, TermIndex};
 use base64::Engine;
 
-use super::builtins::PrintSink;
+use super::builtins::{PrintSink, MODULE_NAMES};
 use super::parse::Program;
-use super::state::{try_from_value, ReplState};
-use super::value::{UserFunc, Value};
+use super::state::{try_from_value, ReplState, StoredValue};
+use super::value::{SetItem, UserFunc, Value};
+use super::ImportPolicy;
 
 pub struct Env {
     globals: HashMap<String, Value>,
     locals_stack: Vec<HashMap<String, Value>>,
     max_zlib_output_bytes: usize,
+    lenient_json: bool,
+    regex_size_limit: usize,
+    max_regex_matches: usize,
+    max_loop_iterations: usize,
+    echo_expressions: bool,
+    dict_int_index: bool,
+    import_policy: ImportPolicy,
+    rank_index: TermIndex,
+    /// Names of the user functions currently executing, innermost last.
+    scopes: Vec<String>,
+    /// Frames of the error being propagated, innermost first; cleared when it's caught.
+    traceback: Vec<TraceFrame>,
+    depth: usize,
+    max_depth: usize,
+    rng_state: u64,
+    /// Whether the generator was seeded in code, drawn from, or restored; only then is its
+    /// state carried in the dumped `ReplState`.
+    rng_touched: bool,
 }
 
+/// `ReplState` key carrying the `random` generator between executions, so successive calls
+/// continue one sequence instead of replaying it. Unreachable from code (leading `_`).
+const RANDOM_STATE_KEY: &str = "_random_state";
+
 impl Env {
     pub fn new(globals: HashMap<String, Value>, max_zlib_output_bytes: usize) -> Self {
         Self {
             globals,
             locals_stack: Vec::new(),
             max_zlib_output_bytes,
+            lenient_json: false,
+            regex_size_limit: usize::MAX,
+            max_regex_matches: usize::MAX,
+            max_loop_iterations: usize::MAX,
+            echo_expressions: false,
+            dict_int_index: true,
+            import_policy: ImportPolicy::BindKnown,
+            rank_index: TermIndex::default(),
+            scopes: Vec::new(),
+            traceback: Vec::new(),
+            depth: 0,
+            max_depth: usize::MAX,
+            rng_state: 0,
+            rng_touched: false,
         }
     }
 
@@ -27,13 +65,82 @@ impl Env {
         self.max_zlib_output_bytes
     }
 
+    /// Let `json.loads` retry with trailing commas and single-quoted strings accepted.
+    pub fn set_lenient_json(&mut self, lenient: bool) {
+        self.lenient_json = lenient;
+    }
+
+    /// Cap compiled regex programs (and their lazy DFA cache) at this many bytes.
+    pub fn set_regex_size_limit(&mut self, bytes: usize) {
+        self.regex_size_limit = bytes;
+    }
+
+    /// Fail `re.findall` once it has produced more than this many matches.
+    pub fn set_max_regex_matches(&mut self, n: usize) {
+        self.max_regex_matches = n;
+    }
+
+    /// Fail a `while` loop once its body has run this many times.
+    pub fn set_max_loop_iterations(&mut self, n: usize) {
+        self.max_loop_iterations = n;
+    }
+
+    /// Echo every top-level bare expression (see `ReplConfig::echo_all_expressions`).
+    pub fn set_echo_expressions(&mut self, on: bool) {
+        self.echo_expressions = on;
+    }
+
+    /// Allow the non-Python `d[0]` lookup by sorted key position (see `dict_lookup`).
+    pub fn set_dict_int_index(&mut self, enabled: bool) {
+        self.dict_int_index = enabled;
+    }
+
+    pub fn set_import_policy(&mut self, policy: ImportPolicy) {
+        self.import_policy = policy;
+    }
+
+    /// Bound expression nesting during evaluation; user-function recursion counts as nesting.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    /// Reset the `random` module's generator. Unseeded executions start from 0 so the
+    /// interpreter stays deterministic.
+    pub fn seed_random(&mut self, seed: u64) {
+        self.rng_state = seed;
+    }
+
+    fn next_random_u64(&mut self) -> u64 {
+        self.rng_touched = true;
+        // splitmix64: tiny, fast, and good enough for model-side sampling.
+        self.rng_state = self.rng_state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.rng_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
     pub fn get(&self, name: &str) -> Option<Value> {
+        self.get_ref(name).cloned()
+    }
+
+    /// Borrow a binding without copying it; lookups into big dicts/lists use this so a
+    /// hot loop doesn't clone the whole container on every `k in d` / `d[k]`.
+    pub fn get_ref(&self, name: &str) -> Option<&Value> {
         if let Some(frame) = self.locals_stack.last() {
             if let Some(v) = frame.get(name) {
-                return Some(v.clone());
+                return Some(v);
             }
         }
-        self.globals.get(name).cloned()
+        self.globals.get(name)
+    }
+
+    /// The binding `set` would overwrite, borrowed mutably so `+=` can append in place.
+    fn get_mut_current(&mut self, name: &str) -> Option<&mut Value> {
+        match self.locals_stack.last_mut() {
+            Some(frame) => frame.get_mut(name),
+            None => self.globals.get_mut(name),
+        }
     }
 
     pub fn set(&mut self, name: &str, value: Value) {
@@ -55,12 +162,44 @@ impl Env {
         self.locals_stack.pop();
     }
 
+    /// Frames of the last uncaught error, outermost (`<module>`) first.
+    pub fn take_traceback(&mut self) -> Vec<TraceFrame> {
+        let mut frames = std::mem::take(&mut self.traceback);
+        frames.reverse();
+        frames
+    }
+
+    /// Remember the statement that failed in the current scope. Enclosing statements of the
+    /// same scope are skipped while the error unwinds, so each scope keeps its innermost one.
+    fn note_error_at(&mut self, offset: usize) {
+        let depth = self.scopes.len();
+        if self.traceback.last().is_some_and(|f| f.depth == depth) {
+            return;
+        }
+        self.traceback.push(TraceFrame {
+            offset,
+            scope: self
+                .scopes
+                .last()
+                .cloned()
+                .unwrap_or_else(|| "<module>".to_string()),
+            depth,
+        });
+    }
+
     pub fn define_func(&mut self, f: UserFunc) {
         self.globals.insert(f.name.clone(), Value::UserFunc(f));
     }
 
     pub fn apply_state(&mut self, st: &ReplState) -> Result<(), ReplError> {
         for (k, sv) in st {
+            if k == RANDOM_STATE_KEY {
+                if let StoredValue::Int(i) = sv {
+                    self.rng_state = *i as u64;
+                    self.rng_touched = true;
+                }
+                continue;
+            }
             if is_reserved_name(k) {
                 continue;
             }
@@ -80,6 +219,12 @@ impl Env {
                 out.insert(k.clone(), sv);
             }
         }
+        if self.rng_touched {
+            out.insert(
+                RANDOM_STATE_KEY.to_string(),
+                StoredValue::Int(self.rng_state as i64),
+            );
+        }
         out
     }
 }
@@ -87,44 +232,44 @@ impl Env {
 fn is_reserved_name(name: &str) -> bool {
     matches!(
         name,
-        "context" | "query" | "re" | "json" | "base64" | "binascii" | "zlib"
+        "context" | "query" | "re" | "json" | "base64" | "binascii" | "zlib" | "random" | "sys"
     )
 }
 
 pub fn exec_program(
-    _program: &Program,
-    _env: &mut Env,
-    _sink: &mut PrintSink,
+    program: &Program,
+    env: &mut Env,
+    sink: &mut PrintSink,
 ) -> Result<(), ReplError> {
-    exec_suite(_program, _env, _sink).map(|_| ())
-}
-
-pub fn maybe_echo_last_expr(code: &str, program: &Program, env: &mut Env, sink: &mut PrintSink) {
-    // Emulate the upstream unofficial executor's "echo last expression" behavior:
-    // it tries to `eval(last_line)` and appends it to output if non-None, but only when
-    // the last line looks like a "simple expression" (naively filtered by substrings).
-    let lines: Vec<&str> = code.trim().lines().collect();
-    let Some(last_line) = lines.last().map(|s| s.trim()) else {
-        return;
-    };
-    if last_line.is_empty() {
-        return;
+    if !env.echo_expressions {
+        return exec_suite(program, env, sink).map(|_| ());
     }
-    const SKIP_SUBSTRS: &[&str] = &["=", "import", "def", "class", "if", "for", "while", "with"];
-    if SKIP_SUBSTRS.iter().any(|kw| last_line.contains(kw)) {
-        return;
+    // Interactive mode: each top-level bare expression echoes its non-None repr in place.
+    for st in program {
+        let rustpython_parser::ast::Stmt::Expr(e) = st else {
+            exec_suite(std::slice::from_ref(st), env, sink)?;
+            continue;
+        };
+        let v = eval_expr(&e.value, env, sink).inspect_err(|_| {
+            env.note_error_at(rustpython_parser::ast::Ranged::start(st).to_usize());
+        })?;
+        if v != Value::None {
+            sink.push_echo_line(&py_repr_value(&v))?;
+        }
     }
+    Ok(())
+}
 
-    let Some(last_stmt) = program.last() else {
-        return;
-    };
-    let rustpython_parser::ast::Stmt::Expr(s) = last_stmt else {
+pub fn maybe_echo_last_expr(program: &Program, env: &mut Env, sink: &mut PrintSink) {
+    // Emulate the upstream unofficial executor's "echo last expression" behavior: it
+    // evaluates the last statement again and appends the value to output if non-None.
+    // Decided on the AST so `x == y` or a name like `data_for` still echo, while
+    // assignments and anything that would run a call a second time (including the usual
+    // `print(...)`) don't.
+    let Some(rustpython_parser::ast::Stmt::Expr(s)) = program.last() else {
         return;
     };
-
-    // Avoid double-running calls (the upstream eval frequently fails for `print(...)`
-    // because `print` isn't a normal builtin in their RestrictedPython globals).
-    if matches!(s.value.as_ref(), rustpython_parser::ast::Expr::Call(_)) {
+    if !is_echoable(&s.value) {
         return;
     }
 
@@ -136,6 +281,60 @@ pub fn maybe_echo_last_expr(code: &str, program: &Program, env: &mut Env, sink:
     }
 }
 
+/// Whether re-evaluating `e` is side-effect free: no calls or `:=` anywhere inside it.
+fn is_echoable(e: &rustpython_parser::ast::Expr) -> bool {
+    use rustpython_parser::ast::Expr::*;
+    let all = |xs: &[rustpython_parser::ast::Expr]| xs.iter().all(is_echoable);
+    match e {
+        Constant(_) | Name(_) => true,
+        BinOp(b) => {
+            let mut b = b;
+            loop {
+                if !is_echoable(&b.right) {
+                    return false;
+                }
+                match b.left.as_ref() {
+                    BinOp(inner) => b = inner,
+                    left => return is_echoable(left),
+                }
+            }
+        }
+        UnaryOp(u) => is_echoable(&u.operand),
+        IfExp(i) => is_echoable(&i.test) && is_echoable(&i.body) && is_echoable(&i.orelse),
+        Compare(c) => is_echoable(&c.left) && all(&c.comparators),
+        BoolOp(b) => all(&b.values),
+        Attribute(a) => is_echoable(&a.value),
+        Subscript(s) => is_echoable(&s.value) && is_echoable(&s.slice),
+        Slice(s) => [&s.lower, &s.upper, &s.step]
+            .into_iter()
+            .all(|x| x.as_deref().is_none_or(is_echoable)),
+        List(l) => all(&l.elts),
+        Tuple(t) => all(&t.elts),
+        Set(s) => all(&s.elts),
+        JoinedStr(j) => all(&j.values),
+        FormattedValue(f) => {
+            is_echoable(&f.value) && f.format_spec.as_deref().is_none_or(is_echoable)
+        }
+        Dict(d) => d.keys.iter().flatten().all(is_echoable) && all(&d.values),
+        ListComp(c) => {
+            is_echoable(&c.elt)
+                && c.generators
+                    .iter()
+                    .all(|g| is_echoable(&g.iter) && all(&g.ifs))
+        }
+        _ => false,
+    }
+}
+
+/// Where a runtime error passed through one scope: the byte offset of the failing statement
+/// in the source and the scope's name (`<module>` or the function name).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceFrame {
+    pub offset: usize,
+    pub scope: String,
+    depth: usize,
+}
+
 #[derive(Debug)]
 enum Flow {
     Continue,
@@ -144,13 +343,33 @@ enum Flow {
     ContinueLoop,
 }
 
+/// Run a block of statements. Each block counts one nesting level, like an expression does,
+/// so statements nested inside recursive calls share the evaluator's depth budget.
 fn exec_suite(
     stmts: &[rustpython_parser::ast::Stmt],
     env: &mut Env,
     sink: &mut PrintSink,
+) -> Result<Flow, ReplError> {
+    env.depth += 1;
+    let res = if env.depth > env.max_depth {
+        Err(ReplError::ForbiddenSyntax("nesting too deep".into()))
+    } else {
+        exec_stmts(stmts, env, sink)
+    };
+    env.depth -= 1;
+    res
+}
+
+fn exec_stmts(
+    stmts: &[rustpython_parser::ast::Stmt],
+    env: &mut Env,
+    sink: &mut PrintSink,
 ) -> Result<Flow, ReplError> {
     for st in stmts {
-        match exec_stmt(st, env, sink)? {
+        let flow = exec_stmt(st, env, sink).inspect_err(|_| {
+            env.note_error_at(rustpython_parser::ast::Ranged::start(st).to_usize());
+        })?;
+        match flow {
             Flow::Continue => {}
             Flow::Return(v) => return Ok(Flow::Return(v)),
             Flow::Break => return Ok(Flow::Break),
@@ -160,6 +379,8 @@ fn exec_suite(
     Ok(Flow::Continue)
 }
 
+/// Bulky statement kinds run in `#[inline(never)]` helpers so this frame stays small: it sits
+/// on the stack once per nested block and once per level of user-function recursion.
 fn exec_stmt(
     stmt: &rustpython_parser::ast::Stmt,
     env: &mut Env,
@@ -168,51 +389,8 @@ fn exec_stmt(
     use rustpython_parser::ast::Stmt::*;
 
     match stmt {
-        Assign(s) => {
-            let v = eval_expr(&s.value, env, sink)?;
-            for t in &s.targets {
-                match t {
-                    rustpython_parser::ast::Expr::Name(n) => env.set(n.id.as_str(), v.clone()),
-                    _ => return Err(ReplError::ForbiddenSyntax("assign target".into())),
-                }
-            }
-            Ok(Flow::Continue)
-        }
-        AugAssign(s) => {
-            use rustpython_parser::ast::Operator;
-            let target = match s.target.as_ref() {
-                rustpython_parser::ast::Expr::Name(n) => n.id.to_string(),
-                _ => return Err(ReplError::ForbiddenSyntax("augassign target".into())),
-            };
-            let left = env
-                .get(&target)
-                .ok_or_else(|| ReplError::NameError(target.clone()))?;
-            let right = eval_expr(&s.value, env, sink)?;
-            let out = match s.op {
-                Operator::Add => match (left, right) {
-                    (Value::Str(a), Value::Str(b)) => Value::Str(a + &b),
-                    (Value::Bytes(mut a), Value::Bytes(b)) => {
-                        a.extend_from_slice(&b);
-                        Value::Bytes(a)
-                    }
-                    (Value::Int(a), Value::Int(b)) => Value::Int(a + b),
-                    (a, b) => {
-                        return Err(ReplError::TypeError(format!(
-                            "unsupported +=: {} and {}",
-                            a.type_name(),
-                            b.type_name()
-                        )))
-                    }
-                },
-                _ => {
-                    return Err(ReplError::ForbiddenSyntax(
-                        "unsupported augassign op".into(),
-                    ))
-                }
-            };
-            env.set(&target, out);
-            Ok(Flow::Continue)
-        }
+        Assign(s) => exec_assign(s, env, sink),
+        AugAssign(s) => exec_augassign(s, env, sink),
         Expr(s) => {
             // Expressions do not print by default. (print() is explicit).
             let _ = eval_expr(&s.value, env, sink)?;
@@ -227,53 +405,10 @@ fn exec_stmt(
             }
         }
         Pass(_) => Ok(Flow::Continue),
-        For(s) => {
-            let iter_v = eval_expr(&s.iter, env, sink)?;
-            let items = iter_to_vec(iter_v)?;
-            for it in items {
-                bind_for_target(s.target.as_ref(), it, env)?;
-                match exec_suite(&s.body, env, sink)? {
-                    Flow::Continue => {}
-                    Flow::Return(v) => return Ok(Flow::Return(v)),
-                    Flow::Break => break,
-                    Flow::ContinueLoop => continue,
-                }
-            }
-            Ok(Flow::Continue)
-        }
-        Try(s) => {
-            match exec_suite(&s.body, env, sink) {
-                Ok(Flow::Continue) => Ok(Flow::Continue),
-                Ok(Flow::Return(v)) => Ok(Flow::Return(v)),
-                Ok(Flow::Break) => Ok(Flow::Break),
-                Ok(Flow::ContinueLoop) => Ok(Flow::ContinueLoop),
-                Err(e) => {
-                    if matches!(e, ReplError::SystemExit) {
-                        return Err(e);
-                    }
-                    // Our subset treats any error as "Exception" and allows a single handler.
-                    let h = s.handlers.first().ok_or(e)?;
-                    match h {
-                        rustpython_parser::ast::ExceptHandler::ExceptHandler(eh) => {
-                            exec_suite(&eh.body, env, sink)
-                        }
-                    }
-                }
-            }
-        }
-        FunctionDef(s) => {
-            let mut params = Vec::new();
-            for a in &s.args.args {
-                params.push(a.def.arg.to_string());
-            }
-            let f = UserFunc {
-                name: s.name.to_string(),
-                params,
-                body: s.body.clone(),
-            };
-            env.define_func(f);
-            Ok(Flow::Continue)
-        }
+        For(s) => exec_for(s, env, sink),
+        While(s) => exec_while(s, env, sink),
+        Try(s) => exec_try(s, env, sink),
+        FunctionDef(s) => exec_function_def(s, env),
         Return(s) => {
             let v = if let Some(e) = &s.value {
                 eval_expr(e, env, sink)?
@@ -288,61 +423,269 @@ fn exec_stmt(
             if let Some(exc) = &s.exc {
                 if let rustpython_parser::ast::Expr::Name(n) = exc.as_ref() {
                     if n.id.as_str() == "SystemExit" {
-                        return Err(ReplError::SystemExit);
+                        return Err(ReplError::SystemExit(None));
                     }
                 }
             }
             Err(ReplError::RuntimeError("raise".into()))
         }
-        Import(s) => {
-            // Treat imports as bindings to pre-injected safe modules, otherwise no-op.
-            // This avoids spurious failures when the model writes `import ...`.
-            for a in &s.names {
-                let mod_name = a.name.as_str();
-                let bind_name = a
-                    .asname
-                    .as_ref()
-                    .map(|x| x.as_str())
-                    .unwrap_or_else(|| mod_name.split('.').next().unwrap_or(mod_name));
-                if let Some(v) = env.get(mod_name) {
-                    env.set(bind_name, v);
-                }
-            }
+        Import(_) | ImportFrom(_) if env.import_policy == ImportPolicy::Ignore => {
             Ok(Flow::Continue)
         }
-        ImportFrom(s) => {
-            // Treat `from X import y [as z]` as bindings to pre-injected module attributes.
-            // We intentionally do not perform any dynamic importing.
-            let level = s.level.map(|l| l.to_u32()).unwrap_or(0);
-            if level != 0 {
+        Import(s) => exec_import(s, env),
+        ImportFrom(s) => exec_import_from(s, env),
+        _ => Err(ReplError::ForbiddenSyntax(format!("{:?}", stmt))),
+    }
+}
+
+#[inline(never)]
+fn exec_assign(
+    s: &rustpython_parser::ast::StmtAssign,
+    env: &mut Env,
+    sink: &mut PrintSink,
+) -> Result<Flow, ReplError> {
+    let v = eval_expr(&s.value, env, sink)?;
+    for t in &s.targets {
+        match t {
+            rustpython_parser::ast::Expr::Name(n) => env.set(n.id.as_str(), v.clone()),
+            rustpython_parser::ast::Expr::Subscript(sub) => assign_item(sub, v.clone(), env, sink)?,
+            rustpython_parser::ast::Expr::Tuple(t) => bind_unpack_elts(&t.elts, v.clone(), env)?,
+            rustpython_parser::ast::Expr::List(t) => bind_unpack_elts(&t.elts, v.clone(), env)?,
+            _ => return Err(ReplError::ForbiddenSyntax("assign target".into())),
+        }
+    }
+    Ok(Flow::Continue)
+}
+
+#[inline(never)]
+fn exec_augassign(
+    s: &rustpython_parser::ast::StmtAugAssign,
+    env: &mut Env,
+    sink: &mut PrintSink,
+) -> Result<Flow, ReplError> {
+    use rustpython_parser::ast::Operator;
+    let target = match s.target.as_ref() {
+        rustpython_parser::ast::Expr::Name(n) => n.id.to_string(),
+        _ => return Err(ReplError::ForbiddenSyntax("augassign target".into())),
+    };
+    if env.get_ref(&target).is_none() {
+        return Err(ReplError::NameError(target));
+    }
+    let right = eval_expr(&s.value, env, sink)?;
+    // Accumulating `out += chunk` appends to the binding instead of copying it,
+    // so building a large str/bytes in a loop stays linear.
+    if matches!(s.op, Operator::Add) {
+        match (env.get_mut_current(&target), &right) {
+            (Some(Value::Str(a)), Value::Str(b)) => {
+                a.push_str(b);
                 return Ok(Flow::Continue);
             }
-            let Some(module) = &s.module else {
-                return Ok(Flow::Continue);
-            };
-            let module_name = module.as_str();
-
-            // Only bind from modules that are already present.
-            if env.get(module_name).is_none() {
+            (Some(Value::Bytes(a)), Value::Bytes(b)) => {
+                a.extend_from_slice(b);
                 return Ok(Flow::Continue);
             }
+            _ => {}
+        }
+    }
+    // `d |= other` updates the dict in place, like `dict.update`.
+    if matches!(s.op, Operator::BitOr) {
+        if let (Some(Value::Dict(a)), Value::Dict(b)) = (env.get_mut_current(&target), &right) {
+            a.extend(b.iter().map(|(k, v)| (k.clone(), v.clone())));
+            return Ok(Flow::Continue);
+        }
+    }
+    let left = env
+        .get(&target)
+        .ok_or_else(|| ReplError::NameError(target.clone()))?;
+    let out = match s.op {
+        Operator::Add => match (left, right) {
+            (Value::Str(a), Value::Str(b)) => Value::Str(a + &b),
+            (Value::Bytes(mut a), Value::Bytes(b)) => {
+                a.extend_from_slice(&b);
+                Value::Bytes(a)
+            }
+            (Value::Int(a), Value::Int(b)) => Value::Int(
+                a.checked_add(b)
+                    .ok_or_else(|| ReplError::ValueError("integer overflow in +".into()))?,
+            ),
+            (a, b) if is_float_pair(&a, &b) => Value::Float(as_f64(&a) + as_f64(&b)),
+            (a, b) => {
+                return Err(ReplError::TypeError(format!(
+                    "unsupported +=: {} and {}",
+                    a.type_name(),
+                    b.type_name()
+                )))
+            }
+        },
+        op => apply_binop(op, left, right)?,
+    };
+    env.set(&target, out);
+    Ok(Flow::Continue)
+}
 
-            for a in &s.names {
-                let name = a.name.as_str();
-                if name == "*" {
-                    continue;
-                }
-                let bind_name = a.asname.as_ref().map(|x| x.as_str()).unwrap_or(name);
-                if let Some(v) = importable_module_attr_value(module_name, name) {
-                    env.set(bind_name, v);
+#[inline(never)]
+fn exec_for(
+    s: &rustpython_parser::ast::StmtFor,
+    env: &mut Env,
+    sink: &mut PrintSink,
+) -> Result<Flow, ReplError> {
+    let iter_v = eval_expr(&s.iter, env, sink)?;
+    let items = iter_to_vec(iter_v)?;
+    for it in items {
+        bind_for_target(s.target.as_ref(), it, env)?;
+        match exec_suite(&s.body, env, sink)? {
+            Flow::Continue => {}
+            Flow::Return(v) => return Ok(Flow::Return(v)),
+            Flow::Break => break,
+            Flow::ContinueLoop => continue,
+        }
+    }
+    Ok(Flow::Continue)
+}
+
+#[inline(never)]
+fn exec_while(
+    s: &rustpython_parser::ast::StmtWhile,
+    env: &mut Env,
+    sink: &mut PrintSink,
+) -> Result<Flow, ReplError> {
+    let mut iterations = 0usize;
+    loop {
+        if !eval_expr(&s.test, env, sink)?.to_bool() {
+            // `else` runs only when the condition ends the loop, not on `break`.
+            return exec_suite(&s.orelse, env, sink);
+        }
+        iterations += 1;
+        if iterations > env.max_loop_iterations {
+            return Err(ReplError::ResourceLimitExceeded(format!(
+                "while loop iterations exceed limit ({})",
+                env.max_loop_iterations
+            )));
+        }
+        match exec_suite(&s.body, env, sink)? {
+            Flow::Continue | Flow::ContinueLoop => {}
+            Flow::Return(v) => return Ok(Flow::Return(v)),
+            Flow::Break => return Ok(Flow::Continue),
+        }
+    }
+}
+
+#[inline(never)]
+fn exec_try(
+    s: &rustpython_parser::ast::StmtTry,
+    env: &mut Env,
+    sink: &mut PrintSink,
+) -> Result<Flow, ReplError> {
+    match exec_suite(&s.body, env, sink) {
+        Ok(Flow::Continue) => Ok(Flow::Continue),
+        Ok(Flow::Return(v)) => Ok(Flow::Return(v)),
+        Ok(Flow::Break) => Ok(Flow::Break),
+        Ok(Flow::ContinueLoop) => Ok(Flow::ContinueLoop),
+        Err(e) => {
+            if matches!(e, ReplError::SystemExit(_)) {
+                return Err(e);
+            }
+            // Our subset treats any error as "Exception". `ImportError` is accepted for
+            // the `try: import x / except ImportError:` idiom, but nothing raises it:
+            // unknown imports are ignored, or rejected before execution.
+            let h = s
+                .handlers
+                .iter()
+                .find(|h| {
+                    let rustpython_parser::ast::ExceptHandler::ExceptHandler(eh) = h;
+                    !matches!(
+                        eh.type_.as_deref(),
+                        Some(rustpython_parser::ast::Expr::Name(n)) if n.id.as_str() == "ImportError"
+                    )
+                })
+                .ok_or(e)?;
+            env.traceback.clear();
+            match h {
+                rustpython_parser::ast::ExceptHandler::ExceptHandler(eh) => {
+                    exec_suite(&eh.body, env, sink)
                 }
             }
-            Ok(Flow::Continue)
         }
-        _ => Err(ReplError::ForbiddenSyntax(format!("{:?}", stmt))),
     }
 }
 
+#[inline(never)]
+fn exec_function_def(
+    s: &rustpython_parser::ast::StmtFunctionDef,
+    env: &mut Env,
+) -> Result<Flow, ReplError> {
+    let mut params = Vec::new();
+    for a in &s.args.args {
+        params.push(a.def.arg.to_string());
+    }
+    let f = UserFunc {
+        name: s.name.to_string(),
+        params,
+        body: s.body.clone(),
+    };
+    env.define_func(f);
+    Ok(Flow::Continue)
+}
+
+#[inline(never)]
+fn exec_import(s: &rustpython_parser::ast::StmtImport, env: &mut Env) -> Result<Flow, ReplError> {
+    // Treat imports as bindings to pre-injected safe modules, otherwise no-op.
+    // This avoids spurious failures when the model writes `import ...`.
+    for a in &s.names {
+        let mod_name = a.name.as_str();
+        if env.import_policy == ImportPolicy::ForbidUnknown && !MODULE_NAMES.contains(&mod_name) {
+            return Err(ReplError::ForbiddenName(mod_name.to_string()));
+        }
+        let bind_name = a
+            .asname
+            .as_ref()
+            .map(|x| x.as_str())
+            .unwrap_or_else(|| mod_name.split('.').next().unwrap_or(mod_name));
+        if let Some(v) = env.get(mod_name) {
+            env.set(bind_name, v);
+        }
+    }
+    Ok(Flow::Continue)
+}
+
+#[inline(never)]
+fn exec_import_from(
+    s: &rustpython_parser::ast::StmtImportFrom,
+    env: &mut Env,
+) -> Result<Flow, ReplError> {
+    // Treat `from X import y [as z]` as bindings to pre-injected module attributes.
+    // We intentionally do not perform any dynamic importing.
+    let level = s.level.map(|l| l.to_u32()).unwrap_or(0);
+    if level != 0 {
+        return Ok(Flow::Continue);
+    }
+    let Some(module) = &s.module else {
+        return Ok(Flow::Continue);
+    };
+    let module_name = module.as_str();
+
+    // Only bind from modules that are already present.
+    if env.get(module_name).is_none() {
+        return Ok(Flow::Continue);
+    }
+
+    for a in &s.names {
+        let name = a.name.as_str();
+        if name == "*" {
+            continue;
+        }
+        let bind_name = a.asname.as_ref().map(|x| x.as_str()).unwrap_or(name);
+        match importable_module_attr_value(module_name, name) {
+            Some(v) => env.set(bind_name, v),
+            None if env.import_policy == ImportPolicy::ForbidUnknown => {
+                return Err(ReplError::ForbiddenName(format!("{module_name}.{name}")))
+            }
+            None => {}
+        }
+    }
+    Ok(Flow::Continue)
+}
+
 fn bind_for_target(
     target: &rustpython_parser::ast::Expr,
     it: Value,
@@ -359,24 +702,52 @@ fn bind_for_target(
     }
 }
 
+/// Unpack `it` into `a, b` or `first, *rest, last`; the starred name gets a list of
+/// whatever the plain names around it don't take.
 fn bind_unpack_elts(
     elts: &[rustpython_parser::ast::Expr],
     it: Value,
     env: &mut Env,
 ) -> Result<(), ReplError> {
-    let Value::List(xs) = it else {
-        return Err(ReplError::TypeError(
-            "for target expects iterable of lists".into(),
-        ));
-    };
-    if xs.len() != elts.len() {
-        return Err(ReplError::ValueError("unpack mismatch".into()));
-    }
-    for (el, v) in elts.iter().zip(xs.into_iter()) {
-        match el {
-            rustpython_parser::ast::Expr::Name(n) => env.set(n.id.as_str(), v),
-            _ => return Err(ReplError::ForbiddenSyntax("for target".into())),
+    use rustpython_parser::ast::Expr;
+    let type_name = it.type_name();
+    let xs = iter_to_vec(it).map_err(|_| {
+        ReplError::TypeError(format!("cannot unpack non-iterable {type_name} object"))
+    })?;
+    let star = elts.iter().position(|el| matches!(el, Expr::Starred(_)));
+    let fixed = elts.len() - usize::from(star.is_some());
+    match star {
+        None if xs.len() > fixed => {
+            return Err(ReplError::ValueError(format!(
+                "too many values to unpack (expected {fixed})"
+            )))
+        }
+        None if xs.len() < fixed => {
+            return Err(ReplError::ValueError(format!(
+                "not enough values to unpack (expected {fixed}, got {})",
+                xs.len()
+            )))
         }
+        Some(_) if xs.len() < fixed => {
+            return Err(ReplError::ValueError(format!(
+                "not enough values to unpack (expected at least {fixed}, got {})",
+                xs.len()
+            )))
+        }
+        _ => {}
+    }
+    let rest_len = xs.len() - fixed;
+    let mut xs = xs.into_iter();
+    for el in elts {
+        let (name, v) = match el {
+            Expr::Name(n) => (n, xs.next().unwrap_or(Value::None)),
+            Expr::Starred(st) => match st.value.as_ref() {
+                Expr::Name(n) => (n, Value::List(xs.by_ref().take(rest_len).collect())),
+                _ => return Err(ReplError::ForbiddenSyntax("unpack target".into())),
+            },
+            _ => return Err(ReplError::ForbiddenSyntax("unpack target".into())),
+        };
+        env.set(name.id.as_str(), v);
     }
     Ok(())
 }
@@ -411,6 +782,16 @@ fn importable_module_attr_value(module: &str, attr: &str) -> Option<Value> {
             module: "json".into(),
             attr: "loads".into(),
         })),
+        ("random", "randint" | "randrange" | "choice" | "seed") => {
+            Some(Value::Callable(Callable::Module {
+                module: "random".into(),
+                attr: attr.into(),
+            }))
+        }
+        ("sys", "exit") => Some(Value::Callable(Callable::Module {
+            module: "sys".into(),
+            attr: "exit".into(),
+        })),
         _ => None,
     }
 }
@@ -418,9 +799,12 @@ fn importable_module_attr_value(module: &str, attr: &str) -> Option<Value> {
 fn iter_to_vec(v: Value) -> Result<Vec<Value>, ReplError> {
     match v {
         Value::Str(s) => Ok(s.chars().map(|c| Value::Str(c.to_string())).collect()),
-        Value::Bytes(b) => Ok(b.into_iter().map(|x| Value::Int(x as i64)).collect()),
-        Value::List(xs) => Ok(xs),
+        Value::Bytes(b) | Value::Bytearray(b) => {
+            Ok(b.into_iter().map(|x| Value::Int(x as i64)).collect())
+        }
+        Value::List(xs) | Value::Tuple(xs) => Ok(xs),
         Value::Dict(m) => Ok(m.keys().cloned().map(Value::Str).collect()),
+        Value::Set(items) => Ok(items.iter().map(SetItem::to_value).collect()),
         _ => Err(ReplError::TypeError(format!(
             "object is not iterable: {}",
             v.type_name()
@@ -432,6 +816,21 @@ fn eval_expr(
     expr: &rustpython_parser::ast::Expr,
     env: &mut Env,
     sink: &mut PrintSink,
+) -> Result<Value, ReplError> {
+    env.depth += 1;
+    let res = if env.depth > env.max_depth {
+        Err(ReplError::ForbiddenSyntax("nesting too deep".into()))
+    } else {
+        eval_expr_kind(expr, env, sink)
+    };
+    env.depth -= 1;
+    res
+}
+
+fn eval_expr_kind(
+    expr: &rustpython_parser::ast::Expr,
+    env: &mut Env,
+    sink: &mut PrintSink,
 ) -> Result<Value, ReplError> {
     use rustpython_parser::ast::Expr::*;
     match expr {
@@ -489,15 +888,33 @@ fn eval_expr(
             }
             Ok(Value::Dict(out))
         }
+        Set(e) => {
+            let mut out = BTreeSet::new();
+            for el in &e.elts {
+                out.insert(SetItem::from_value(&eval_expr(el, env, sink)?)?);
+            }
+            Ok(Value::Set(out))
+        }
         Tuple(e) => {
-            // treat tuple as list for now (only used for internal purposes, rarely observed)
             let mut out = Vec::new();
             for el in &e.elts {
                 out.push(eval_expr(el, env, sink)?);
             }
-            Ok(Value::List(out))
+            Ok(Value::Tuple(out))
         }
         ListComp(e) => eval_listcomp(e, env, sink),
+        DictComp(e) => eval_dictcomp(e, env, sink),
+        JoinedStr(e) => {
+            let mut out = String::new();
+            for part in &e.values {
+                match eval_expr(part, env, sink)? {
+                    Value::Str(s) => out.push_str(&s),
+                    other => out.push_str(&to_print_string(&other)),
+                }
+            }
+            Ok(Value::Str(out))
+        }
+        FormattedValue(e) => eval_formatted_value(e, env, sink),
         _ => Err(ReplError::ForbiddenSyntax(format!("{:?}", expr))),
     }
 }
@@ -535,11 +952,27 @@ fn eval_listcomp(
     env: &mut Env,
     sink: &mut PrintSink,
 ) -> Result<Value, ReplError> {
-    // Restrict to a single generator: [elt for name in iterable if cond]
-    if e.generators.len() != 1 {
-        return Err(ReplError::ForbiddenSyntax("listcomp generators".into()));
-    }
-    let gen = &e.generators[0];
+    // [elt for name in iterable if cond for name2 in iterable2 ...], nested left to right.
+    env.push_locals();
+    let mut out = Vec::new();
+    listcomp_level(e, 0, env, sink, &mut out)?;
+    env.pop_locals();
+
+    Ok(Value::List(out))
+}
+
+/// Iterates generator `level`, recursing into the next one for each item that passes its `ifs`.
+fn listcomp_level(
+    e: &rustpython_parser::ast::ExprListComp,
+    level: usize,
+    env: &mut Env,
+    sink: &mut PrintSink,
+    out: &mut Vec<Value>,
+) -> Result<(), ReplError> {
+    let Some(gen) = e.generators.get(level) else {
+        out.push(eval_expr(&e.elt, env, sink)?);
+        return Ok(());
+    };
     if gen.is_async {
         return Err(ReplError::ForbiddenSyntax("async listcomp".into()));
     }
@@ -550,8 +983,6 @@ fn eval_listcomp(
     let iter_v = eval_expr(&gen.iter, env, sink)?;
     let items = iter_to_vec(iter_v)?;
 
-    env.push_locals();
-    let mut out = Vec::new();
     for it in items {
         env.set(&target_name, it);
         let mut ok = true;
@@ -565,18 +996,68 @@ fn eval_listcomp(
         if !ok {
             continue;
         }
-        out.push(eval_expr(&e.elt, env, sink)?);
+        listcomp_level(e, level + 1, env, sink, out)?;
     }
-    env.pop_locals();
-
-    Ok(Value::List(out))
+    Ok(())
 }
 
-fn constant_to_value(c: &rustpython_parser::ast::Constant) -> Result<Value, ReplError> {
-    use rustpython_parser::ast::Constant::*;
-    match c {
-        None => Ok(Value::None),
-        Bool(b) => Ok(Value::Bool(*b)),
+fn eval_dictcomp(
+    e: &rustpython_parser::ast::ExprDictComp,
+    env: &mut Env,
+    sink: &mut PrintSink,
+) -> Result<Value, ReplError> {
+    // Restrict to a single generator: {key: value for name in iterable if cond}
+    if e.generators.len() != 1 {
+        return Err(ReplError::ForbiddenSyntax("dictcomp generators".into()));
+    }
+    let gen = &e.generators[0];
+    if gen.is_async {
+        return Err(ReplError::ForbiddenSyntax("async dictcomp".into()));
+    }
+    let target_name = match &gen.target {
+        rustpython_parser::ast::Expr::Name(n) => n.id.to_string(),
+        _ => return Err(ReplError::ForbiddenSyntax("dictcomp target".into())),
+    };
+    let iter_v = eval_expr(&gen.iter, env, sink)?;
+    let items = iter_to_vec(iter_v)?;
+
+    env.push_locals();
+    let mut out = BTreeMap::new();
+    for it in items {
+        env.set(&target_name, it);
+        let mut ok = true;
+        for if_expr in &gen.ifs {
+            let v = eval_expr(if_expr, env, sink)?;
+            if !v.to_bool() {
+                ok = false;
+                break;
+            }
+        }
+        if !ok {
+            continue;
+        }
+        // Dicts are str-keyed in this subset, like dict literals.
+        let key = match eval_expr(&e.key, env, sink)? {
+            Value::Str(s) => s,
+            other => {
+                return Err(ReplError::TypeError(format!(
+                    "dict keys must be str, got {}",
+                    other.type_name()
+                )))
+            }
+        };
+        out.insert(key, eval_expr(&e.value, env, sink)?);
+    }
+    env.pop_locals();
+
+    Ok(Value::Dict(out))
+}
+
+fn constant_to_value(c: &rustpython_parser::ast::Constant) -> Result<Value, ReplError> {
+    use rustpython_parser::ast::Constant::*;
+    match c {
+        None => Ok(Value::None),
+        Bool(b) => Ok(Value::Bool(*b)),
         Str(s) => Ok(Value::Str(s.clone())),
         Bytes(b) => Ok(Value::Bytes(b.clone())),
         Int(i) => {
@@ -586,26 +1067,76 @@ fn constant_to_value(c: &rustpython_parser::ast::Constant) -> Result<Value, Repl
                 .map_err(|_| ReplError::ValueError("int out of range".into()))?;
             Ok(Value::Int(v))
         }
-        _ => Err(ReplError::ForbiddenSyntax("unsupported constant".into())),
+        Float(x) => Ok(Value::Float(*x)),
+        Ellipsis => Ok(Value::Ellipsis),
+        Complex { .. } => Err(ReplError::ForbiddenSyntax(
+            "complex number literals are not supported".into(),
+        )),
+        Tuple(_) => Err(ReplError::ForbiddenSyntax("unsupported constant".into())),
     }
 }
 
+/// One `{value!conv:spec}` field of an f-string; the spec may itself hold fields.
+fn eval_formatted_value(
+    e: &rustpython_parser::ast::ExprFormattedValue,
+    env: &mut Env,
+    sink: &mut PrintSink,
+) -> Result<Value, ReplError> {
+    use rustpython_parser::ast::ConversionFlag;
+    let v = eval_expr(&e.value, env, sink)?;
+    let v = match e.conversion {
+        ConversionFlag::None => v,
+        ConversionFlag::Str => Value::Str(to_print_string(&v)),
+        ConversionFlag::Repr | ConversionFlag::Ascii => Value::Str(py_repr_value(&v)),
+    };
+    let spec = match &e.format_spec {
+        Some(spec) => match eval_expr(spec, env, sink)? {
+            Value::Str(s) => s,
+            other => to_print_string(&other),
+        },
+        None => String::new(),
+    };
+    Ok(Value::Str(super::format_spec::format_value(&v, &spec)?))
+}
+
 fn eval_binop(
     e: &rustpython_parser::ast::ExprBinOp,
     env: &mut Env,
     sink: &mut PrintSink,
+) -> Result<Value, ReplError> {
+    // Fold a left-leaning chain (`a + b + c + ...`) iteratively, mirroring the validator, so
+    // its length costs neither stack nor nesting depth.
+    let mut spine = vec![e];
+    while let rustpython_parser::ast::Expr::BinOp(inner) = spine[spine.len() - 1].left.as_ref() {
+        spine.push(inner);
+    }
+    let mut acc = eval_expr(&spine[spine.len() - 1].left, env, sink)?;
+    for b in spine.iter().rev() {
+        let r = eval_expr(&b.right, env, sink)?;
+        acc = apply_binop(b.op, acc, r)?;
+    }
+    Ok(acc)
+}
+
+/// `l <op> r` on already-evaluated operands; shared by binary expressions and `x <op>= y`.
+fn apply_binop(
+    op: rustpython_parser::ast::Operator,
+    l: Value,
+    r: Value,
 ) -> Result<Value, ReplError> {
     use rustpython_parser::ast::Operator;
-    let l = eval_expr(&e.left, env, sink)?;
-    let r = eval_expr(&e.right, env, sink)?;
-    match e.op {
+    match op {
         Operator::Add => match (l, r) {
             (Value::Str(a), Value::Str(b)) => Ok(Value::Str(a + &b)),
             (Value::Bytes(mut a), Value::Bytes(b)) => {
                 a.extend_from_slice(&b);
                 Ok(Value::Bytes(a))
             }
-            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a + b)),
+            (Value::Int(a), Value::Int(b)) => a
+                .checked_add(b)
+                .map(Value::Int)
+                .ok_or_else(|| ReplError::ValueError("integer overflow in +".into())),
+            (a, b) if is_float_pair(&a, &b) => Ok(Value::Float(as_f64(&a) + as_f64(&b))),
             (a, b) => Err(ReplError::TypeError(format!(
                 "unsupported +: {} and {}",
                 a.type_name(),
@@ -613,15 +1144,67 @@ fn eval_binop(
             ))),
         },
         Operator::Sub => match (l, r) {
-            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a - b)),
+            (Value::Int(a), Value::Int(b)) => a
+                .checked_sub(b)
+                .map(Value::Int)
+                .ok_or_else(|| ReplError::ValueError("integer overflow in -".into())),
+            (Value::Set(a), Value::Set(b)) => Ok(Value::Set(&a - &b)),
+            (a, b) if is_float_pair(&a, &b) => Ok(Value::Float(as_f64(&a) - as_f64(&b))),
             (a, b) => Err(ReplError::TypeError(format!(
                 "unsupported -: {} and {}",
                 a.type_name(),
                 b.type_name()
             ))),
         },
+        Operator::Mult => match (l, r) {
+            (Value::Int(a), Value::Int(b)) => a
+                .checked_mul(b)
+                .map(Value::Int)
+                .ok_or_else(|| ReplError::ValueError("integer overflow in *".into())),
+            (a, b) if is_float_pair(&a, &b) => Ok(Value::Float(as_f64(&a) * as_f64(&b))),
+            (Value::Str(s), Value::Int(n)) | (Value::Int(n), Value::Str(s)) => {
+                let n = repeat_count(s.chars().count(), n)?;
+                Ok(Value::Str(s.repeat(n)))
+            }
+            (Value::List(xs), Value::Int(n)) | (Value::Int(n), Value::List(xs)) => {
+                let n = repeat_count(xs.len(), n)?;
+                Ok(Value::List(
+                    xs.iter().cycle().take(xs.len() * n).cloned().collect(),
+                ))
+            }
+            (a, b) => Err(ReplError::TypeError(format!(
+                "unsupported *: {} and {}",
+                a.type_name(),
+                b.type_name()
+            ))),
+        },
         Operator::Mod => match (l, r) {
-            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a % b)),
+            (Value::Int(a), Value::Int(b)) => {
+                if b == 0 {
+                    return Err(ReplError::ValueError("integer modulo by zero".into()));
+                }
+                // Python's `%` takes the sign of the divisor; `wrapping_rem` only wraps for
+                // `i64::MIN % -1`, whose remainder is 0 anyway.
+                let r = a.wrapping_rem(b);
+                Ok(Value::Int(if r != 0 && (r < 0) != (b < 0) {
+                    r + b
+                } else {
+                    r
+                }))
+            }
+            (a, b) if is_float_pair(&a, &b) => {
+                let (x, y) = (as_f64(&a), as_f64(&b));
+                if y == 0.0 {
+                    return Err(ReplError::ValueError("float modulo by zero".into()));
+                }
+                // Python's `%` takes the sign of the divisor.
+                let r = x % y;
+                Ok(Value::Float(if r != 0.0 && (r < 0.0) != (y < 0.0) {
+                    r + y
+                } else {
+                    r
+                }))
+            }
             (Value::Str(fmt), arg) => format_percent(&fmt, arg),
             (a, b) => Err(ReplError::TypeError(format!(
                 "unsupported %: {} and {}",
@@ -629,18 +1212,89 @@ fn eval_binop(
                 b.type_name()
             ))),
         },
+        Operator::Div => match (l, r) {
+            (a, b) if is_number(&a) && is_number(&b) => {
+                let y = as_f64(&b);
+                if y == 0.0 {
+                    return Err(ReplError::ValueError("division by zero".into()));
+                }
+                Ok(Value::Float(as_f64(&a) / y))
+            }
+            (a, b) => Err(ReplError::TypeError(format!(
+                "unsupported /: {} and {}",
+                a.type_name(),
+                b.type_name()
+            ))),
+        },
         Operator::BitOr => match (l, r) {
             (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a | b)),
+            // Python 3.9 dict merge: keys from the right operand win.
+            (Value::Dict(mut a), Value::Dict(b)) => {
+                a.extend(b);
+                Ok(Value::Dict(a))
+            }
+            (Value::Set(mut a), Value::Set(b)) => {
+                a.extend(b);
+                Ok(Value::Set(a))
+            }
             (a, b) => Err(ReplError::TypeError(format!(
                 "unsupported |: {} and {}",
                 a.type_name(),
                 b.type_name()
             ))),
         },
+        Operator::BitAnd => match (l, r) {
+            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a & b)),
+            (Value::Set(a), Value::Set(b)) => Ok(Value::Set(&a & &b)),
+            (a, b) => Err(ReplError::TypeError(format!(
+                "unsupported &: {} and {}",
+                a.type_name(),
+                b.type_name()
+            ))),
+        },
+        Operator::BitXor => match (l, r) {
+            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a ^ b)),
+            (Value::Set(a), Value::Set(b)) => Ok(Value::Set(&a ^ &b)),
+            (a, b) => Err(ReplError::TypeError(format!(
+                "unsupported ^: {} and {}",
+                a.type_name(),
+                b.type_name()
+            ))),
+        },
         _ => Err(ReplError::ForbiddenSyntax("unsupported operator".into())),
     }
 }
 
+fn is_number(v: &Value) -> bool {
+    matches!(v, Value::Int(_) | Value::Float(_))
+}
+
+/// Mixed int/float operands (at least one float), which combine as floats.
+fn is_float_pair(a: &Value, b: &Value) -> bool {
+    is_number(a) && is_number(b) && (matches!(a, Value::Float(_)) || matches!(b, Value::Float(_)))
+}
+
+fn as_f64(v: &Value) -> f64 {
+    match v {
+        Value::Int(i) => *i as f64,
+        Value::Float(x) => *x,
+        _ => f64::NAN,
+    }
+}
+
+/// Validate a sequence repetition count (negative means empty, like Python).
+fn repeat_count(len: usize, n: i64) -> Result<usize, ReplError> {
+    // Hard cap to keep resource bounded.
+    const MAX_REPEAT_ITEMS: usize = 1_000_000;
+    let n = usize::try_from(n).unwrap_or(0);
+    if len.saturating_mul(n) > MAX_REPEAT_ITEMS {
+        return Err(ReplError::ResourceLimitExceeded(
+            "sequence repetition exceeds max length".into(),
+        ));
+    }
+    Ok(n)
+}
+
 fn eval_unaryop(
     e: &rustpython_parser::ast::ExprUnaryOp,
     env: &mut Env,
@@ -651,7 +1305,11 @@ fn eval_unaryop(
     match e.op {
         UnaryOp::Not => Ok(Value::Bool(!v.to_bool())),
         UnaryOp::USub => match v {
-            Value::Int(i) => Ok(Value::Int(-i)),
+            Value::Int(i) => i
+                .checked_neg()
+                .map(Value::Int)
+                .ok_or_else(|| ReplError::ValueError("integer overflow in unary -".into())),
+            Value::Float(x) => Ok(Value::Float(-x)),
             _ => Err(ReplError::TypeError(format!(
                 "bad operand type for unary -: '{}'",
                 v.type_name()
@@ -667,20 +1325,30 @@ fn eval_compare(
     sink: &mut PrintSink,
 ) -> Result<Value, ReplError> {
     use rustpython_parser::ast::CmpOp;
+    // `k in d` on a bound name: test membership in place (a `BTreeMap` lookup for dicts).
+    if let ([op @ (CmpOp::In | CmpOp::NotIn)], [rustpython_parser::ast::Expr::Name(n)]) =
+        (e.ops.as_slice(), e.comparators.as_slice())
+    {
+        let needle = eval_expr(&e.left, env, sink)?;
+        let haystack = env
+            .get_ref(n.id.as_str())
+            .ok_or_else(|| ReplError::NameError(n.id.to_string()))?;
+        return Ok(Value::Bool(is_in(&needle, haystack) == (*op == CmpOp::In)));
+    }
     let mut left = eval_expr(&e.left, env, sink)?;
     for (op, right_expr) in e.ops.iter().zip(e.comparators.iter()) {
         let right = eval_expr(right_expr, env, sink)?;
         let ok = match op {
-            CmpOp::Eq => left == right,
-            CmpOp::NotEq => left != right,
+            CmpOp::Eq => py_eq(&left, &right),
+            CmpOp::NotEq => !py_eq(&left, &right),
             CmpOp::Is => is_same(&left, &right),
             CmpOp::IsNot => !is_same(&left, &right),
             CmpOp::In => is_in(&left, &right),
             CmpOp::NotIn => !is_in(&left, &right),
-            CmpOp::Lt => cmp_int(&left, &right, |a, b| a < b)?,
-            CmpOp::LtE => cmp_int(&left, &right, |a, b| a <= b)?,
-            CmpOp::Gt => cmp_int(&left, &right, |a, b| a > b)?,
-            CmpOp::GtE => cmp_int(&left, &right, |a, b| a >= b)?,
+            CmpOp::Lt => cmp_values("<", &left, &right, std::cmp::Ordering::is_lt)?,
+            CmpOp::LtE => cmp_values("<=", &left, &right, std::cmp::Ordering::is_le)?,
+            CmpOp::Gt => cmp_values(">", &left, &right, std::cmp::Ordering::is_gt)?,
+            CmpOp::GtE => cmp_values(">=", &left, &right, std::cmp::Ordering::is_ge)?,
             // Keep this as a forward-compat fallback; should be unreachable for current CmpOp set.
             #[allow(unreachable_patterns)]
             _ => return Err(ReplError::ForbiddenSyntax("unsupported compare".into())),
@@ -693,21 +1361,68 @@ fn eval_compare(
     Ok(Value::Bool(true))
 }
 
-fn cmp_int<F>(a: &Value, b: &Value, f: F) -> Result<bool, ReplError>
+/// Ordering comparison: ints and floats (mixed ones compare as floats; NaN is unordered),
+/// and str or bytes against their own kind.
+fn cmp_values<F>(op: &str, a: &Value, b: &Value, f: F) -> Result<bool, ReplError>
 where
-    F: FnOnce(i64, i64) -> bool,
+    F: FnOnce(std::cmp::Ordering) -> bool,
 {
+    Ok(py_order(op, a, b)?.is_some_and(f))
+}
+
+fn py_order(op: &str, a: &Value, b: &Value) -> Result<Option<std::cmp::Ordering>, ReplError> {
+    match (a, b) {
+        (Value::Int(x), Value::Int(y)) => Ok(Some(x.cmp(y))),
+        (a, b) if is_number(a) && is_number(b) => Ok(as_f64(a).partial_cmp(&as_f64(b))),
+        // UTF-8 byte order is code point order, which is how Python orders str.
+        (Value::Str(x), Value::Str(y)) => Ok(Some(x.cmp(y))),
+        (Value::Bytes(x) | Value::Bytearray(x), Value::Bytes(y) | Value::Bytearray(y)) => {
+            Ok(Some(x.cmp(y)))
+        }
+        // Same wording as CPython, so a stray `None` from `.get()` is easy to spot.
+        _ => Err(ReplError::TypeError(format!(
+            "'{op}' not supported between instances of '{}' and '{}'",
+            python_type_name(a),
+            python_type_name(b)
+        ))),
+    }
+}
+
+/// `==` with Python's numeric equality across int and float (`1 == 1.0`), also inside
+/// lists, tuples and dict values.
+fn py_eq(a: &Value, b: &Value) -> bool {
     match (a, b) {
-        (Value::Int(x), Value::Int(y)) => Ok(f(*x, *y)),
-        _ => Err(ReplError::TypeError("comparison expects int".into())),
+        (a, b) if is_float_pair(a, b) => as_f64(a) == as_f64(b),
+        (Value::List(xs), Value::List(ys)) | (Value::Tuple(xs), Value::Tuple(ys)) => {
+            xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| py_eq(x, y))
+        }
+        (Value::Dict(xs), Value::Dict(ys)) => {
+            xs.len() == ys.len()
+                && xs
+                    .iter()
+                    .zip(ys)
+                    .all(|((kx, vx), (ky, vy))| kx == ky && py_eq(vx, vy))
+        }
+        _ => a == b,
+    }
+}
+
+fn python_type_name(v: &Value) -> &'static str {
+    match v {
+        Value::None => "NoneType",
+        other => other.type_name(),
     }
 }
 
 fn is_in(needle: &Value, haystack: &Value) -> bool {
     match (needle, haystack) {
         (Value::Str(n), Value::Str(h)) => h.contains(n),
-        (Value::Str(n), Value::List(xs)) => xs.iter().any(|v| matches!(v, Value::Str(s) if s == n)),
-        (Value::Int(i), Value::List(xs)) => xs.iter().any(|v| matches!(v, Value::Int(j) if j == i)),
+        // Like Python, `x in d` is key membership; use `x in d.values()` for values.
+        (Value::Str(n), Value::Dict(m)) => m.contains_key(n),
+        (_, Value::List(xs) | Value::Tuple(xs)) => xs.iter().any(|v| py_eq(v, needle)),
+        (_, Value::Set(items)) => {
+            SetItem::from_value(needle).is_ok_and(|item| items.contains(&item))
+        }
         _ => false,
     }
 }
@@ -718,7 +1433,7 @@ fn is_same(a: &Value, b: &Value) -> bool {
         (Value::Bool(x), Value::Bool(y)) => x == y,
         (Value::Int(x), Value::Int(y)) => x == y,
         (Value::Str(x), Value::Str(y)) => x == y,
-        (Value::Bytes(x), Value::Bytes(y)) => x == y,
+        (Value::Bytes(x) | Value::Bytearray(x), Value::Bytes(y) | Value::Bytearray(y)) => x == y,
         _ => false,
     }
 }
@@ -728,29 +1443,47 @@ fn eval_call(
     env: &mut Env,
     sink: &mut PrintSink,
 ) -> Result<Value, ReplError> {
-    // Special-case in-place list append: xs.append(v)
+    // Special-case in-place list mutation on name-bound lists: xs.append(v), xs.remove(v), ...
     if let rustpython_parser::ast::Expr::Attribute(a) = e.func.as_ref() {
-        if a.attr.as_str() == "append" {
+        let attr = a.attr.as_str();
+        if matches!(
+            attr,
+            "append" | "extend" | "pop" | "remove" | "clear" | "reverse"
+        ) {
             if let rustpython_parser::ast::Expr::Name(n) = a.value.as_ref() {
-                if e.args.len() != 1 || !e.keywords.is_empty() {
-                    return Err(ReplError::TypeError("append(x)".into()));
+                let (arity, sig) = match attr {
+                    "clear" | "reverse" => (0..=0, "()"),
+                    "pop" => (0..=1, "([i])"),
+                    _ => (1..=1, "(x)"),
+                };
+                if !arity.contains(&e.args.len()) || !e.keywords.is_empty() {
+                    return Err(ReplError::TypeError(format!("{attr}{sig}")));
+                }
+                let mut args_v = Vec::new();
+                for arg in &e.args {
+                    args_v.push(eval_expr(arg, env, sink)?);
                 }
-                let item = eval_expr(&e.args[0], env, sink)?;
                 let cur = env
                     .get(n.id.as_str())
                     .ok_or_else(|| ReplError::NameError(n.id.to_string()))?;
-                let mut xs = match cur {
-                    Value::List(v) => v,
+                let (updated, result) = match cur {
+                    Value::List(mut xs) => {
+                        let result = mutate_list(&mut xs, attr, args_v)?;
+                        (Value::List(xs), result)
+                    }
+                    Value::Bytearray(mut b) if attr == "append" => {
+                        b.push(byte_value(args_v.first().unwrap_or(&Value::None))?);
+                        (Value::Bytearray(b), Value::None)
+                    }
                     other => {
                         return Err(ReplError::TypeError(format!(
-                            "append() target must be list, got {}",
+                            "{attr}() target must be list, got {}",
                             other.type_name()
                         )))
                     }
                 };
-                xs.push(item);
-                env.set(n.id.as_str(), Value::List(xs));
-                return Ok(Value::None);
+                env.set(n.id.as_str(), updated);
+                return Ok(result);
             }
         }
     }
@@ -760,7 +1493,7 @@ fn eval_call(
     for a in &e.args {
         args_v.push(eval_expr(a, env, sink)?);
     }
-    let mut kwargs: HashMap<String, Value> = HashMap::new();
+    let mut kwargs: BTreeMap<String, Value> = BTreeMap::new();
     for k in &e.keywords {
         let key = k
             .arg
@@ -778,23 +1511,36 @@ fn eval_call(
     }
 }
 
+/// Builtins with sizeable bodies live in `#[inline(never)]` helpers so this frame stays small:
+/// it sits on the stack once per level of user-function recursion.
 fn call_name(
     name: &str,
     args: Vec<Value>,
-    kwargs: HashMap<String, Value>,
+    kwargs: BTreeMap<String, Value>,
     env: &mut Env,
     sink: &mut PrintSink,
 ) -> Result<Value, ReplError> {
     match name {
         "print" => {
-            if !kwargs.is_empty() {
-                return Err(ReplError::ForbiddenSyntax("keyword args".into()));
+            let mut sep = " ".to_string();
+            for (k, v) in kwargs {
+                match (k.as_str(), v) {
+                    ("sep", Value::Str(s)) => sep = s,
+                    ("sep", Value::None) => {}
+                    ("sep", other) => {
+                        return Err(ReplError::TypeError(format!(
+                            "print() sep must be str, got {}",
+                            other.type_name()
+                        )))
+                    }
+                    _ => return Err(ReplError::ForbiddenSyntax("keyword args".into())),
+                }
             }
             let mut parts = Vec::new();
             for v in args {
                 parts.push(to_print_string(&v));
             }
-            sink.push_print_line(&parts.join(" "))?;
+            sink.push_print_line(&parts.join(&sep))?;
             Ok(Value::None)
         }
         "len" => {
@@ -808,125 +1554,103 @@ fn call_name(
             }
             match &args[0] {
                 Value::Str(s) => Ok(Value::Int(s.chars().count() as i64)),
-                Value::Bytes(b) => Ok(Value::Int(b.len() as i64)),
-                Value::List(v) => Ok(Value::Int(v.len() as i64)),
+                Value::Bytes(b) | Value::Bytearray(b) => Ok(Value::Int(b.len() as i64)),
+                Value::List(v) | Value::Tuple(v) => Ok(Value::Int(v.len() as i64)),
+                Value::Dict(m) => Ok(Value::Int(m.len() as i64)),
+                Value::Set(m) => Ok(Value::Int(m.len() as i64)),
                 _ => Err(ReplError::TypeError("object has no len()".into())),
             }
         }
-        "max" => {
+        "max" | "min" => builtin_max_min(name, args, kwargs),
+        "sorted" => builtin_sorted(args, kwargs),
+        "sum" => builtin_sum(args, kwargs),
+        "zip" => builtin_zip(args, kwargs),
+        "int" => builtin_int(args, kwargs),
+        "any" | "all" => {
             if !kwargs.is_empty() {
                 return Err(ReplError::ForbiddenSyntax("keyword args".into()));
             }
-            if args.len() != 2 {
+            if args.len() != 1 {
+                return Err(ReplError::TypeError(format!(
+                    "{name}() takes exactly one argument"
+                )));
+            }
+            let items = iter_to_vec(args.into_iter().next().expect("one arg"))?;
+            Ok(Value::Bool(if name == "any" {
+                items.iter().any(Value::to_bool)
+            } else {
+                items.iter().all(Value::to_bool)
+            }))
+        }
+        "set" => builtin_set(args, kwargs),
+        "bool" => {
+            if !kwargs.is_empty() {
+                return Err(ReplError::ForbiddenSyntax("keyword args".into()));
+            }
+            if args.len() > 1 {
                 return Err(ReplError::TypeError(
-                    "max() takes 2 arguments in this subset".into(),
+                    "bool() takes at most one argument".into(),
                 ));
             }
-            match (&args[0], &args[1]) {
-                (Value::Int(a), Value::Int(b)) => Ok(Value::Int(std::cmp::max(*a, *b))),
-                _ => Err(ReplError::TypeError("max() only supports int".into())),
-            }
-        }
-        "rank_documents" => {
-            // Prefer signature: rank_documents(query: str, documents: list, top_k: int=5, min_score: ignored)
-            // For robustness, also accept swapped first args: (documents, query, top_k).
-            let mut top_k: Option<i64> = None;
-            for (k, v) in &kwargs {
-                match k.as_str() {
-                    "top_k" => match v {
-                        Value::Int(i) => top_k = Some(*i),
-                        other => {
-                            return Err(ReplError::TypeError(format!(
-                                "rank_documents() top_k must be int, got {}",
-                                other.type_name()
-                            )))
-                        }
-                    },
-                    // accepted but ignored (we avoid floats in this subset)
-                    "min_score" => {}
-                    _ => return Err(ReplError::ForbiddenSyntax("keyword args".into())),
-                }
+            Ok(Value::Bool(args.first().is_some_and(Value::to_bool)))
+        }
+        "ord" => builtin_ord(args, kwargs),
+        "chr" => builtin_chr(args, kwargs),
+        "rank_documents" => builtin_rank_documents(args, kwargs, env),
+        "range" => builtin_range(args, kwargs),
+        "list" => {
+            if !kwargs.is_empty() {
+                return Err(ReplError::ForbiddenSyntax("keyword args".into()));
             }
-
-            if args.len() < 2 || args.len() > 4 {
+            if args.len() > 1 {
                 return Err(ReplError::TypeError(
-                    "rank_documents() takes 2-4 positional arguments".into(),
+                    "list() takes at most 1 argument".into(),
                 ));
             }
-
-            let (docs, query) = match (&args[0], &args[1]) {
-                (Value::Str(q), Value::List(xs)) => (xs.clone(), q.clone()),
-                (Value::List(xs), Value::Str(q)) => (xs.clone(), q.clone()),
-                (a, b) => {
-                    return Err(ReplError::TypeError(format!(
-                        "rank_documents() expects (str, list, ...), got ({}, {})",
-                        a.type_name(),
-                        b.type_name()
-                    )))
-                }
-            };
-
-            if let Some(v) = args.get(2) {
-                match v {
-                    Value::Int(i) => top_k = Some(*i),
-                    other => {
-                        return Err(ReplError::TypeError(format!(
-                            "rank_documents() expects int for top_k, got {}",
-                            other.type_name()
-                        )))
-                    }
-                }
+            match args.into_iter().next() {
+                None => Ok(Value::List(Vec::new())),
+                Some(v) => Ok(Value::List(iter_to_vec(v)?)),
             }
-            let top_k = top_k.unwrap_or(5);
-
-            let out = rank_documents_impl(&docs, &query, top_k)?;
-            Ok(Value::List(out))
         }
-        "range" => {
+        // `str(x)` is the print rendering; `repr(x)` quotes and escapes strings and bytes.
+        "str" | "repr" => {
             if !kwargs.is_empty() {
                 return Err(ReplError::ForbiddenSyntax("keyword args".into()));
             }
-            if args.is_empty() || args.len() > 3 {
-                return Err(ReplError::TypeError(
-                    "range() takes 1 to 3 arguments".into(),
-                ));
+            if args.len() > 1 || (name == "repr" && args.is_empty()) {
+                return Err(ReplError::TypeError(format!(
+                    "{name}() takes exactly one argument"
+                )));
             }
-            let mut ints = Vec::new();
-            for a in &args {
-                match a {
-                    Value::Int(i) => ints.push(*i),
-                    other => {
-                        return Err(ReplError::TypeError(format!(
-                            "range() expects int, got {}",
-                            other.type_name()
-                        )))
-                    }
-                }
+            Ok(Value::Str(match args.first() {
+                None => String::new(),
+                Some(v) if name == "repr" => py_repr_value(v),
+                Some(v) => to_print_string(v),
+            }))
+        }
+        "bytearray" => builtin_bytearray(args, kwargs),
+        "dict" => builtin_dict(args, kwargs),
+        "format" => {
+            if !kwargs.is_empty() {
+                return Err(ReplError::ForbiddenSyntax("keyword args".into()));
             }
-            let (start, stop, step) = match ints.as_slice() {
-                [stop] => (0i64, *stop, 1i64),
-                [start, stop] => (*start, *stop, 1i64),
-                [start, stop, step] => (*start, *stop, *step),
-                _ => unreachable!(),
+            let spec = match args.get(1) {
+                None => "",
+                Some(v) => v.as_str()?,
             };
-            if step == 0 {
-                return Err(ReplError::ValueError("range() step must not be 0".into()));
-            }
-            // Hard cap to keep resource bounded.
-            const MAX_RANGE_LEN: usize = 5000;
-            let mut out = Vec::new();
-            let mut v = start;
-            while (step > 0 && v < stop) || (step < 0 && v > stop) {
-                out.push(Value::Int(v));
-                if out.len() >= MAX_RANGE_LEN {
-                    return Err(ReplError::ResourceLimitExceeded(
-                        "range() exceeds max length".into(),
-                    ));
+            match args.first() {
+                Some(v) if args.len() <= 2 => {
+                    Ok(Value::Str(super::format_spec::format_value(v, spec)?))
                 }
-                v += step;
+                _ => Err(ReplError::TypeError("format(value[, format_spec])".into())),
             }
-            Ok(Value::List(out))
         }
+        "parse_csv" => builtin_parse_csv(args, kwargs),
+        "percent" => int_ratio("percent", &args, &kwargs, 100),
+        "ratio_bps" => int_ratio("ratio_bps", &args, &kwargs, 10_000),
+        "pluck" => builtin_pluck(args, kwargs, env),
+        "get_in" => builtin_get_in(args, kwargs),
+        "reversed" => builtin_reversed(args, kwargs),
         other => match env.get(other) {
             Some(Value::UserFunc(f)) => {
                 if !kwargs.is_empty() {
@@ -940,73 +1664,710 @@ fn call_name(
     }
 }
 
-fn rank_documents_impl(docs: &[Value], query: &str, top_k: i64) -> Result<Vec<Value>, ReplError> {
-    let top_k = top_k.clamp(0, 20) as usize;
-    let terms: Vec<String> = query
-        .to_lowercase()
-        .split(|c: char| !c.is_alphanumeric())
-        .filter(|s| s.len() >= 2)
-        .map(|s| s.to_string())
-        .collect();
-
-    let mut scored: Vec<(i64, String, String)> = Vec::new(); // (score, doc_id, snippet)
-    for d in docs {
-        let Value::Dict(map) = d else {
-            continue;
-        };
-        let Some(Value::Str(doc_id)) = map.get("id").cloned() else {
-            continue;
-        };
-        let Some(Value::Str(text)) = map.get("text").cloned() else {
-            continue;
-        };
-        let hay = text.to_lowercase();
-        let mut s = 0i64;
-        let mut best_snippet: Option<String> = None;
-        for t in &terms {
-            if let Some(i) = hay.find(t) {
-                s += 1;
-                if best_snippet.is_none() {
-                    best_snippet = Some(extract_window(&text, i, t.len(), 80));
-                }
-            }
+#[inline(never)]
+fn builtin_max_min(
+    name: &str,
+    args: Vec<Value>,
+    kwargs: BTreeMap<String, Value>,
+) -> Result<Value, ReplError> {
+    if !kwargs.is_empty() {
+        return Err(ReplError::ForbiddenSyntax("keyword args".into()));
+    }
+    // `max(xs)` reduces over an iterable; `max(a, b, ...)` over the arguments.
+    let items = match args.len() {
+        0 => {
+            return Err(ReplError::TypeError(format!(
+                "{name}() expected at least 1 argument, got 0"
+            )))
         }
-        if s <= 0 {
-            continue;
+        1 => iter_to_vec(args.into_iter().next().expect("one arg"))?,
+        _ => args,
+    };
+    let mut items = items.into_iter();
+    let mut best = items
+        .next()
+        .ok_or_else(|| ReplError::TypeError(format!("{name}() arg is an empty sequence")))?;
+    let (op, wanted) = if name == "max" {
+        (">", std::cmp::Ordering::Greater)
+    } else {
+        ("<", std::cmp::Ordering::Less)
+    };
+    // Ties keep the first item, like Python.
+    for item in items {
+        if py_order(op, &item, &best)? == Some(wanted) {
+            best = item;
         }
-        let snippet = best_snippet.unwrap_or_else(|| extract_window(&text, 0, 0, 80));
-        scored.push((s, doc_id, snippet));
     }
+    Ok(best)
+}
 
-    scored.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
-    let mut out = Vec::new();
-    for (_s, doc_id, snippet) in scored.into_iter().take(top_k) {
-        let mut m = std::collections::BTreeMap::new();
-        // Provide both keys to reduce LLM confusion:
-        // - documents use "id"
-        // - downstream schema uses "doc_id"
-        m.insert("id".to_string(), Value::Str(doc_id.clone()));
-        m.insert("doc_id".to_string(), Value::Str(doc_id));
-        m.insert("snippet".to_string(), Value::Str(snippet));
-        out.push(Value::Dict(m));
+#[inline(never)]
+fn builtin_sorted(args: Vec<Value>, kwargs: BTreeMap<String, Value>) -> Result<Value, ReplError> {
+    let mut reverse = false;
+    for (k, v) in &kwargs {
+        match k.as_str() {
+            "reverse" => reverse = v.to_bool(),
+            _ => return Err(ReplError::ForbiddenSyntax("keyword args".into())),
+        }
     }
-    Ok(out)
+    if args.len() != 1 {
+        return Err(ReplError::TypeError(
+            "sorted() takes exactly one positional argument".into(),
+        ));
+    }
+    let mut items = iter_to_vec(args.into_iter().next().expect("one arg"))?;
+    // Every item must order against the others; checking against the first one is
+    // enough since each orderable kind (numbers, str, bytes) is only ordered within.
+    if let Some(first) = items.first() {
+        for item in &items[1..] {
+            py_order("<", item, first)?;
+        }
+    }
+    let order = |a: &Value, b: &Value| {
+        py_order("<", a, b)
+            .ok()
+            .flatten()
+            .unwrap_or(std::cmp::Ordering::Equal)
+    };
+    // Stable both ways: `reverse` flips the comparison, not the result.
+    if reverse {
+        items.sort_by(|a, b| order(b, a));
+    } else {
+        items.sort_by(|a, b| order(a, b));
+    }
+    Ok(Value::List(items))
 }
 
-fn extract_window(text: &str, start_byte: usize, needle_len: usize, window: usize) -> String {
-    // Best-effort: treat start_byte as a byte offset into UTF-8, but clamp safely.
-    let window = window.max(1);
-    let start = start_byte.saturating_sub(window);
-    let end = (start_byte + needle_len + window).min(text.len());
-    // Snap to UTF-8 boundaries.
-    let start = snap_to_char_boundary(text, start);
-    let end = snap_to_char_boundary(text, end);
-    text[start..end].to_string()
+#[inline(never)]
+fn builtin_sum(args: Vec<Value>, kwargs: BTreeMap<String, Value>) -> Result<Value, ReplError> {
+    if !kwargs.is_empty() {
+        return Err(ReplError::ForbiddenSyntax("keyword args".into()));
+    }
+    if args.is_empty() || args.len() > 2 {
+        return Err(ReplError::TypeError(
+            "sum() takes an iterable and an optional start".into(),
+        ));
+    }
+    let mut args = args.into_iter();
+    let items = iter_to_vec(args.next().expect("one arg"))?;
+    // Bools count as ints, as in Python: `sum([x > 0 for x in xs])` counts matches.
+    let as_num = |v: Value| match v {
+        Value::Bool(b) => Value::Int(b as i64),
+        v => v,
+    };
+    let mut total = as_num(args.next().unwrap_or(Value::Int(0)));
+    for item in items {
+        total = match (total, as_num(item)) {
+            (Value::Int(a), Value::Int(b)) => a
+                .checked_add(b)
+                .map(Value::Int)
+                .ok_or_else(|| ReplError::ValueError("integer overflow in sum".into()))?,
+            (a, b) if is_float_pair(&a, &b) => Value::Float(as_f64(&a) + as_f64(&b)),
+            (a, b) => {
+                return Err(ReplError::TypeError(format!(
+                    "unsupported operand type(s) for +: '{}' and '{}'",
+                    python_type_name(&a),
+                    python_type_name(&b)
+                )))
+            }
+        };
+    }
+    Ok(total)
 }
 
-fn snap_to_char_boundary(s: &str, mut i: usize) -> usize {
-    i = i.min(s.len());
-    while i > 0 && !s.is_char_boundary(i) {
+#[inline(never)]
+fn builtin_zip(args: Vec<Value>, kwargs: BTreeMap<String, Value>) -> Result<Value, ReplError> {
+    if !kwargs.is_empty() {
+        return Err(ReplError::ForbiddenSyntax("keyword args".into()));
+    }
+    if args.len() < 2 {
+        return Err(ReplError::TypeError(
+            "zip() takes at least two iterables in this subset".into(),
+        ));
+    }
+    let columns = args
+        .into_iter()
+        .map(iter_to_vec)
+        .collect::<Result<Vec<_>, _>>()?;
+    let len = columns.iter().map(Vec::len).min().unwrap_or(0);
+    let rows = (0..len)
+        .map(|i| Value::Tuple(columns.iter().map(|c| c[i].clone()).collect()))
+        .collect();
+    Ok(Value::List(rows))
+}
+
+#[inline(never)]
+fn builtin_int(args: Vec<Value>, kwargs: BTreeMap<String, Value>) -> Result<Value, ReplError> {
+    let mut base = None;
+    for (k, v) in &kwargs {
+        match k.as_str() {
+            "base" => base = Some(v.clone()),
+            _ => return Err(ReplError::ForbiddenSyntax("keyword args".into())),
+        }
+    }
+    if args.is_empty() || args.len() > 2 || (args.len() == 2 && base.is_some()) {
+        return Err(ReplError::TypeError(
+            "int() takes a value and an optional base".into(),
+        ));
+    }
+    let mut args = args.into_iter();
+    let value = args.next().expect("one arg");
+    if let Some(base) = base.or(args.next()) {
+        let Value::Int(base) = base else {
+            return Err(ReplError::TypeError(format!(
+                "int() base must be an int, not '{}'",
+                base.type_name()
+            )));
+        };
+        let Value::Str(s) = &value else {
+            return Err(ReplError::TypeError(
+                "int() can't convert non-string with explicit base".into(),
+            ));
+        };
+        return parse_int_literal(s, base).map(Value::Int);
+    }
+    match &value {
+        Value::Int(i) => Ok(Value::Int(*i)),
+        // `as` would saturate, so reject anything outside i64 instead of clamping it.
+        Value::Float(x) if x.is_finite() => {
+            let t = x.trunc();
+            if t >= i64::MIN as f64 && t < -(i64::MIN as f64) {
+                Ok(Value::Int(t as i64))
+            } else {
+                Err(ReplError::ValueError("int out of range".into()))
+            }
+        }
+        Value::Float(x) => Err(ReplError::ValueError(format!(
+            "cannot convert float {} to integer",
+            py_repr_float(*x)
+        ))),
+        Value::Bool(b) => Ok(Value::Int(*b as i64)),
+        Value::Str(s) => parse_int_literal(s, 10).map(Value::Int),
+        other => Err(ReplError::TypeError(format!(
+            "int() argument must be a string or a number, not '{}'",
+            other.type_name()
+        ))),
+    }
+}
+
+#[inline(never)]
+fn builtin_set(args: Vec<Value>, kwargs: BTreeMap<String, Value>) -> Result<Value, ReplError> {
+    if !kwargs.is_empty() {
+        return Err(ReplError::ForbiddenSyntax("keyword args".into()));
+    }
+    if args.len() > 1 {
+        return Err(ReplError::TypeError(
+            "set() takes at most one argument".into(),
+        ));
+    }
+    let mut out = BTreeSet::new();
+    if let Some(iterable) = args.into_iter().next() {
+        for item in iter_to_vec(iterable)? {
+            out.insert(SetItem::from_value(&item)?);
+        }
+    }
+    Ok(Value::Set(out))
+}
+
+#[inline(never)]
+fn builtin_ord(args: Vec<Value>, kwargs: BTreeMap<String, Value>) -> Result<Value, ReplError> {
+    if !kwargs.is_empty() {
+        return Err(ReplError::ForbiddenSyntax("keyword args".into()));
+    }
+    if args.len() != 1 {
+        return Err(ReplError::TypeError(
+            "ord() takes exactly one argument".into(),
+        ));
+    }
+    match &args[0] {
+        Value::Str(s) => {
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(Value::Int(c as i64)),
+                _ => Err(ReplError::TypeError(format!(
+                    "ord() expected a character, but string of length {} found",
+                    s.chars().count()
+                ))),
+            }
+        }
+        Value::Bytes(b) | Value::Bytearray(b) if b.len() == 1 => Ok(Value::Int(b[0] as i64)),
+        other => Err(ReplError::TypeError(format!(
+            "ord() expected string of length 1, but {} found",
+            python_type_name(other)
+        ))),
+    }
+}
+
+#[inline(never)]
+fn builtin_chr(args: Vec<Value>, kwargs: BTreeMap<String, Value>) -> Result<Value, ReplError> {
+    if !kwargs.is_empty() {
+        return Err(ReplError::ForbiddenSyntax("keyword args".into()));
+    }
+    match args.as_slice() {
+        [Value::Int(i)] => u32::try_from(*i)
+            .ok()
+            .and_then(char::from_u32)
+            .map(|c| Value::Str(c.to_string()))
+            .ok_or_else(|| ReplError::ValueError("chr() arg not in range(0x110000)".into())),
+        [other] => Err(ReplError::TypeError(format!(
+            "'{}' object cannot be interpreted as an integer",
+            python_type_name(other)
+        ))),
+        _ => Err(ReplError::TypeError(
+            "chr() takes exactly one argument".into(),
+        )),
+    }
+}
+
+#[inline(never)]
+fn builtin_rank_documents(
+    args: Vec<Value>,
+    kwargs: BTreeMap<String, Value>,
+    env: &mut Env,
+) -> Result<Value, ReplError> {
+    // Prefer signature: rank_documents(query: str, documents: list, top_k: int=5, min_score: ignored)
+    // For robustness, also accept swapped first args: (documents, query, top_k).
+    let mut top_k: Option<i64> = None;
+    let mut boundary = Boundary::Char;
+    for (k, v) in &kwargs {
+        match k.as_str() {
+            "top_k" => match v {
+                Value::Int(i) => top_k = Some(*i),
+                other => {
+                    return Err(ReplError::TypeError(format!(
+                        "rank_documents() top_k must be int, got {}",
+                        other.type_name()
+                    )))
+                }
+            },
+            // accepted but ignored
+            "min_score" => {}
+            // Snap snippets to whole words or lines instead of raw char offsets.
+            "boundary" => {
+                boundary = Boundary::parse(v.as_str()?).ok_or_else(|| {
+                    ReplError::ValueError(
+                        "rank_documents() boundary must be 'char', 'word' or 'line'".into(),
+                    )
+                })?
+            }
+            _ => return Err(ReplError::ForbiddenSyntax("keyword args".into())),
+        }
+    }
+
+    if args.len() < 2 || args.len() > 4 {
+        return Err(ReplError::TypeError(
+            "rank_documents() takes 2-4 positional arguments".into(),
+        ));
+    }
+
+    let (docs, query) = match (&args[0], &args[1]) {
+        (Value::Str(q), Value::List(xs)) => (xs.clone(), q.clone()),
+        (Value::List(xs), Value::Str(q)) => (xs.clone(), q.clone()),
+        (a, b) => {
+            return Err(ReplError::TypeError(format!(
+                "rank_documents() expects (str, list, ...), got ({}, {})",
+                a.type_name(),
+                b.type_name()
+            )))
+        }
+    };
+
+    if let Some(v) = args.get(2) {
+        match v {
+            Value::Int(i) => top_k = Some(*i),
+            other => {
+                return Err(ReplError::TypeError(format!(
+                    "rank_documents() expects int for top_k, got {}",
+                    other.type_name()
+                )))
+            }
+        }
+    }
+    let top_k = top_k.unwrap_or(5);
+
+    let ranked = rank_documents_impl(&mut env.rank_index, &docs, &query, top_k, boundary)?;
+    Ok(Value::List(ranked))
+}
+
+#[inline(never)]
+fn builtin_range(args: Vec<Value>, kwargs: BTreeMap<String, Value>) -> Result<Value, ReplError> {
+    if !kwargs.is_empty() {
+        return Err(ReplError::ForbiddenSyntax("keyword args".into()));
+    }
+    if args.is_empty() || args.len() > 3 {
+        return Err(ReplError::TypeError(
+            "range() takes 1 to 3 arguments".into(),
+        ));
+    }
+    let mut ints = Vec::new();
+    for a in &args {
+        match a {
+            Value::Int(i) => ints.push(*i),
+            other => {
+                return Err(ReplError::TypeError(format!(
+                    "range() expects int, got {}",
+                    other.type_name()
+                )))
+            }
+        }
+    }
+    let (start, stop, step) = match ints.as_slice() {
+        [stop] => (0i64, *stop, 1i64),
+        [start, stop] => (*start, *stop, 1i64),
+        [start, stop, step] => (*start, *stop, *step),
+        _ => unreachable!(),
+    };
+    if step == 0 {
+        return Err(ReplError::ValueError("range() step must not be 0".into()));
+    }
+    // Hard cap to keep resource bounded.
+    const MAX_RANGE_LEN: usize = 5000;
+    let mut out = Vec::new();
+    let mut v = start;
+    while (step > 0 && v < stop) || (step < 0 && v > stop) {
+        out.push(Value::Int(v));
+        if out.len() >= MAX_RANGE_LEN {
+            return Err(ReplError::ResourceLimitExceeded(
+                "range() exceeds max length".into(),
+            ));
+        }
+        // The next value would pass i64::MAX/MIN, so it is past `stop` too.
+        match v.checked_add(step) {
+            Some(next) => v = next,
+            None => break,
+        }
+    }
+    Ok(Value::List(out))
+}
+
+#[inline(never)]
+fn builtin_bytearray(
+    args: Vec<Value>,
+    kwargs: BTreeMap<String, Value>,
+) -> Result<Value, ReplError> {
+    if !kwargs.is_empty() {
+        return Err(ReplError::ForbiddenSyntax("keyword args".into()));
+    }
+    if args.len() > 1 {
+        return Err(ReplError::TypeError(
+            "bytearray() takes at most 1 argument".into(),
+        ));
+    }
+    match args.into_iter().next() {
+        None => Ok(Value::Bytearray(Vec::new())),
+        Some(Value::Int(n)) => {
+            if n < 0 {
+                return Err(ReplError::ValueError("negative count".into()));
+            }
+            // Hard cap to keep resource bounded.
+            if n > 1_000_000 {
+                return Err(ReplError::ResourceLimitExceeded(
+                    "bytearray() exceeds max length".into(),
+                ));
+            }
+            Ok(Value::Bytearray(vec![0; n as usize]))
+        }
+        Some(Value::Bytes(b) | Value::Bytearray(b)) => Ok(Value::Bytearray(b)),
+        Some(Value::List(xs) | Value::Tuple(xs)) => Ok(Value::Bytearray(
+            xs.iter().map(byte_value).collect::<Result<_, _>>()?,
+        )),
+        Some(other) => Err(ReplError::TypeError(format!(
+            "cannot convert '{}' object to bytearray",
+            other.type_name()
+        ))),
+    }
+}
+
+#[inline(never)]
+fn builtin_dict(args: Vec<Value>, kwargs: BTreeMap<String, Value>) -> Result<Value, ReplError> {
+    if args.len() > 1 {
+        return Err(ReplError::TypeError(
+            "dict() takes at most 1 positional argument".into(),
+        ));
+    }
+    let mut out = BTreeMap::new();
+    match args.into_iter().next() {
+        None => {}
+        Some(Value::Dict(m)) => out = m,
+        Some(pairs) => {
+            for pair in iter_to_vec(pairs)? {
+                let kv = match pair {
+                    Value::List(kv) | Value::Tuple(kv) if kv.len() == 2 => kv,
+                    other => {
+                        return Err(ReplError::TypeError(format!(
+                            "dict() items must be (key, value) pairs, got {}",
+                            other.type_name()
+                        )))
+                    }
+                };
+                let mut kv = kv.into_iter();
+                let (k, v) = (kv.next().unwrap_or(Value::None), kv.next());
+                let Value::Str(k) = k else {
+                    return Err(ReplError::TypeError(format!(
+                        "dict keys must be str in this subset, got {}",
+                        k.type_name()
+                    )));
+                };
+                out.insert(k, v.unwrap_or(Value::None));
+            }
+        }
+    }
+    // Keyword arguments win over positional entries, like Python.
+    out.extend(kwargs);
+    Ok(Value::Dict(out))
+}
+
+#[inline(never)]
+fn builtin_parse_csv(
+    args: Vec<Value>,
+    kwargs: BTreeMap<String, Value>,
+) -> Result<Value, ReplError> {
+    let mut delimiter = ',';
+    for (k, v) in &kwargs {
+        match (k.as_str(), v) {
+            ("delimiter", Value::Str(d)) if d.chars().count() == 1 => {
+                delimiter = d.chars().next().expect("one char");
+            }
+            ("delimiter", _) => {
+                return Err(ReplError::TypeError(
+                    "parse_csv() delimiter must be a 1-character string".into(),
+                ))
+            }
+            _ => return Err(ReplError::ForbiddenSyntax("keyword args".into())),
+        }
+    }
+    let [Value::Str(text)] = args.as_slice() else {
+        return Err(ReplError::TypeError(
+            "parse_csv(text, delimiter=',')".into(),
+        ));
+    };
+    let rows = parse_csv(text, delimiter)?;
+    Ok(Value::List(
+        rows.into_iter()
+            .map(|row| Value::List(row.into_iter().map(Value::Str).collect()))
+            .collect(),
+    ))
+}
+
+#[inline(never)]
+fn builtin_pluck(
+    args: Vec<Value>,
+    kwargs: BTreeMap<String, Value>,
+    env: &mut Env,
+) -> Result<Value, ReplError> {
+    if !kwargs.is_empty() {
+        return Err(ReplError::ForbiddenSyntax("keyword args".into()));
+    }
+    if args.len() != 2 {
+        return Err(ReplError::TypeError("pluck(list_of_dicts, key)".into()));
+    }
+    let Value::List(items) = &args[0] else {
+        return Err(ReplError::TypeError(format!(
+            "pluck() expects a list, got {}",
+            args[0].type_name()
+        )));
+    };
+    let mut out = Vec::with_capacity(items.len());
+    for item in items {
+        match item {
+            Value::Dict(m) => {
+                out.push(dict_lookup(m, &args[1], env.dict_int_index)?.unwrap_or(Value::None))
+            }
+            other => {
+                return Err(ReplError::TypeError(format!(
+                    "pluck() expects dict items, got {}",
+                    other.type_name()
+                )))
+            }
+        }
+    }
+    Ok(Value::List(out))
+}
+
+#[inline(never)]
+fn builtin_get_in(args: Vec<Value>, kwargs: BTreeMap<String, Value>) -> Result<Value, ReplError> {
+    let mut args = args.into_iter();
+    let (Some(obj), Some(path)) = (args.next(), args.next()) else {
+        return Err(ReplError::TypeError(
+            "get_in(obj, path, default=None)".into(),
+        ));
+    };
+    let mut default = args.next();
+    if args.next().is_some() {
+        return Err(ReplError::TypeError(
+            "get_in(obj, path, default=None)".into(),
+        ));
+    }
+    for (k, v) in kwargs {
+        match k.as_str() {
+            "default" if default.is_none() => default = Some(v),
+            _ => return Err(ReplError::ForbiddenSyntax("keyword args".into())),
+        }
+    }
+    let (Value::List(path) | Value::Tuple(path)) = path else {
+        return Err(ReplError::TypeError(format!(
+            "get_in() path must be a list of keys/indices, got {}",
+            path.type_name()
+        )));
+    };
+    Ok(get_in(obj, &path).unwrap_or(default.unwrap_or(Value::None)))
+}
+
+#[inline(never)]
+fn builtin_reversed(args: Vec<Value>, kwargs: BTreeMap<String, Value>) -> Result<Value, ReplError> {
+    if !kwargs.is_empty() {
+        return Err(ReplError::ForbiddenSyntax("keyword args".into()));
+    }
+    if args.len() != 1 {
+        return Err(ReplError::TypeError(
+            "reversed() takes exactly one argument".into(),
+        ));
+    }
+    // No lazy iterators here: return a fresh list.
+    let seq = args.into_iter().next().unwrap_or(Value::None);
+    match seq {
+        Value::Str(_) | Value::Bytes(_) | Value::List(_) | Value::Tuple(_) => {
+            let mut out = iter_to_vec(seq)?;
+            out.reverse();
+            Ok(Value::List(out))
+        }
+        other => Err(ReplError::TypeError(format!(
+            "reversed() expects str|bytes|list, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+fn parse_int_literal(s: &str, base: i64) -> Result<i64, ReplError> {
+    // Python's int(str, base): surrounding whitespace, an optional sign, a 0x/0o/0b prefix
+    // matching the base (base 0 infers it, defaulting to 10), and digits that may be grouped
+    // by single underscores (`1_000`), but not leading/trailing/doubled ones.
+    if base != 0 && !(2..=36).contains(&base) {
+        return Err(ReplError::ValueError(
+            "int() base must be >= 2 and <= 36, or 0".into(),
+        ));
+    }
+    let invalid = || {
+        ReplError::ValueError(format!(
+            "invalid literal for int() with base {base}: {}",
+            py_repr_str(s)
+        ))
+    };
+    let t = s.trim();
+    let (negative, digits) = match t.as_bytes().first() {
+        Some(b'-') => (true, &t[1..]),
+        Some(b'+') => (false, &t[1..]),
+        _ => (false, t),
+    };
+    if base == 10 && digits.contains(',') {
+        return Err(ReplError::ValueError(format!(
+            "invalid literal for int() with base 10: {} (remove ',' separators first, e.g. s.replace(',', ''))",
+            py_repr_str(s)
+        )));
+    }
+    let prefix = digits.get(..2).map(str::to_ascii_lowercase);
+    let (radix, digits) = match (base, prefix.as_deref()) {
+        (0 | 16, Some("0x")) => (16, &digits[2..]),
+        (0 | 8, Some("0o")) => (8, &digits[2..]),
+        (0 | 2, Some("0b")) => (2, &digits[2..]),
+        (0, _) => (10, digits),
+        (b, _) => (b as u32, digits),
+    };
+    if digits.is_empty()
+        || digits.starts_with('_')
+        || digits.ends_with('_')
+        || digits.contains("__")
+        || !digits.chars().all(|c| c.is_digit(radix) || c == '_')
+    {
+        return Err(invalid());
+    }
+    // Parse with the sign attached: negating afterwards can't produce i64::MIN.
+    let mut cleaned = String::with_capacity(digits.len() + 1);
+    if negative {
+        cleaned.push('-');
+    }
+    cleaned.extend(digits.chars().filter(|&c| c != '_'));
+    i64::from_str_radix(&cleaned, radix)
+        .map_err(|_| ReplError::ValueError("int out of range".into()))
+}
+
+/// Models often re-run the same ranking within one snippet, so folding and term lookups
+/// go through `index`, which lives for the whole execution.
+fn rank_documents_impl(
+    index: &mut TermIndex,
+    docs: &[Value],
+    query: &str,
+    top_k: i64,
+    boundary: Boundary,
+) -> Result<Vec<Value>, ReplError> {
+    let top_k = top_k.clamp(0, 20) as usize;
+    let terms = index.query_terms(query);
+
+    let mut scored: Vec<(usize, String, String)> = Vec::new(); // (score, doc_id, snippet)
+    for d in docs {
+        let Value::Dict(map) = d else {
+            continue;
+        };
+        let Some(Value::Str(doc_id)) = map.get("id").cloned() else {
+            continue;
+        };
+        let Some(Value::Str(text)) = map.get("text").cloned() else {
+            continue;
+        };
+        let (s, first) = index.match_terms(&text, &terms);
+        if s == 0 {
+            continue;
+        }
+        let snippet = match first {
+            Some((start, end)) => extract_window(&text, start, end - start, 80, boundary),
+            None => extract_window(&text, 0, 0, 80, boundary),
+        };
+        scored.push((s, doc_id, snippet));
+    }
+
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    let mut out = Vec::new();
+    for (_s, doc_id, snippet) in scored.into_iter().take(top_k) {
+        let mut m = std::collections::BTreeMap::new();
+        // Provide both keys to reduce LLM confusion:
+        // - documents use "id"
+        // - downstream schema uses "doc_id"
+        m.insert("id".to_string(), Value::Str(doc_id.clone()));
+        m.insert("doc_id".to_string(), Value::Str(doc_id));
+        m.insert("snippet".to_string(), Value::Str(snippet));
+        out.push(Value::Dict(m));
+    }
+    Ok(out)
+}
+
+fn extract_window(
+    text: &str,
+    start_byte: usize,
+    needle_len: usize,
+    window: usize,
+    boundary: Boundary,
+) -> String {
+    // Best-effort: treat start_byte as a byte offset into UTF-8, but clamp safely.
+    let window = window.max(1);
+    let start = start_byte.saturating_sub(window);
+    let end = (start_byte + needle_len + window).min(text.len());
+    // Snap to UTF-8 boundaries.
+    let start = snap_to_char_boundary(text, start);
+    let end = snap_to_char_boundary(text, end);
+    let (start, end) = snap_window(
+        text,
+        start,
+        end,
+        start_byte,
+        start_byte + needle_len,
+        boundary,
+    );
+    text[start..end].to_string()
+}
+
+fn snap_to_char_boundary(s: &str, mut i: usize) -> usize {
+    i = i.min(s.len());
+    while i > 0 && !s.is_char_boundary(i) {
         i -= 1;
     }
     i
@@ -1015,7 +2376,7 @@ fn snap_to_char_boundary(s: &str, mut i: usize) -> usize {
 fn call_callable(
     c: super::value::Callable,
     args: Vec<Value>,
-    kwargs: HashMap<String, Value>,
+    kwargs: BTreeMap<String, Value>,
     env: &mut Env,
     sink: &mut PrintSink,
 ) -> Result<Value, ReplError> {
@@ -1027,12 +2388,20 @@ fn call_callable(
         }
         Module { module, attr } => call_module_method(&module, &attr, args, kwargs, env),
         BytesDecode { bytes } => call_bytes_method(&bytes, "decode", args, kwargs),
+        BytesJoin { bytes } => call_bytes_method(&bytes, "join", args, kwargs),
         StrStrip { s } => call_str_method(&s, "strip", args, kwargs),
+        StrLStrip { s } => call_str_method(&s, "lstrip", args, kwargs),
+        StrRStrip { s } => call_str_method(&s, "rstrip", args, kwargs),
         StrLower { s } => call_str_method(&s, "lower", args, kwargs),
+        StrUpper { s } => call_str_method(&s, "upper", args, kwargs),
         StrFind { s } => call_str_method(&s, "find", args, kwargs),
         StrReplace { s } => call_str_method(&s, "replace", args, kwargs),
         StrSplit { s } => call_str_method(&s, "split", args, kwargs),
         StrStartsWith { s } => call_str_method(&s, "startswith", args, kwargs),
+        StrEndsWith { s } => call_str_method(&s, "endswith", args, kwargs),
+        StrCount { s } => call_str_method(&s, "count", args, kwargs),
+        StrFormat { s } => call_str_method(&s, "format", args, kwargs),
+        StrJoin { s } => call_str_method(&s, "join", args, kwargs),
         MatchGroup { m } => call_match_method(&m, "group", args, kwargs),
     }
 }
@@ -1053,10 +2422,18 @@ fn call_user_func(
     }
 
     env.push_locals();
-    for (name, val) in f.params.iter().zip(args.into_iter()) {
+    env.scopes.push(f.name.clone());
+    for (name, val) in f.params.iter().zip(args) {
         env.set(name, val);
     }
-    let res = match exec_suite(&f.body, env, sink)? {
+    // A call frame takes several times the stack of one expression level, so it is charged
+    // an extra level on top of its body.
+    env.depth += 1;
+    let flow = exec_suite(&f.body, env, sink);
+    env.depth -= 1;
+    env.scopes.pop();
+    env.pop_locals();
+    let res = match flow? {
         Flow::Continue => Value::None,
         Flow::Return(v) => v,
         Flow::Break | Flow::ContinueLoop => {
@@ -1065,58 +2442,69 @@ fn call_user_func(
             ))
         }
     };
-    env.pop_locals();
     Ok(res)
 }
 
 fn call_attr(
     a: &rustpython_parser::ast::ExprAttribute,
     args: Vec<Value>,
-    kwargs: HashMap<String, Value>,
+    kwargs: BTreeMap<String, Value>,
     env: &mut Env,
     sink: &mut PrintSink,
 ) -> Result<Value, ReplError> {
-    let recv = eval_expr(&a.value, env, sink)?;
     let attr = a.attr.as_str();
+    // `d.get(k)` on a bound name: the recommended single lookup, done without copying `d`.
+    if let (rustpython_parser::ast::Expr::Name(n), "get", 1 | 2, true) =
+        (a.value.as_ref(), attr, args.len(), kwargs.is_empty())
+    {
+        if let Some(Value::Dict(m)) = env.get_ref(n.id.as_str()) {
+            let found = dict_lookup(m, &args[0], env.dict_int_index)?;
+            return Ok(found.unwrap_or_else(|| args.get(1).cloned().unwrap_or(Value::None)));
+        }
+    }
+    let recv = eval_expr(&a.value, env, sink)?;
 
     match recv {
         Value::Module(m) => call_module_method(&m.name, attr, args, kwargs, env),
         Value::Str(s) => call_str_method(&s, attr, args, kwargs),
-        Value::Bytes(b) => call_bytes_method(&b, attr, args, kwargs),
+        Value::Bytes(b) | Value::Bytearray(b) => call_bytes_method(&b, attr, args, kwargs),
         Value::Match(m) => call_match_method(&m, attr, args, kwargs),
+        Value::List(xs) | Value::Tuple(xs) => call_list_method(&xs, attr, args, kwargs),
         Value::Dict(m) => {
-            if attr != "get" {
-                return Err(ReplError::TypeError(format!(
-                    "object has no attribute {}",
-                    attr
-                )));
-            }
             if !kwargs.is_empty() {
                 return Err(ReplError::ForbiddenSyntax("keyword args".into()));
             }
+            match attr {
+                "get" => {}
+                "keys" | "values" | "items" => {
+                    if !args.is_empty() {
+                        return Err(ReplError::TypeError(format!("{attr}() takes no args")));
+                    }
+                    // Views are materialized as lists (items as (key, value) tuples).
+                    let out = match attr {
+                        "keys" => m.into_keys().map(Value::Str).collect(),
+                        "values" => m.into_values().collect(),
+                        _ => m
+                            .into_iter()
+                            .map(|(k, v)| Value::Tuple(vec![Value::Str(k), v]))
+                            .collect(),
+                    };
+                    return Ok(Value::List(out));
+                }
+                _ => {
+                    return Err(ReplError::TypeError(format!(
+                        "object has no attribute {}",
+                        attr
+                    )))
+                }
+            }
             if args.len() != 1 && args.len() != 2 {
                 return Err(ReplError::TypeError("dict.get(key[, default])".into()));
             }
             let default = args.get(1).cloned().unwrap_or(Value::None);
-            match &args[0] {
-                Value::Str(k) => Ok(m.get(k).cloned().unwrap_or(default)),
-                Value::Int(i) => {
-                    if *i < 0 {
-                        return Ok(default);
-                    }
-                    let idx = *i as usize;
-                    let key = m.keys().nth(idx).cloned();
-                    match key {
-                        Some(k) => Ok(m.get(&k).cloned().unwrap_or(default)),
-                        None => Ok(default),
-                    }
-                }
-                other => Err(ReplError::TypeError(format!(
-                    "dict.get key must be str|int, got {}",
-                    other.type_name()
-                ))),
-            }
+            Ok(dict_lookup(&m, &args[0], env.dict_int_index)?.unwrap_or(default))
         }
+        Value::None => Err(none_has_no_attribute(attr)),
         _ => Err(ReplError::TypeError(format!(
             "object has no attribute {}",
             attr
@@ -1124,6 +2512,49 @@ fn call_attr(
     }
 }
 
+/// Shared key lookup for `d[key]`, `d.get(key)` and `pluck`.
+///
+/// Non-Python extension for LLM robustness: an int key selects the value at that position
+/// in sorted key order (negative positions never match). Disabled by `int_index == false`.
+fn dict_lookup(
+    m: &BTreeMap<String, Value>,
+    key: &Value,
+    int_index: bool,
+) -> Result<Option<Value>, ReplError> {
+    match key {
+        Value::Str(k) => Ok(m.get(k).cloned()),
+        Value::Int(i) if int_index => Ok(usize::try_from(*i)
+            .ok()
+            .and_then(|idx| m.values().nth(idx).cloned())),
+        other if int_index => Err(ReplError::TypeError(format!(
+            "dict key must be str|int, got {}",
+            other.type_name()
+        ))),
+        other => Err(ReplError::TypeError(format!(
+            "dict key must be str, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+/// Walks `path` through nested dicts (str keys) and lists/tuples (int indices, negative from
+/// the end); `None` as soon as any level is missing or can't be indexed by its key. An int
+/// step on a dict is a missing key, never the `dict_int_index` positional lookup.
+fn get_in(obj: Value, path: &[Value]) -> Option<Value> {
+    let mut cur = obj;
+    for key in path {
+        cur = match (cur, key) {
+            (Value::Dict(mut m), Value::Str(k)) => m.remove(k)?,
+            (Value::List(xs) | Value::Tuple(xs), Value::Int(i)) => {
+                let idx = normalize_index(*i, xs.len() as i64).ok()?;
+                xs.into_iter().nth(idx as usize)?
+            }
+            _ => return None,
+        };
+    }
+    Some(cur)
+}
+
 fn eval_attribute(
     a: &rustpython_parser::ast::ExprAttribute,
     env: &mut Env,
@@ -1164,19 +2595,43 @@ fn eval_attribute(
                 attr: "decompress".into(),
             })),
             ("zlib", "MAX_WBITS") => Ok(Value::Int(15)),
+            ("random", "randint" | "randrange" | "choice" | "seed") => {
+                Ok(Value::Callable(super::value::Callable::Module {
+                    module: "random".into(),
+                    attr: attr.into(),
+                }))
+            }
+            ("sys", "exit") => Ok(Value::Callable(super::value::Callable::Module {
+                module: "sys".into(),
+                attr: "exit".into(),
+            })),
             _ => Err(ReplError::ForbiddenSyntax("attribute value".into())),
         },
-        Value::Bytes(b) if attr == "decode" => {
+        Value::Bytes(b) | Value::Bytearray(b) if attr == "decode" => {
             Ok(Value::Callable(super::value::Callable::BytesDecode {
                 bytes: b,
             }))
         }
+        Value::Bytes(b) | Value::Bytearray(b) if attr == "join" => {
+            Ok(Value::Callable(super::value::Callable::BytesJoin {
+                bytes: b,
+            }))
+        }
         Value::Str(s) if attr == "strip" => {
             Ok(Value::Callable(super::value::Callable::StrStrip { s }))
         }
+        Value::Str(s) if attr == "lstrip" => {
+            Ok(Value::Callable(super::value::Callable::StrLStrip { s }))
+        }
+        Value::Str(s) if attr == "rstrip" => {
+            Ok(Value::Callable(super::value::Callable::StrRStrip { s }))
+        }
         Value::Str(s) if attr == "lower" => {
             Ok(Value::Callable(super::value::Callable::StrLower { s }))
         }
+        Value::Str(s) if attr == "upper" => {
+            Ok(Value::Callable(super::value::Callable::StrUpper { s }))
+        }
         Value::Str(s) if attr == "find" => {
             Ok(Value::Callable(super::value::Callable::StrFind { s }))
         }
@@ -1189,34 +2644,149 @@ fn eval_attribute(
         Value::Str(s) if attr == "startswith" => {
             Ok(Value::Callable(super::value::Callable::StrStartsWith { s }))
         }
+        Value::Str(s) if attr == "endswith" => {
+            Ok(Value::Callable(super::value::Callable::StrEndsWith { s }))
+        }
+        Value::Str(s) if attr == "count" => {
+            Ok(Value::Callable(super::value::Callable::StrCount { s }))
+        }
+        Value::Str(s) if attr == "format" => {
+            Ok(Value::Callable(super::value::Callable::StrFormat { s }))
+        }
+        Value::Str(s) if attr == "join" => {
+            Ok(Value::Callable(super::value::Callable::StrJoin { s }))
+        }
         Value::Match(m) if attr == "group" => {
             Ok(Value::Callable(super::value::Callable::MatchGroup { m }))
         }
+        Value::None => Err(none_has_no_attribute(attr)),
         _ => Err(ReplError::ForbiddenSyntax("attribute value".into())),
     }
 }
 
+/// Python's message for the common `re.search(...)` miss followed by `m.group(...)`.
+fn none_has_no_attribute(attr: &str) -> ReplError {
+    ReplError::AttributeError(format!("'NoneType' object has no attribute '{attr}'"))
+}
+
 fn call_module_method(
     module: &str,
     attr: &str,
     args: Vec<Value>,
-    kwargs: HashMap<String, Value>,
+    kwargs: BTreeMap<String, Value>,
+    env: &mut Env,
+) -> Result<Value, ReplError> {
+    match module {
+        "re" => call_re(
+            attr,
+            args,
+            kwargs,
+            env.regex_size_limit,
+            env.max_regex_matches,
+        ),
+        "json" => call_json(attr, args, kwargs, env.lenient_json),
+        "base64" => call_base64(attr, args, kwargs),
+        "binascii" => call_binascii(attr, args, kwargs),
+        "zlib" => call_zlib(attr, args, kwargs, env.max_zlib_output_bytes()),
+        "random" => call_random(attr, args, kwargs, env),
+        "sys" => call_sys(attr, args, kwargs),
+        _ => Err(ReplError::NameError(module.to_string())),
+    }
+}
+
+fn call_sys(
+    attr: &str,
+    args: Vec<Value>,
+    kwargs: BTreeMap<String, Value>,
+) -> Result<Value, ReplError> {
+    if !kwargs.is_empty() {
+        return Err(ReplError::ForbiddenSyntax("keyword args".into()));
+    }
+    match attr {
+        // `sys.exit()` / `sys.exit(None)` carry no value; anything else is reported as-is.
+        "exit" => match args.as_slice() {
+            [] | [Value::None] => Err(ReplError::SystemExit(None)),
+            [v] => Err(ReplError::SystemExit(Some(to_print_string(v)))),
+            _ => Err(ReplError::TypeError("sys.exit([code])".into())),
+        },
+        _ => Err(ReplError::TypeError(format!(
+            "object has no attribute {}",
+            attr
+        ))),
+    }
+}
+
+fn call_random(
+    attr: &str,
+    args: Vec<Value>,
+    kwargs: BTreeMap<String, Value>,
     env: &mut Env,
 ) -> Result<Value, ReplError> {
-    match module {
-        "re" => call_re(attr, args, kwargs),
-        "json" => call_json(attr, args, kwargs),
-        "base64" => call_base64(attr, args, kwargs),
-        "binascii" => call_binascii(attr, args, kwargs),
-        "zlib" => call_zlib(attr, args, kwargs, env.max_zlib_output_bytes()),
-        _ => Err(ReplError::NameError(module.to_string())),
+    if !kwargs.is_empty() {
+        return Err(ReplError::ForbiddenSyntax("keyword args".into()));
+    }
+    let int_arg = |v: &Value| match v {
+        Value::Int(i) => Ok(*i),
+        other => Err(ReplError::TypeError(format!(
+            "random.{attr}() expects int, got {}",
+            other.type_name()
+        ))),
+    };
+    match attr {
+        "seed" => {
+            if args.len() != 1 {
+                return Err(ReplError::TypeError("random.seed(n)".into()));
+            }
+            env.seed_random(int_arg(&args[0])? as u64);
+            env.rng_touched = true;
+            Ok(Value::None)
+        }
+        "randint" | "randrange" => {
+            // randint(a, b) includes b; randrange(stop) / randrange(start, stop) excludes stop.
+            // Bounds are i128 so `stop - 1` can't overflow at i64::MIN.
+            let (lo, hi) = match (attr, args.as_slice()) {
+                ("randint", [a, b]) => (int_arg(a)? as i128, int_arg(b)? as i128),
+                ("randrange", [stop]) => (0, int_arg(stop)? as i128 - 1),
+                ("randrange", [start, stop]) => {
+                    (int_arg(start)? as i128, int_arg(stop)? as i128 - 1)
+                }
+                _ => {
+                    return Err(ReplError::TypeError(format!(
+                        "random.{attr}() got wrong number of arguments"
+                    )))
+                }
+            };
+            if lo > hi {
+                return Err(ReplError::ValueError(format!(
+                    "empty range for random.{attr}()"
+                )));
+            }
+            let span = (hi - lo + 1) as u128;
+            let offset = (env.next_random_u64() as u128 % span) as i128;
+            Ok(Value::Int((lo + offset) as i64))
+        }
+        "choice" => {
+            if args.len() != 1 {
+                return Err(ReplError::TypeError("random.choice(seq)".into()));
+            }
+            let items = iter_to_vec(args.into_iter().next().unwrap_or(Value::None))?;
+            if items.is_empty() {
+                return Err(ReplError::ValueError(
+                    "cannot choose from an empty sequence".into(),
+                ));
+            }
+            let idx = (env.next_random_u64() % items.len() as u64) as usize;
+            Ok(items[idx].clone())
+        }
+        _ => Err(ReplError::NameError(format!("random.{}", attr))),
     }
 }
 
 fn call_json(
     attr: &str,
     args: Vec<Value>,
-    kwargs: HashMap<String, Value>,
+    kwargs: BTreeMap<String, Value>,
+    lenient: bool,
 ) -> Result<Value, ReplError> {
     if !kwargs.is_empty() {
         return Err(ReplError::ForbiddenSyntax("keyword args".into()));
@@ -1227,8 +2797,12 @@ fn call_json(
                 return Err(ReplError::TypeError("json.loads(s)".into()));
             }
             let s = args[0].as_str()?;
-            let v: serde_json::Value =
-                serde_json::from_str(s).map_err(|e| ReplError::ValueError(e.to_string()))?;
+            let v: serde_json::Value = match serde_json::from_str(s) {
+                Ok(v) => v,
+                Err(e) if lenient => serde_json::from_str(&normalize_lenient_json(s))
+                    .map_err(|_| ReplError::ValueError(e.to_string()))?,
+                Err(e) => return Err(ReplError::ValueError(e.to_string())),
+            };
             json_to_value(&v)
         }
         "dumps" => {
@@ -1244,16 +2818,81 @@ fn call_json(
     }
 }
 
+/// Rewrite almost-JSON into JSON: single-quoted strings become double-quoted and
+/// commas directly before `}` / `]` are dropped. Only used after strict parsing fails.
+fn normalize_lenient_json(s: &str) -> String {
+    let chars: Vec<char> = s.chars().collect();
+    let mut out = String::with_capacity(s.len());
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '"' => {
+                out.push('"');
+                i += 1;
+                while i < chars.len() {
+                    let c = chars[i];
+                    out.push(c);
+                    i += 1;
+                    if c == '\\' && i < chars.len() {
+                        out.push(chars[i]);
+                        i += 1;
+                    } else if c == '"' {
+                        break;
+                    }
+                }
+            }
+            '\'' => {
+                out.push('"');
+                i += 1;
+                while i < chars.len() {
+                    let c = chars[i];
+                    i += 1;
+                    match c {
+                        '\\' if i < chars.len() => {
+                            if chars[i] != '\'' {
+                                out.push('\\');
+                            }
+                            out.push(chars[i]);
+                            i += 1;
+                        }
+                        '"' => out.push_str("\\\""),
+                        '\'' => break,
+                        _ => out.push(c),
+                    }
+                }
+                out.push('"');
+            }
+            ',' => {
+                let next = chars[i + 1..].iter().find(|c| !c.is_whitespace());
+                if !matches!(next, Some('}') | Some(']')) {
+                    out.push(',');
+                }
+                i += 1;
+            }
+            c => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
 fn value_to_json(v: &Value) -> Result<serde_json::Value, ReplError> {
     Ok(match v {
         Value::None => serde_json::Value::Null,
         Value::Bool(b) => serde_json::Value::Bool(*b),
         Value::Int(i) => serde_json::Value::Number((*i).into()),
+        Value::Float(x) => serde_json::Number::from_f64(*x)
+            .map(serde_json::Value::Number)
+            .ok_or_else(|| {
+                ReplError::ValueError("Out of range float values are not JSON compliant".into())
+            })?,
         Value::Str(s) => serde_json::Value::String(s.clone()),
-        Value::Bytes(b) => {
+        Value::Bytes(b) | Value::Bytearray(b) => {
             serde_json::Value::String(base64::engine::general_purpose::STANDARD.encode(b))
         }
-        Value::List(xs) => {
+        Value::List(xs) | Value::Tuple(xs) => {
             serde_json::Value::Array(xs.iter().map(value_to_json).collect::<Result<_, _>>()?)
         }
         Value::Dict(m) => {
@@ -1274,8 +2913,9 @@ fn json_to_value(v: &serde_json::Value) -> Result<Value, ReplError> {
         serde_json::Value::Number(n) => {
             if let Some(i) = n.as_i64() {
                 Ok(Value::Int(i))
+            } else if let Some(x) = n.as_f64().filter(|_| n.is_f64()) {
+                Ok(Value::Float(x))
             } else {
-                // Keep the subset small: represent non-int numbers as strings.
                 Ok(Value::Str(n.to_string()))
             }
         }
@@ -1300,7 +2940,9 @@ fn json_to_value(v: &serde_json::Value) -> Result<Value, ReplError> {
 fn call_re(
     attr: &str,
     args: Vec<Value>,
-    kwargs: HashMap<String, Value>,
+    kwargs: BTreeMap<String, Value>,
+    size_limit: usize,
+    max_matches: usize,
 ) -> Result<Value, ReplError> {
     match attr {
         "search" => {
@@ -1316,7 +2958,7 @@ fn call_re(
                 None => 0,
                 _ => return Err(ReplError::TypeError("flags must be int".into())),
             };
-            let re = build_regex(&pat, flags)?;
+            let re = build_regex(&pat, flags, size_limit)?;
             if let Some(caps) = re.captures(&s) {
                 let m0 = caps
                     .get(0)
@@ -1353,10 +2995,30 @@ fn call_re(
                 None => 0,
                 _ => return Err(ReplError::TypeError("flags must be int".into())),
             };
-            let re = build_regex(&pat, flags)?;
+            let re = build_regex(&pat, flags, size_limit)?;
+            // Python returns the whole match with no groups, the group text with one group,
+            // and a tuple of groups with several.
+            let group_count = re.captures_len() - 1;
             let mut out = Vec::new();
-            for m in re.find_iter(&s) {
-                out.push(Value::Str(m.as_str().to_string()));
+            for caps in re.captures_iter(&s) {
+                if out.len() == max_matches {
+                    return Err(ReplError::ResourceLimitExceeded(format!(
+                        "re.findall matches exceed limit ({max_matches})"
+                    )));
+                }
+                let group_text = |i: usize| {
+                    Value::Str(
+                        caps.get(i)
+                            .map(|m| m.as_str().to_string())
+                            .unwrap_or_default(),
+                    )
+                };
+                let item = match group_count {
+                    0 => group_text(0),
+                    1 => group_text(1),
+                    n => Value::Tuple((1..=n).map(group_text).collect()),
+                };
+                out.push(item);
             }
             Ok(Value::List(out))
         }
@@ -1364,7 +3026,7 @@ fn call_re(
     }
 }
 
-fn build_regex(pat: &str, flags: i64) -> Result<regex::Regex, ReplError> {
+fn build_regex(pat: &str, flags: i64, size_limit: usize) -> Result<regex::Regex, ReplError> {
     // Minimal normalization for Python-ish patterns seen in transcripts.
     // Rust's `regex` does not support `\\Z`, so map it to `\\z` (end of text).
     let pat = pat.replace("\\Z", "\\z");
@@ -1375,7 +3037,13 @@ fn build_regex(pat: &str, flags: i64) -> Result<regex::Regex, ReplError> {
     if (flags & 16) != 0 {
         b.dot_matches_new_line(true);
     }
-    b.build().map_err(|e| ReplError::ValueError(e.to_string()))
+    b.size_limit(size_limit).dfa_size_limit(size_limit);
+    b.build().map_err(|e| match e {
+        regex::Error::CompiledTooBig(limit) => ReplError::ValueError(format!(
+            "regex pattern too complex: compiled size exceeds {limit} bytes"
+        )),
+        e => ReplError::ValueError(e.to_string()),
+    })
 }
 
 fn byte_to_char_idx(s: &str, byte_idx: usize) -> usize {
@@ -1395,7 +3063,7 @@ fn call_match_method(
     m: &super::value::MatchObject,
     attr: &str,
     args: Vec<Value>,
-    kwargs: HashMap<String, Value>,
+    kwargs: BTreeMap<String, Value>,
 ) -> Result<Value, ReplError> {
     if !kwargs.is_empty() {
         return Err(ReplError::ForbiddenSyntax("keyword args".into()));
@@ -1420,21 +3088,239 @@ fn call_match_method(
     }
 }
 
+/// `part * scale // whole` clamped to `0..=scale`, with a zero `whole` giving 0. Host helper
+/// so models can express fractions (percent, basis points) without floats.
+fn int_ratio(
+    name: &str,
+    args: &[Value],
+    kwargs: &BTreeMap<String, Value>,
+    scale: i64,
+) -> Result<Value, ReplError> {
+    if !kwargs.is_empty() {
+        return Err(ReplError::ForbiddenSyntax("keyword args".into()));
+    }
+    let (part, whole) = match args {
+        [Value::Int(part), Value::Int(whole)] => (*part as i128, *whole as i128),
+        [_, _] => return Err(ReplError::TypeError(format!("{name}() expects ints"))),
+        _ => return Err(ReplError::TypeError(format!("{name}(part, whole)"))),
+    };
+    if whole == 0 {
+        return Ok(Value::Int(0));
+    }
+    let out = (part * scale as i128 / whole).clamp(0, scale as i128);
+    Ok(Value::Int(out as i64))
+}
+
+/// Rows of delimited text: fields may be wrapped in double quotes to hold the delimiter or
+/// line breaks, with `""` for a literal quote. Line endings are `\n` or `\r\n`; a trailing
+/// line break doesn't add an empty row.
+fn parse_csv(text: &str, delimiter: char) -> Result<Vec<Vec<String>>, ReplError> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                c => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => in_quotes = true,
+            c if c == delimiter => row.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(ReplError::ValueError(
+            "parse_csv(): unterminated quoted field".into(),
+        ));
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// An int usable as a single byte (`bytearray` items), like Python's 0..=255 check.
+fn byte_value(v: &Value) -> Result<u8, ReplError> {
+    match v {
+        Value::Int(i) => u8::try_from(*i)
+            .map_err(|_| ReplError::ValueError("byte must be in range(0, 256)".into())),
+        other => Err(ReplError::TypeError(format!(
+            "'{}' object cannot be interpreted as an integer",
+            other.type_name()
+        ))),
+    }
+}
+
+/// Applies an in-place list method; returns what the call evaluates to (the removed item
+/// for `pop`, otherwise `None`).
+fn mutate_list(xs: &mut Vec<Value>, attr: &str, args: Vec<Value>) -> Result<Value, ReplError> {
+    let mut args = args.into_iter();
+    match attr {
+        "append" => xs.extend(args.next()),
+        "extend" => {
+            let items = iter_to_vec(args.next().unwrap_or(Value::None))?;
+            xs.extend(items);
+        }
+        "pop" => {
+            if xs.is_empty() {
+                return Err(ReplError::ValueError("pop from empty list".into()));
+            }
+            let idx = match args.next() {
+                None => xs.len() as i64 - 1,
+                Some(Value::Int(i)) => i,
+                Some(other) => {
+                    return Err(ReplError::TypeError(format!(
+                        "'{}' object cannot be interpreted as an integer",
+                        python_type_name(&other)
+                    )))
+                }
+            };
+            let idx = normalize_index(idx, xs.len() as i64)
+                .map_err(|_| ReplError::ValueError("pop index out of range".into()))?;
+            return Ok(xs.remove(idx as usize));
+        }
+        "remove" => {
+            let item = args.next().unwrap_or(Value::None);
+            let pos = xs.iter().position(|v| py_eq(v, &item)).ok_or_else(|| {
+                ReplError::ValueError(format!(
+                    "list.remove(x): {} not in list",
+                    py_repr_value(&item)
+                ))
+            })?;
+            xs.remove(pos);
+        }
+        "clear" => xs.clear(),
+        "reverse" => xs.reverse(),
+        _ => {
+            return Err(ReplError::TypeError(format!(
+                "object has no attribute {}",
+                attr
+            )))
+        }
+    }
+    Ok(Value::None)
+}
+
+fn call_list_method(
+    xs: &[Value],
+    attr: &str,
+    args: Vec<Value>,
+    kwargs: BTreeMap<String, Value>,
+) -> Result<Value, ReplError> {
+    if !kwargs.is_empty() {
+        return Err(ReplError::ForbiddenSyntax("keyword args".into()));
+    }
+    match attr {
+        "index" => {
+            if args.is_empty() || args.len() > 3 {
+                return Err(ReplError::TypeError("index(x[, start[, stop]])".into()));
+            }
+            let bound = |i: usize| -> Result<Option<i64>, ReplError> {
+                match args.get(i) {
+                    None | Some(Value::None) => Ok(None),
+                    Some(Value::Int(n)) => Ok(Some(*n)),
+                    Some(other) => Err(ReplError::TypeError(format!(
+                        "index() bounds must be int, got {}",
+                        other.type_name()
+                    ))),
+                }
+            };
+            let (start, stop) = normalize_slice(bound(1)?, bound(2)?, xs.len() as i64);
+            xs[start..stop]
+                .iter()
+                .position(|v| py_eq(v, &args[0]))
+                .map(|i| Value::Int((start + i) as i64))
+                .ok_or_else(|| {
+                    ReplError::ValueError(format!("{} is not in list", py_repr_value(&args[0])))
+                })
+        }
+        "count" => {
+            if args.len() != 1 {
+                return Err(ReplError::TypeError("count(x)".into()));
+            }
+            Ok(Value::Int(
+                xs.iter().filter(|v| py_eq(v, &args[0])).count() as i64
+            ))
+        }
+        _ => Err(ReplError::TypeError(format!(
+            "object has no attribute {}",
+            attr
+        ))),
+    }
+}
+
+/// `s.split(None, maxsplit)`: runs of whitespace separate fields, leading whitespace is
+/// dropped, and after `maxsplit` splits the rest is kept whole (trailing whitespace included).
+fn split_whitespace_n(s: &str, maxsplit: Option<usize>) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut rest = s.trim_start();
+    while !rest.is_empty() {
+        if maxsplit.is_some_and(|n| parts.len() == n) {
+            parts.push(rest.to_string());
+            break;
+        }
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        parts.push(rest[..end].to_string());
+        rest = rest[end..].trim_start();
+    }
+    parts
+}
+
 fn call_str_method(
     s: &str,
     attr: &str,
     args: Vec<Value>,
-    kwargs: HashMap<String, Value>,
+    kwargs: BTreeMap<String, Value>,
 ) -> Result<Value, ReplError> {
+    // Keyword arguments are field values for `format`; no other method takes any.
+    if attr == "format" {
+        return Ok(Value::Str(super::format_spec::format_template(
+            s, &args, &kwargs,
+        )?));
+    }
     if !kwargs.is_empty() {
         return Err(ReplError::ForbiddenSyntax("keyword args".into()));
     }
     match attr {
-        "strip" => {
-            if !args.is_empty() {
-                return Err(ReplError::TypeError("strip() takes no args".into()));
-            }
-            Ok(Value::Str(s.trim().to_string()))
+        "strip" | "lstrip" | "rstrip" => {
+            // No argument (or None) trims whitespace; a string trims any of its chars.
+            let chars: Option<Vec<char>> = match args.as_slice() {
+                [] | [Value::None] => None,
+                [Value::Str(cs)] => Some(cs.chars().collect()),
+                [other] => {
+                    return Err(ReplError::TypeError(format!(
+                        "{attr} arg must be None or str, not {}",
+                        python_type_name(other)
+                    )))
+                }
+                _ => return Err(ReplError::TypeError(format!("{attr}([chars])"))),
+            };
+            let strip = |c: char| match &chars {
+                Some(cs) => cs.contains(&c),
+                None => c.is_whitespace(),
+            };
+            let out = match attr {
+                "lstrip" => s.trim_start_matches(strip),
+                "rstrip" => s.trim_end_matches(strip),
+                _ => s.trim_matches(strip),
+            };
+            Ok(Value::Str(out.to_string()))
         }
         "lower" => {
             if !args.is_empty() {
@@ -1442,12 +3328,81 @@ fn call_str_method(
             }
             Ok(Value::Str(s.to_lowercase()))
         }
+        "upper" => {
+            if !args.is_empty() {
+                return Err(ReplError::TypeError("upper() takes no args".into()));
+            }
+            Ok(Value::Str(s.to_uppercase()))
+        }
+        "casefold" => {
+            if !args.is_empty() {
+                return Err(ReplError::TypeError("casefold() takes no args".into()));
+            }
+            Ok(Value::Str(casefold(s)))
+        }
+        "encode" => {
+            let enc = match args.as_slice() {
+                [] => "utf-8",
+                [v] => v.as_str()?,
+                _ => return Err(ReplError::TypeError("encode([encoding])".into())),
+            };
+            if !matches!(enc, "utf-8" | "utf8") {
+                return Err(ReplError::ValueError(format!(
+                    "unsupported encoding: {enc}"
+                )));
+            }
+            Ok(Value::Bytes(s.as_bytes().to_vec()))
+        }
         "find" => {
             if args.len() != 1 {
                 return Err(ReplError::TypeError("find(sub)".into()));
             }
             let sub = args[0].as_str()?;
-            Ok(Value::Int(s.find(sub).map(|i| i as i64).unwrap_or(-1)))
+            // Codepoint index, so the result can be fed straight back into `s[i:]`.
+            Ok(Value::Int(
+                s.find(sub)
+                    .map(|i| byte_to_char_idx(s, i) as i64)
+                    .unwrap_or(-1),
+            ))
+        }
+        "ljust" | "rjust" | "center" => {
+            if args.is_empty() || args.len() > 2 {
+                return Err(ReplError::TypeError(format!("{attr}(width[, fillchar])")));
+            }
+            let Value::Int(width) = args[0] else {
+                return Err(ReplError::TypeError(format!("{attr}() width must be int")));
+            };
+            let fill = match args.get(1) {
+                None => ' ',
+                Some(v) => {
+                    let f = v.as_str()?;
+                    let mut chars = f.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) => c,
+                        _ => {
+                            return Err(ReplError::TypeError(
+                                "fill character must be exactly one character long".into(),
+                            ))
+                        }
+                    }
+                }
+            };
+            let pad = repeat_count(1, width.saturating_sub(s.chars().count() as i64))?;
+            // CPython's center() gives the odd extra fill char to the left only when
+            // both the padding and the width are odd: 'ab'.center(5) == '  ab '.
+            let (left, right) = match attr {
+                "ljust" => (0, pad),
+                "rjust" => (pad, 0),
+                _ => {
+                    let left = pad / 2 + (pad & width as usize & 1);
+                    (left, pad - left)
+                }
+            };
+            let mut out = String::with_capacity(s.len() + pad);
+            out.extend(std::iter::repeat_n(fill, left));
+            out.push_str(s);
+            out.extend(std::iter::repeat_n(fill, right));
+            Ok(Value::Str(out))
         }
         "replace" => {
             if args.len() != 2 {
@@ -1458,14 +3413,32 @@ fn call_str_method(
             Ok(Value::Str(s.replace(old, new)))
         }
         "split" => {
-            if args.len() > 1 {
-                return Err(ReplError::TypeError("split([sep])".into()));
+            if args.len() > 2 {
+                return Err(ReplError::TypeError("split([sep[, maxsplit]])".into()));
             }
-            let parts: Vec<String> = if args.is_empty() {
-                s.split_whitespace().map(|x| x.to_string()).collect()
-            } else {
-                let sep = args[0].as_str()?;
-                s.split(sep).map(|x| x.to_string()).collect()
+            // A negative maxsplit (Python's default -1) means no limit.
+            let maxsplit = match args.get(1) {
+                None => None,
+                Some(Value::Int(n)) => usize::try_from(*n).ok(),
+                Some(other) => {
+                    return Err(ReplError::TypeError(format!(
+                        "'{}' object cannot be interpreted as an integer",
+                        python_type_name(other)
+                    )))
+                }
+            };
+            let parts: Vec<String> = match args.first() {
+                None | Some(Value::None) => split_whitespace_n(s, maxsplit),
+                Some(sep) => {
+                    let sep = sep.as_str()?;
+                    if sep.is_empty() {
+                        return Err(ReplError::ValueError("empty separator".into()));
+                    }
+                    match maxsplit {
+                        Some(n) => s.splitn(n + 1, sep).map(|x| x.to_string()).collect(),
+                        None => s.split(sep).map(|x| x.to_string()).collect(),
+                    }
+                }
             };
             Ok(Value::List(parts.into_iter().map(Value::Str).collect()))
         }
@@ -1476,6 +3449,40 @@ fn call_str_method(
             let p = args[0].as_str()?;
             Ok(Value::Bool(s.starts_with(p)))
         }
+        "endswith" => {
+            if args.len() != 1 {
+                return Err(ReplError::TypeError("endswith(suffix)".into()));
+            }
+            let p = args[0].as_str()?;
+            Ok(Value::Bool(s.ends_with(p)))
+        }
+        "count" => {
+            if args.len() != 1 {
+                return Err(ReplError::TypeError("count(sub)".into()));
+            }
+            // Non-overlapping; an empty `sub` matches at every char boundary (len + 1).
+            let sub = args[0].as_str()?;
+            Ok(Value::Int(s.matches(sub).count() as i64))
+        }
+        "join" => {
+            if args.len() != 1 {
+                return Err(ReplError::TypeError("join(iterable)".into()));
+            }
+            let items = iter_to_vec(args.into_iter().next().expect("one arg"))?;
+            let mut parts = Vec::with_capacity(items.len());
+            for (i, item) in items.into_iter().enumerate() {
+                match item {
+                    Value::Str(x) => parts.push(x),
+                    other => {
+                        return Err(ReplError::TypeError(format!(
+                            "sequence item {i}: expected str instance, {} found",
+                            python_type_name(&other)
+                        )))
+                    }
+                }
+            }
+            Ok(Value::Str(parts.join(s)))
+        }
         _ => Err(ReplError::NameError(format!("str.{}", attr))),
     }
 }
@@ -1484,7 +3491,7 @@ fn call_bytes_method(
     b: &[u8],
     attr: &str,
     args: Vec<Value>,
-    kwargs: HashMap<String, Value>,
+    kwargs: BTreeMap<String, Value>,
 ) -> Result<Value, ReplError> {
     match attr {
         "decode" => {
@@ -1538,6 +3545,28 @@ fn call_bytes_method(
                 _ => Err(ReplError::ValueError("unsupported encoding".into())),
             }
         }
+        "join" => {
+            if args.len() != 1 || !kwargs.is_empty() {
+                return Err(ReplError::TypeError("join(iterable)".into()));
+            }
+            let items = iter_to_vec(args.into_iter().next().expect("one arg"))?;
+            let mut out = Vec::new();
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.extend_from_slice(b);
+                }
+                match item {
+                    Value::Bytes(x) | Value::Bytearray(x) => out.extend_from_slice(x),
+                    other => {
+                        return Err(ReplError::TypeError(format!(
+                            "sequence item {i}: expected a bytes-like object, {} found",
+                            other.type_name()
+                        )))
+                    }
+                }
+            }
+            Ok(Value::Bytes(out))
+        }
         _ => Err(ReplError::NameError(format!("bytes.{}", attr))),
     }
 }
@@ -1545,7 +3574,7 @@ fn call_bytes_method(
 fn call_base64(
     attr: &str,
     args: Vec<Value>,
-    kwargs: HashMap<String, Value>,
+    kwargs: BTreeMap<String, Value>,
 ) -> Result<Value, ReplError> {
     if !kwargs.is_empty() {
         return Err(ReplError::ForbiddenSyntax("keyword args".into()));
@@ -1582,7 +3611,7 @@ fn call_base64(
 fn call_binascii(
     attr: &str,
     args: Vec<Value>,
-    kwargs: HashMap<String, Value>,
+    kwargs: BTreeMap<String, Value>,
 ) -> Result<Value, ReplError> {
     if !kwargs.is_empty() {
         return Err(ReplError::ForbiddenSyntax("keyword args".into()));
@@ -1615,7 +3644,7 @@ fn nibble_to_hex(n: u8) -> u8 {
 fn call_zlib(
     attr: &str,
     args: Vec<Value>,
-    kwargs: HashMap<String, Value>,
+    kwargs: BTreeMap<String, Value>,
     max_output: usize,
 ) -> Result<Value, ReplError> {
     if !kwargs.is_empty() {
@@ -1729,6 +3758,30 @@ fn py_repr_str(s: &str) -> String {
     out
 }
 
+/// Python's `repr(float)`: shortest round-trip digits, always with a `.0` or exponent, and
+/// scientific notation outside `1e-4 <= |x| < 1e16`.
+pub(super) fn py_repr_float(x: f64) -> String {
+    if x.is_nan() {
+        return "nan".into();
+    }
+    if x.is_infinite() {
+        return if x > 0.0 { "inf" } else { "-inf" }.into();
+    }
+    let sci = format!("{x:e}");
+    let (mantissa, exp) = sci.split_once('e').expect("`{:e}` has an exponent");
+    let exp: i32 = exp.parse().expect("`{:e}` exponent is an integer");
+    if x == 0.0 || (-4..16).contains(&exp) {
+        let plain = x.to_string();
+        return if plain.contains('.') {
+            plain
+        } else {
+            plain + ".0"
+        };
+    }
+    let sign = if exp < 0 { '-' } else { '+' };
+    format!("{mantissa}e{sign}{:02}", exp.abs())
+}
+
 fn py_repr_bytes(b: &[u8]) -> String {
     // Python's `str(bytes)` is the same as `repr(bytes)`: b'...'
     let mut out = String::new();
@@ -1749,9 +3802,10 @@ fn py_repr_bytes(b: &[u8]) -> String {
     out
 }
 
-fn py_repr_value(v: &Value) -> String {
+pub(super) fn py_repr_value(v: &Value) -> String {
     match v {
         Value::None => "None".into(),
+        Value::Ellipsis => "Ellipsis".into(),
         Value::Bool(b) => {
             if *b {
                 "True".into()
@@ -1760,8 +3814,10 @@ fn py_repr_value(v: &Value) -> String {
             }
         }
         Value::Int(i) => i.to_string(),
+        Value::Float(x) => py_repr_float(*x),
         Value::Str(s) => py_repr_str(s),
         Value::Bytes(b) => py_repr_bytes(b),
+        Value::Bytearray(b) => format!("bytearray({})", py_repr_bytes(b)),
         Value::List(xs) => {
             let mut out = String::from("[");
             for (i, x) in xs.iter().enumerate() {
@@ -1773,6 +3829,13 @@ fn py_repr_value(v: &Value) -> String {
             out.push(']');
             out
         }
+        Value::Tuple(xs) => {
+            let parts: Vec<String> = xs.iter().map(py_repr_value).collect();
+            match parts.as_slice() {
+                [one] => format!("({one},)"),
+                _ => format!("({})", parts.join(", ")),
+            }
+        }
         Value::Dict(m) => {
             let mut out = String::from("{");
             for (i, (k, v)) in m.iter().enumerate() {
@@ -1786,6 +3849,11 @@ fn py_repr_value(v: &Value) -> String {
             out.push('}');
             out
         }
+        Value::Set(items) if items.is_empty() => "set()".into(),
+        Value::Set(items) => {
+            let parts: Vec<String> = items.iter().map(|x| py_repr_value(&x.to_value())).collect();
+            format!("{{{}}}", parts.join(", "))
+        }
         Value::Match(m) => {
             let matched = m.groups.first().map(|s| s.as_str()).unwrap_or("");
             format!(
@@ -1801,7 +3869,7 @@ fn py_repr_value(v: &Value) -> String {
     }
 }
 
-fn to_print_string(v: &Value) -> String {
+pub(super) fn to_print_string(v: &Value) -> String {
     // Python's `print(x)` uses `str(x)`. For str values that means the raw contents
     // (no quotes), while containers/bytes show a repr-like form.
     match v {
@@ -1811,9 +3879,9 @@ fn to_print_string(v: &Value) -> String {
 }
 
 fn format_percent(fmt: &str, arg: Value) -> Result<Value, ReplError> {
+    // Like Python, only a tuple spreads into several arguments; a list is one `%s` value.
     let args: Vec<Value> = match arg {
-        // Tuple literals are represented as Value::List in this subset.
-        Value::List(xs) => xs,
+        Value::Tuple(xs) => xs,
         other => vec![other],
     };
 
@@ -1858,63 +3926,116 @@ fn eval_subscript(
     env: &mut Env,
     sink: &mut PrintSink,
 ) -> Result<Value, ReplError> {
+    if let (rustpython_parser::ast::Expr::Name(n), false) = (
+        e.value.as_ref(),
+        matches!(e.slice.as_ref(), rustpython_parser::ast::Expr::Slice(_)),
+    ) {
+        let idx_v = eval_expr(&e.slice, env, sink)?;
+        let v = env
+            .get_ref(n.id.as_str())
+            .ok_or_else(|| ReplError::NameError(n.id.to_string()))?;
+        return index_value(v, &idx_v, env.dict_int_index);
+    }
     let v = eval_expr(&e.value, env, sink)?;
     match e.slice.as_ref() {
         rustpython_parser::ast::Expr::Slice(slice_expr) => apply_slice(v, slice_expr, env, sink),
         _ => {
             let idx_v = eval_expr(&e.slice, env, sink)?;
-            match v {
-                Value::Dict(m) => {
-                    match idx_v {
-                        Value::Str(s) => Ok(m.get(&s).cloned().unwrap_or(Value::None)),
-                        // Non-Python extension for LLM robustness:
-                        // allow integer indexing into dict values using sorted key order.
-                        Value::Int(i) => {
-                            if i < 0 {
-                                return Err(ReplError::ValueError("index out of range".into()));
-                            }
-                            let idx = i as usize;
-                            let key = m.keys().nth(idx).cloned();
-                            match key {
-                                Some(k) => Ok(m.get(&k).cloned().unwrap_or(Value::None)),
-                                None => Err(ReplError::ValueError("index out of range".into())),
-                            }
-                        }
-                        _ => Err(ReplError::TypeError("dict index must be str".into())),
-                    }
-                }
-                Value::Str(st) => {
-                    let idx = match idx_v {
-                        Value::Int(i) => i,
-                        _ => return Err(ReplError::TypeError("index must be int".into())),
-                    };
-                    let idx = normalize_index(idx, st.chars().count() as i64)?;
-                    let ch = st
-                        .chars()
-                        .nth(idx as usize)
-                        .ok_or_else(|| ReplError::ValueError("index out of range".into()))?;
-                    Ok(Value::Str(ch.to_string()))
-                }
-                Value::Bytes(b) => {
-                    let idx = match idx_v {
-                        Value::Int(i) => i,
-                        _ => return Err(ReplError::TypeError("index must be int".into())),
-                    };
-                    let idx = normalize_index(idx, b.len() as i64)?;
-                    Ok(Value::Int(b[idx as usize] as i64))
-                }
-                Value::List(xs) => {
-                    let idx = match idx_v {
-                        Value::Int(i) => i,
-                        _ => return Err(ReplError::TypeError("index must be int".into())),
-                    };
-                    let idx = normalize_index(idx, xs.len() as i64)?;
-                    Ok(xs[idx as usize].clone())
-                }
-                _ => Err(ReplError::TypeError("unsupported subscript".into())),
-            }
+            index_value(&v, &idx_v, env.dict_int_index)
+        }
+    }
+}
+
+/// `v[idx]` for a non-slice index.
+fn index_value(v: &Value, idx_v: &Value, dict_int_index: bool) -> Result<Value, ReplError> {
+    let int_index = || match idx_v {
+        Value::Int(i) => Ok(*i),
+        _ => Err(ReplError::TypeError("index must be int".into())),
+    };
+    match v {
+        Value::Dict(m) => match (dict_lookup(m, idx_v, dict_int_index)?, idx_v) {
+            (Some(v), _) => Ok(v),
+            (None, Value::Int(_)) => Err(ReplError::ValueError("index out of range".into())),
+            // Missing str keys read as None instead of raising KeyError.
+            (None, _) => Ok(Value::None),
+        },
+        Value::Str(st) => {
+            let idx = normalize_index(int_index()?, st.chars().count() as i64)?;
+            let ch = st
+                .chars()
+                .nth(idx as usize)
+                .ok_or_else(|| ReplError::ValueError("index out of range".into()))?;
+            Ok(Value::Str(ch.to_string()))
         }
+        Value::Bytes(b) | Value::Bytearray(b) => {
+            let idx = normalize_index(int_index()?, b.len() as i64)?;
+            Ok(Value::Int(b[idx as usize] as i64))
+        }
+        Value::List(xs) | Value::Tuple(xs) => {
+            let idx = normalize_index(int_index()?, xs.len() as i64)?;
+            Ok(xs[idx as usize].clone())
+        }
+        _ => Err(ReplError::TypeError("unsupported subscript".into())),
+    }
+}
+
+/// `name[i] = v` on a name-bound bytearray or list; the updated value is rebound to `name`.
+fn assign_item(
+    e: &rustpython_parser::ast::ExprSubscript,
+    v: Value,
+    env: &mut Env,
+    sink: &mut PrintSink,
+) -> Result<(), ReplError> {
+    let rustpython_parser::ast::Expr::Name(n) = e.value.as_ref() else {
+        return Err(ReplError::ForbiddenSyntax("assign target".into()));
+    };
+    if matches!(e.slice.as_ref(), rustpython_parser::ast::Expr::Slice(_)) {
+        return Err(ReplError::ForbiddenSyntax("slice assignment".into()));
+    }
+    let cur = env
+        .get(n.id.as_str())
+        .ok_or_else(|| ReplError::NameError(n.id.to_string()))?;
+    // Check the target before the key, so `t[k] = v` on a str or tuple reports the
+    // unsupported assignment rather than a complaint about `k`.
+    let type_name = cur.type_name();
+    if !matches!(cur, Value::Bytearray(_) | Value::List(_) | Value::Dict(_)) {
+        return Err(ReplError::TypeError(format!(
+            "'{type_name}' object does not support item assignment"
+        )));
     }
+    let key = eval_expr(&e.slice, env, sink)?;
+    let index = |len: usize| match &key {
+        Value::Int(i) => Ok(normalize_index(*i, len as i64)? as usize),
+        other => Err(ReplError::TypeError(format!(
+            "{type_name} indices must be integers, not {}",
+            python_type_name(other)
+        ))),
+    };
+    let updated = match cur {
+        Value::Bytearray(mut b) => {
+            let i = index(b.len())?;
+            b[i] = byte_value(&v)?;
+            Value::Bytearray(b)
+        }
+        Value::List(mut xs) => {
+            let i = index(xs.len())?;
+            xs[i] = v;
+            Value::List(xs)
+        }
+        Value::Dict(mut m) => {
+            let Value::Str(k) = key else {
+                return Err(ReplError::TypeError(format!(
+                    "dict keys must be str in this subset, got {}",
+                    key.type_name()
+                )));
+            };
+            m.insert(k, v);
+            Value::Dict(m)
+        }
+        _ => unreachable!("checked above"),
+    };
+    env.set(n.id.as_str(), updated);
+    Ok(())
 }
 
 fn eval_slice_expr(
@@ -1964,10 +4085,18 @@ fn apply_slice(
             let (a, b) = normalize_slice(start, stop, bs.len() as i64);
             Ok(Value::Bytes(bs[a..b].to_vec()))
         }
+        Value::Bytearray(bs) => {
+            let (a, b) = normalize_slice(start, stop, bs.len() as i64);
+            Ok(Value::Bytearray(bs[a..b].to_vec()))
+        }
         Value::List(xs) => {
             let (a, b) = normalize_slice(start, stop, xs.len() as i64);
             Ok(Value::List(xs[a..b].to_vec()))
         }
+        Value::Tuple(xs) => {
+            let (a, b) = normalize_slice(start, stop, xs.len() as i64);
+            Ok(Value::Tuple(xs[a..b].to_vec()))
+        }
         _ => Err(ReplError::TypeError(
             "slicing supported only on str/bytes/list".into(),
         )),
@@ -0,0 +1,427 @@
+use crate::error::ReplError;
+
+use std::collections::BTreeMap;
+
+use super::eval::{py_repr_float, py_repr_value, to_print_string};
+use super::value::Value;
+
+/// Python's format-spec mini-language:
+/// `[[fill]align][sign][#][0][width][grouping][.precision][type]`.
+///
+/// Lives outside `eval` so every single-value formatter (`format()`, `str.format` and
+/// f-strings) parses specs the same way.
+#[derive(Debug, Default)]
+struct FormatSpec {
+    fill: Option<char>,
+    align: Option<char>,
+    sign: Option<char>,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    grouping: Option<char>,
+    precision: Option<usize>,
+    kind: Option<char>,
+}
+
+fn parse_spec(spec: &str) -> Result<FormatSpec, ReplError> {
+    let bad = || ReplError::ValueError(format!("invalid format specifier '{spec}'"));
+    let chars: Vec<char> = spec.chars().collect();
+    let mut out = FormatSpec::default();
+    let mut i = 0;
+
+    let is_align = |c: char| matches!(c, '<' | '>' | '^' | '=');
+    if chars.len() >= 2 && is_align(chars[1]) {
+        out.fill = Some(chars[0]);
+        out.align = Some(chars[1]);
+        i = 2;
+    } else if !chars.is_empty() && is_align(chars[0]) {
+        out.align = Some(chars[0]);
+        i = 1;
+    }
+    if let Some(&c) = chars.get(i).filter(|c| matches!(c, '+' | '-' | ' ')) {
+        out.sign = Some(c);
+        i += 1;
+    }
+    if chars.get(i) == Some(&'#') {
+        out.alternate = true;
+        i += 1;
+    }
+    if chars.get(i) == Some(&'0') {
+        out.zero = true;
+        i += 1;
+    }
+    let digits_from = |i: &mut usize| {
+        let start = *i;
+        while chars.get(*i).is_some_and(|c| c.is_ascii_digit()) {
+            *i += 1;
+        }
+        chars[start..*i].iter().collect::<String>()
+    };
+    let width = digits_from(&mut i);
+    if !width.is_empty() {
+        out.width = width.parse().map_err(|_| bad())?;
+        // Hard cap to keep resource bounded.
+        if out.width > 1_000_000 {
+            return Err(ReplError::ResourceLimitExceeded(
+                "format width exceeds max length".into(),
+            ));
+        }
+    }
+    if let Some(&c) = chars.get(i).filter(|c| matches!(c, ',' | '_')) {
+        out.grouping = Some(c);
+        i += 1;
+    }
+    if chars.get(i) == Some(&'.') {
+        i += 1;
+        let precision = digits_from(&mut i);
+        if precision.is_empty() {
+            return Err(bad());
+        }
+        let precision: usize = precision.parse().map_err(|_| bad())?;
+        if precision > 1_000 {
+            return Err(ReplError::ResourceLimitExceeded(
+                "format precision exceeds max".into(),
+            ));
+        }
+        out.precision = Some(precision);
+    }
+    if let Some(&c) = chars.get(i) {
+        out.kind = Some(c);
+        i += 1;
+    }
+    if i != chars.len() {
+        return Err(bad());
+    }
+    Ok(out)
+}
+
+/// Format one value like Python's `format(value, spec)`.
+pub fn format_value(v: &Value, spec: &str) -> Result<String, ReplError> {
+    if spec.is_empty() {
+        return Ok(to_print_string(v));
+    }
+    let spec = parse_spec(spec)?;
+    match v {
+        Value::Int(i) => format_int(*i, &spec),
+        Value::Float(x) => format_float(*x, &spec),
+        Value::Bool(b) if spec.kind.is_some_and(|k| k != 's') => format_int(*b as i64, &spec),
+        Value::Str(s) => format_str(s, &spec),
+        other if spec.kind.is_none() || spec.kind == Some('s') => {
+            format_str(&to_print_string(other), &spec)
+        }
+        other => Err(ReplError::TypeError(format!(
+            "unsupported format string passed to {}.__format__",
+            other.type_name()
+        ))),
+    }
+}
+
+/// `template.format(*args, **kwargs)`: `{}` (numbered automatically), `{0}`, `{name}`, each
+/// with an optional `!s`/`!r` conversion and `:spec`; `{{` and `}}` are literal braces.
+pub fn format_template(
+    template: &str,
+    args: &[Value],
+    kwargs: &BTreeMap<String, Value>,
+) -> Result<String, ReplError> {
+    let mut out = String::new();
+    // `Some(n)` while fields are numbered automatically, `None` once one was explicit.
+    let mut auto_index: Option<Option<usize>> = None;
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '}' => {
+                return Err(ReplError::ValueError(
+                    "Single '}' encountered in format string".into(),
+                ))
+            }
+            '{' => {
+                let mut field = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some('{') | None => {
+                            return Err(ReplError::ValueError(
+                                "Single '{' encountered in format string".into(),
+                            ))
+                        }
+                        Some(ch) => field.push(ch),
+                    }
+                }
+                let (field, spec) = field.split_once(':').unwrap_or((&field, ""));
+                let (name, conversion) = match field.split_once('!') {
+                    Some((name, conv)) => (name, Some(conv)),
+                    None => (field, None),
+                };
+                let value = if name.is_empty() {
+                    let next = match auto_index {
+                        None => 0,
+                        Some(Some(n)) => n,
+                        Some(None) => return Err(switch_numbering_error()),
+                    };
+                    auto_index = Some(Some(next + 1));
+                    positional_field(args, next)?
+                } else if let Ok(index) = name.parse::<usize>() {
+                    if matches!(auto_index, Some(Some(_))) {
+                        return Err(switch_numbering_error());
+                    }
+                    auto_index = Some(None);
+                    positional_field(args, index)?
+                } else {
+                    kwargs.get(name).ok_or_else(|| {
+                        ReplError::ValueError(format!("missing format field '{name}'"))
+                    })?
+                };
+                let converted;
+                let value = match conversion {
+                    None => value,
+                    Some("s") => {
+                        converted = Value::Str(to_print_string(value));
+                        &converted
+                    }
+                    Some("r" | "a") => {
+                        converted = Value::Str(py_repr_value(value));
+                        &converted
+                    }
+                    Some(other) => {
+                        return Err(ReplError::ValueError(format!(
+                            "Unknown conversion specifier {other}"
+                        )))
+                    }
+                };
+                out.push_str(&format_value(value, spec)?);
+            }
+            c => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+fn positional_field(args: &[Value], index: usize) -> Result<&Value, ReplError> {
+    args.get(index).ok_or_else(|| {
+        ReplError::ValueError(format!(
+            "Replacement index {index} out of range for positional args tuple"
+        ))
+    })
+}
+
+fn switch_numbering_error() -> ReplError {
+    ReplError::ValueError(
+        "cannot switch between automatic field numbering and manual field specification".into(),
+    )
+}
+
+fn format_str(s: &str, spec: &FormatSpec) -> Result<String, ReplError> {
+    if spec.kind.is_some_and(|k| k != 's') || spec.sign.is_some() || spec.grouping.is_some() {
+        return Err(ReplError::ValueError(
+            "invalid format specifier for str".into(),
+        ));
+    }
+    let body: String = match spec.precision {
+        Some(p) => s.chars().take(p).collect(),
+        None => s.to_string(),
+    };
+    Ok(pad("", &body, spec, '<'))
+}
+
+fn format_int(i: i64, spec: &FormatSpec) -> Result<String, ReplError> {
+    let kind = spec.kind.unwrap_or('d');
+    let mag = i.unsigned_abs();
+    let (digits, prefix) = match kind {
+        'd' | 'n' => (mag.to_string(), ""),
+        'x' => (format!("{mag:x}"), "0x"),
+        'X' => (format!("{mag:X}"), "0X"),
+        'o' => (format!("{mag:o}"), "0o"),
+        'b' => (format!("{mag:b}"), "0b"),
+        'c' => {
+            let c = u32::try_from(i)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| ReplError::ValueError("%c arg not in range".into()))?;
+            return Ok(pad("", &c.to_string(), spec, '<'));
+        }
+        // Ints format as floats for these types, e.g. format(3, ".2f") == "3.00".
+        'e' | 'E' | 'f' | 'F' | 'g' | 'G' | '%' => return format_float(i as f64, spec),
+        other => {
+            return Err(ReplError::ValueError(format!(
+                "unknown format code '{other}' for int"
+            )))
+        }
+    };
+    if spec.precision.is_some() {
+        return Err(ReplError::ValueError(
+            "precision not allowed in integer format specifier".into(),
+        ));
+    }
+    let every = if matches!(kind, 'd' | 'n') { 3 } else { 4 };
+    let prefix = if spec.alternate { prefix } else { "" };
+    let sign = format!("{}{prefix}", sign_of(i < 0, spec.sign));
+    let grouped = group_padded(&digits, spec, every, sign.chars().count());
+    Ok(pad(&sign, &grouped, spec, '>'))
+}
+
+fn format_float(x: f64, spec: &FormatSpec) -> Result<String, ReplError> {
+    // -0.0 keeps its sign, as in Python; a NaN never shows one.
+    let sign = sign_of(x.is_sign_negative() && !x.is_nan(), spec.sign);
+    let upper = spec.kind.is_some_and(|k| k.is_ascii_uppercase());
+    let body = if !x.is_finite() {
+        let text = if x.is_nan() { "nan" } else { "inf" };
+        let text = if upper {
+            text.to_uppercase()
+        } else {
+            text.to_string()
+        };
+        if spec.kind == Some('%') {
+            text + "%"
+        } else {
+            text
+        }
+    } else {
+        let mag = x.abs();
+        let body = match (spec.kind, spec.precision) {
+            (Some('f' | 'F'), p) => fixed(mag, p.unwrap_or(6), spec.alternate),
+            (Some('%'), p) => fixed(mag * 100.0, p.unwrap_or(6), spec.alternate) + "%",
+            (Some('e' | 'E'), p) => scientific(mag, p.unwrap_or(6), spec.alternate, upper),
+            (Some('g' | 'G'), p) => general(mag, p.unwrap_or(6), spec.alternate, upper, false),
+            // No type: `str(x)`, or `g` that keeps a `.0` when given a precision.
+            (None, None) => py_repr_float(mag),
+            (None, Some(p)) => general(mag, p, spec.alternate, false, true),
+            (Some(other), _) => {
+                return Err(ReplError::ValueError(format!(
+                    "unknown format code '{other}' for object of type 'float'"
+                )))
+            }
+        };
+        let split = body
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(body.len());
+        let (digits, rest) = body.split_at(split);
+        let reserved = sign.len() + rest.chars().count();
+        format!("{}{rest}", group_padded(digits, spec, 3, reserved))
+    };
+    Ok(pad(sign, &body, spec, '>'))
+}
+
+/// `mag` with `precision` digits after the point; `#` keeps the point when there are none.
+fn fixed(mag: f64, precision: usize, alternate: bool) -> String {
+    let text = format!("{mag:.precision$}");
+    if alternate && precision == 0 {
+        text + "."
+    } else {
+        text
+    }
+}
+
+/// `d.ddde+XX`: Python writes at least two exponent digits with an explicit sign.
+fn scientific(mag: f64, precision: usize, alternate: bool, upper: bool) -> String {
+    let text = format!("{mag:.precision$e}");
+    let (mantissa, exp) = text.split_once('e').expect("`{:e}` has an exponent");
+    let exp: i32 = exp.parse().expect("`{:e}` exponent is an integer");
+    let point = if alternate && precision == 0 { "." } else { "" };
+    let e = if upper { 'E' } else { 'e' };
+    let sign = if exp < 0 { '-' } else { '+' };
+    format!("{mantissa}{point}{e}{sign}{:02}", exp.abs())
+}
+
+/// The `g` type: `precision` significant digits, scientific only for very small or large
+/// exponents, trailing zeros dropped unless `#`. `add_dot_0` is the no-type variant, which
+/// keeps one digit after the point and so switches to scientific one exponent earlier.
+fn general(mag: f64, precision: usize, alternate: bool, upper: bool, add_dot_0: bool) -> String {
+    let precision = precision.max(1);
+    let rounded = format!("{mag:.0$e}", precision - 1);
+    let (_, exp) = rounded.split_once('e').expect("`{:e}` has an exponent");
+    let exp: i64 = exp.parse().expect("`{:e}` exponent is an integer");
+    let limit = if add_dot_0 {
+        precision as i64 - 1
+    } else {
+        precision as i64
+    };
+    let trim = |text: String| {
+        if alternate || !text.contains('.') {
+            return text;
+        }
+        text.trim_end_matches('0').trim_end_matches('.').to_string()
+    };
+    if exp < -4 || exp >= limit {
+        let text = scientific(mag, precision - 1, alternate, upper);
+        let (mantissa, exp) = text.split_at(text.find(['e', 'E']).expect("has an exponent"));
+        return format!("{}{exp}", trim(mantissa.to_string()));
+    }
+    let decimals = (precision as i64 - 1 - exp) as usize;
+    let text = trim(fixed(mag, decimals, alternate));
+    if add_dot_0 && !text.contains('.') {
+        text + ".0"
+    } else {
+        text
+    }
+}
+
+fn sign_of(negative: bool, sign: Option<char>) -> &'static str {
+    match (negative, sign) {
+        (true, _) => "-",
+        (false, Some('+')) => "+",
+        (false, Some(' ')) => " ",
+        _ => "",
+    }
+}
+
+fn group_digits(digits: &str, sep: Option<char>, every: usize) -> String {
+    let Some(sep) = sep else {
+        return digits.to_string();
+    };
+    let n = digits.chars().count();
+    let mut out = String::with_capacity(digits.len() + n / every);
+    for (idx, c) in digits.chars().enumerate() {
+        if idx > 0 && (n - idx).is_multiple_of(every) {
+            out.push(sep);
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// `group_digits`, except that zero padding with grouping (`08,d`) pads the digits
+/// themselves, so separators run through the zeros: `0,000,005`. `reserved` is the width
+/// taken by everything around the digits.
+fn group_padded(digits: &str, spec: &FormatSpec, every: usize, reserved: usize) -> String {
+    let zero_pad = spec.zero && spec.align.is_none() && spec.grouping.is_some();
+    let mut grouped = group_digits(digits, spec.grouping, every);
+    if !zero_pad {
+        return grouped;
+    }
+    let mut padded = digits.to_string();
+    while grouped.chars().count() + reserved < spec.width {
+        padded.insert(0, '0');
+        grouped = group_digits(&padded, spec.grouping, every);
+    }
+    grouped
+}
+
+/// Apply fill/align/width. `sign` (and any `0x`-style prefix) stays left of `=` padding.
+fn pad(sign: &str, body: &str, spec: &FormatSpec, default_align: char) -> String {
+    let (fill, align) = match (spec.fill, spec.align) {
+        (f, Some(a)) => (f.unwrap_or(' '), a),
+        (_, None) if spec.zero => ('0', '='),
+        (_, None) => (' ', default_align),
+    };
+    let len = sign.chars().count() + body.chars().count();
+    let padding = spec.width.saturating_sub(len);
+    let fills = |n: usize| std::iter::repeat_n(fill, n).collect::<String>();
+    match align {
+        '<' => format!("{sign}{body}{}", fills(padding)),
+        '^' => format!(
+            "{}{sign}{body}{}",
+            fills(padding / 2),
+            fills(padding - padding / 2)
+        ),
+        '=' => format!("{sign}{}{body}", fills(padding)),
+        _ => format!("{}{sign}{body}", fills(padding)),
+    }
+}
@@ -1,10 +1,12 @@
 mod allowlist;
 mod builtins;
 mod eval;
+mod format_spec;
 mod parse;
 pub mod state;
 mod value;
 
+pub use builtins::{BUILTIN_FUNCTIONS, MODULE_NAMES, STR_METHODS};
 pub use value::Value;
 
 use serde::{Deserialize, Serialize};
@@ -13,7 +15,52 @@ use serde::{Deserialize, Serialize};
 pub struct ReplConfig {
     pub max_output_chars: usize,
     pub max_zlib_output_bytes: usize,
+    /// Cap on the printed text persisted as `_print_txt` in the returned state. When the
+    /// next call prints nothing, that text is replayed as its output (upstream
+    /// RestrictedPython leak emulation), so this bounds the carryover independently of
+    /// `max_output_chars`.
     pub max_print_state_chars: usize,
+    /// Retry failed `json.loads` calls with trailing commas and single quotes tolerated.
+    pub lenient_json: bool,
+    /// Deepest statement/expression nesting accepted by validation and evaluation. Nesting
+    /// accumulates across user-function calls, each charged an extra level, so recursion
+    /// gets roughly a quarter this many frames; flat `a + b + c` chains don't count. The
+    /// evaluator is recursive, so this keeps its worst case near half of a 2 MiB tokio worker
+    /// stack in debug builds.
+    pub max_nesting_depth: usize,
+    /// Compiled-size budget for each `re` pattern (regex `size_limit`/`dfa_size_limit`).
+    pub max_regex_size_bytes: usize,
+    /// Most matches a single `re.findall` may return before it fails with
+    /// `ResourceLimitExceeded`, so one broad pattern can't flood output and state.
+    pub max_regex_matches: usize,
+    /// Most times a single `while` loop body may run before it fails with
+    /// `ResourceLimitExceeded` (the counterpart of `range()`'s length cap for `for`).
+    pub max_loop_iterations: usize,
+    /// Echo the repr of every top-level bare expression statement that isn't None, in order,
+    /// like an interactive Python REPL. Off by default: only the last expression echoes.
+    pub echo_all_expressions: bool,
+    /// Non-Python extension: `d[0]` / `d.get(0)` select by sorted key position. Disable for
+    /// strict Python semantics, where int keys on a str-keyed dict raise `TypeError`.
+    pub dict_int_index: bool,
+    /// How `import` / `from ... import` statements are treated.
+    pub import_policy: ImportPolicy,
+    /// When set, only these attribute names (methods and module functions alike, e.g.
+    /// `"split"`, `"search"`) pass validation; any other `x.attr` is a `ForbiddenName`.
+    /// `None` allows every attribute that isn't underscore-prefixed or dunder.
+    pub allowed_attributes: Option<Vec<String>>,
+}
+
+/// Import handling. Only the pre-injected modules (`re`, `json`, ...) ever exist; the
+/// policy decides what happens to everything else.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImportPolicy {
+    /// Every import is a no-op; aliases are not bound.
+    Ignore,
+    /// Known modules/attributes are bound (including aliases); unknown ones are ignored.
+    #[default]
+    BindKnown,
+    /// Like `BindKnown`, but importing an unknown module or attribute is a `ForbiddenName`.
+    ForbidUnknown,
 }
 
 impl Default for ReplConfig {
@@ -22,6 +69,15 @@ impl Default for ReplConfig {
             max_output_chars: 2000,
             max_zlib_output_bytes: 1_000_000,
             max_print_state_chars: 100_000,
+            lenient_json: false,
+            max_nesting_depth: 120,
+            max_regex_size_bytes: 1 << 20,
+            max_regex_matches: 10_000,
+            max_loop_iterations: 100_000,
+            echo_all_expressions: false,
+            dict_int_index: true,
+            import_policy: ImportPolicy::default(),
+            allowed_attributes: None,
         }
     }
 }
@@ -33,8 +89,15 @@ pub struct ExecRequest {
     pub code: String,
     #[serde(default)]
     pub max_output_chars: Option<usize>,
+    /// Per-request override of `ReplConfig::max_print_state_chars`.
+    #[serde(default)]
+    pub max_print_state_chars: Option<usize>,
     #[serde(default)]
     pub state: Option<state::ReplState>,
+    /// Seed for the in-REPL `random` module (0 when omitted). Ignored once `state` carries the
+    /// generator from an earlier call, so a chain of calls continues one sequence.
+    #[serde(default)]
+    pub seed: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -42,10 +105,61 @@ pub struct ExecResponse {
     pub ok: bool,
     pub output: String,
     pub error: Option<String>,
+    /// `ReplError` variant name (e.g. `NameError`, `ResourceLimitExceeded`) when `ok` is false.
+    #[serde(default)]
+    pub error_kind: Option<String>,
+    #[serde(default)]
+    pub location: Option<ErrorLocation>,
     #[serde(default)]
     pub state: Option<state::ReplState>,
 }
 
+/// 1-based source position of an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorLocation {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl ExecResponse {
+    fn failure(
+        e: &crate::error::ReplError,
+        location: Option<ErrorLocation>,
+        state: state::ReplState,
+    ) -> Self {
+        Self {
+            ok: false,
+            output: String::new(),
+            error: Some(format_error(e)),
+            error_kind: Some(e.kind().to_string()),
+            location,
+            state: Some(state),
+        }
+    }
+
+    /// A failure during execution: the error is prefixed with a Python-like traceback over
+    /// `frames` (outermost first), and `location` points at the innermost failing statement.
+    fn runtime_failure(
+        e: &crate::error::ReplError,
+        code: &str,
+        frames: &[eval::TraceFrame],
+        state: state::ReplState,
+    ) -> Self {
+        let location = frames.last().map(|f| parse::location_of(code, f.offset));
+        let mut resp = Self::failure(e, location, state);
+        // Like Python, an explicit exit reports only its code/message.
+        if !frames.is_empty() && !matches!(e, crate::error::ReplError::SystemExit(_)) {
+            let mut tb = String::from("Traceback (most recent call last):\n");
+            for f in frames {
+                let line = parse::location_of(code, f.offset).line;
+                tb.push_str(&format!("  line {line}, in {}\n", f.scope));
+            }
+            resp.error = resp.error.map(|msg| tb + &msg);
+        }
+        resp
+    }
+}
+
 pub struct ReplEngine {
     cfg: ReplConfig,
 }
@@ -71,47 +185,59 @@ impl ReplEngine {
         Self { cfg }
     }
 
+    /// Dry run: parse and check `code` against the allowlist without executing it.
+    pub fn validate(&self, code: &str) -> Result<(), crate::error::ReplError> {
+        let program = parse::parse_program(code).map_err(|(e, _)| e)?;
+        allowlist::validate(&program, &self.cfg)
+    }
+
+    /// `validate` for each snippet, so a linter can report every failure in one pass.
+    pub fn validate_many(&self, snippets: &[&str]) -> Vec<Result<(), crate::error::ReplError>> {
+        snippets.iter().map(|code| self.validate(code)).collect()
+    }
+
     pub fn exec(&self, req: ExecRequest) -> ExecResponse {
         if req.code.trim().is_empty() {
             return ExecResponse {
                 ok: true,
                 output: "No code to execute".to_string(),
                 error: None,
+                error_kind: None,
+                location: None,
                 state: Some(req.state.unwrap_or_default()),
             };
         }
 
         let cfg = ReplConfig {
             max_output_chars: req.max_output_chars.unwrap_or(self.cfg.max_output_chars),
-            max_zlib_output_bytes: self.cfg.max_zlib_output_bytes,
-            max_print_state_chars: self.cfg.max_print_state_chars,
+            max_print_state_chars: req
+                .max_print_state_chars
+                .unwrap_or(self.cfg.max_print_state_chars),
+            ..self.cfg.clone()
         };
 
         let base_state = req.state.clone().unwrap_or_default();
 
         let program = match parse::parse_program(&req.code) {
             Ok(p) => p,
-            Err(e) => {
-                return ExecResponse {
-                    ok: false,
-                    output: String::new(),
-                    error: Some(format_error(&e)),
-                    state: Some(base_state),
-                };
-            }
+            Err((e, location)) => return ExecResponse::failure(&e, Some(location), base_state),
         };
 
         let mut sink = builtins::PrintSink::new(cfg.max_output_chars, cfg.max_print_state_chars);
         let mut env =
             builtins::make_initial_env(cfg.max_zlib_output_bytes, &req.context, &req.query);
+        env.seed_random(req.seed.unwrap_or(0));
+        env.set_lenient_json(cfg.lenient_json);
+        env.set_max_depth(cfg.max_nesting_depth);
+        env.set_regex_size_limit(cfg.max_regex_size_bytes);
+        env.set_max_regex_matches(cfg.max_regex_matches);
+        env.set_max_loop_iterations(cfg.max_loop_iterations);
+        env.set_echo_expressions(cfg.echo_all_expressions);
+        env.set_dict_int_index(cfg.dict_int_index);
+        env.set_import_policy(cfg.import_policy);
         if let Some(st) = req.state {
             if let Err(e) = env.apply_state(&st) {
-                return ExecResponse {
-                    ok: false,
-                    output: String::new(),
-                    error: Some(format_error(&e)),
-                    state: Some(base_state),
-                };
+                return ExecResponse::failure(&e, None, base_state);
             }
         }
 
@@ -124,13 +250,8 @@ impl ReplEngine {
             env.set("_print_txt", Value::Str(String::new()));
         }
 
-        if let Err(e) = allowlist::validate(&program) {
-            return ExecResponse {
-                ok: false,
-                output: String::new(),
-                error: Some(format_error(&e)),
-                state: Some(env.dump_state()),
-            };
+        if let Err(e) = allowlist::validate(&program, &cfg) {
+            return ExecResponse::failure(&e, None, env.dump_state());
         }
 
         match eval::exec_program(&program, &mut env, &mut sink) {
@@ -145,8 +266,12 @@ impl ReplEngine {
                     }
                 }
 
-                // Echo the last expression (upstream behavior) after collecting print output.
-                eval::maybe_echo_last_expr(&req.code, &program, &mut env, &mut sink);
+                // Echo the last expression (upstream behavior) after collecting print output,
+                // unless every expression already echoed during execution or a trailing `;`
+                // asks for silence.
+                if !cfg.echo_all_expressions && !parse::ends_with_semicolon(&program, &req.code) {
+                    eval::maybe_echo_last_expr(&program, &mut env, &mut sink);
+                }
 
                 // Persist the latest print output for the next call.
                 if let Some(s) = sink.print_state_snapshot() {
@@ -159,6 +284,8 @@ impl ReplEngine {
                     ok: true,
                     output,
                     error: None,
+                    error_kind: None,
+                    location: None,
                     state: Some(state),
                 }
             }
@@ -167,13 +294,8 @@ impl ReplEngine {
                 if let Some(s) = sink.print_state_snapshot() {
                     env.set("_print_txt", Value::Str(s.to_string()));
                 }
-                let state = env.dump_state();
-                ExecResponse {
-                    ok: false,
-                    output: String::new(),
-                    error: Some(format_error(&e)),
-                    state: Some(state),
-                }
+                let frames = env.take_traceback();
+                ExecResponse::runtime_failure(&e, &req.code, &frames, env.dump_state())
             }
         }
     }
@@ -1,8 +1,36 @@
 use crate::error::ReplError;
 use rustpython_parser::{ast, Parse};
 
+use super::ErrorLocation;
+
 pub type Program = ast::Suite;
 
-pub fn parse_program(code: &str) -> Result<Program, ReplError> {
-    ast::Suite::parse(code, "<repl>").map_err(|e| ReplError::ParseError(e.to_string()))
+pub fn parse_program(code: &str) -> Result<Program, (ReplError, ErrorLocation)> {
+    ast::Suite::parse(code, "<repl>").map_err(|e| {
+        let location = location_of(code, e.offset.to_usize());
+        (ReplError::ParseError(e.to_string()), location)
+    })
+}
+
+/// Whether the last statement is followed by `;`, the Python REPL convention for
+/// "evaluate but don't echo" (`df.describe();`).
+pub fn ends_with_semicolon(program: &Program, code: &str) -> bool {
+    program.last().is_some_and(|st| {
+        code.get(ast::Ranged::end(st).to_usize()..)
+            .is_some_and(|rest| rest.trim_start().starts_with(';'))
+    })
+}
+
+/// Convert a byte offset into a 1-based line/column (columns count chars, like Python).
+pub fn location_of(code: &str, offset: usize) -> ErrorLocation {
+    let mut offset = offset.min(code.len());
+    while offset > 0 && !code.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &code[..offset];
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    ErrorLocation {
+        line: before.matches('\n').count() + 1,
+        col: before[line_start..].chars().count() + 1,
+    }
 }
@@ -1,12 +1,15 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use serde::{Deserialize, Serialize};
 
 use crate::error::ReplError;
 
-use super::value::{MatchObject, Value};
+use super::builtins::MODULE_NAMES;
+use super::value::{MatchObject, Module, SetItem, Value};
 
-pub type ReplState = HashMap<String, StoredValue>;
+/// Persisted bindings. Ordered maps keep serialized state (and anything derived from it)
+/// byte-identical across identical runs.
+pub type ReplState = BTreeMap<String, StoredValue>;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "t", content = "v")]
@@ -14,10 +17,15 @@ pub enum StoredValue {
     None,
     Bool(bool),
     Int(i64),
+    Float(f64),
     Str(String),
     BytesB64(String),
+    BytearrayB64(String),
     List(Vec<StoredValue>),
-    Dict(HashMap<String, StoredValue>),
+    Tuple(Vec<StoredValue>),
+    Dict(BTreeMap<String, StoredValue>),
+    /// Set elements in iteration order.
+    Set(Vec<StoredValue>),
     Match {
         groups: Vec<String>,
         #[serde(default)]
@@ -25,6 +33,8 @@ pub enum StoredValue {
         #[serde(default)]
         span_end: usize,
     },
+    /// A binding to one of the fixed, pre-injected modules (e.g. `import re as r`).
+    ModuleRef(String),
 }
 
 impl StoredValue {
@@ -33,6 +43,7 @@ impl StoredValue {
             StoredValue::None => Ok(Value::None),
             StoredValue::Bool(b) => Ok(Value::Bool(*b)),
             StoredValue::Int(i) => Ok(Value::Int(*i)),
+            StoredValue::Float(x) => Ok(Value::Float(*x)),
             StoredValue::Str(s) => Ok(Value::Str(s.clone())),
             StoredValue::BytesB64(s) => {
                 use base64::Engine;
@@ -41,6 +52,13 @@ impl StoredValue {
                     .map_err(|e| ReplError::ValueError(e.to_string()))?;
                 Ok(Value::Bytes(bytes))
             }
+            StoredValue::BytearrayB64(s) => {
+                use base64::Engine;
+                let bytes = base64::engine::general_purpose::STANDARD
+                    .decode(s.as_bytes())
+                    .map_err(|e| ReplError::ValueError(e.to_string()))?;
+                Ok(Value::Bytearray(bytes))
+            }
             StoredValue::List(xs) => {
                 let mut out = Vec::with_capacity(xs.len());
                 for x in xs {
@@ -48,13 +66,25 @@ impl StoredValue {
                 }
                 Ok(Value::List(out))
             }
+            StoredValue::Tuple(xs) => Ok(Value::Tuple(
+                xs.iter()
+                    .map(StoredValue::to_value)
+                    .collect::<Result<_, _>>()?,
+            )),
             StoredValue::Dict(m) => {
-                let mut out = std::collections::BTreeMap::new();
+                let mut out = BTreeMap::new();
                 for (k, v) in m {
                     out.insert(k.clone(), v.to_value()?);
                 }
                 Ok(Value::Dict(out))
             }
+            StoredValue::Set(xs) => {
+                let mut out = BTreeSet::new();
+                for x in xs {
+                    out.insert(SetItem::from_value(&x.to_value()?)?);
+                }
+                Ok(Value::Set(out))
+            }
             StoredValue::Match {
                 groups,
                 span_start,
@@ -64,6 +94,12 @@ impl StoredValue {
                 span_start: *span_start,
                 span_end: *span_end,
             })),
+            StoredValue::ModuleRef(name) => {
+                if !MODULE_NAMES.contains(&name.as_str()) {
+                    return Err(ReplError::ValueError(format!("unknown module '{name}'")));
+                }
+                Ok(Value::Module(Module { name: name.clone() }))
+            }
         }
     }
 }
@@ -73,12 +109,18 @@ pub fn try_from_value(v: &Value) -> Option<StoredValue> {
         Value::None => Some(StoredValue::None),
         Value::Bool(b) => Some(StoredValue::Bool(*b)),
         Value::Int(i) => Some(StoredValue::Int(*i)),
+        Value::Float(x) => Some(StoredValue::Float(*x)),
         Value::Str(s) => Some(StoredValue::Str(s.clone())),
         Value::Bytes(b) => {
             use base64::Engine;
             let s = base64::engine::general_purpose::STANDARD.encode(b);
             Some(StoredValue::BytesB64(s))
         }
+        Value::Bytearray(b) => {
+            use base64::Engine;
+            let s = base64::engine::general_purpose::STANDARD.encode(b);
+            Some(StoredValue::BytearrayB64(s))
+        }
         Value::List(xs) => {
             let mut out = Vec::with_capacity(xs.len());
             for x in xs {
@@ -86,19 +128,31 @@ pub fn try_from_value(v: &Value) -> Option<StoredValue> {
             }
             Some(StoredValue::List(out))
         }
+        Value::Tuple(xs) => Some(StoredValue::Tuple(
+            xs.iter().map(try_from_value).collect::<Option<_>>()?,
+        )),
         Value::Dict(m) => {
-            let mut out: HashMap<String, StoredValue> = HashMap::new();
+            let mut out: BTreeMap<String, StoredValue> = BTreeMap::new();
             for (k, v) in m {
                 out.insert(k.clone(), try_from_value(v)?);
             }
             Some(StoredValue::Dict(out))
         }
+        Value::Set(items) => Some(StoredValue::Set(
+            items
+                .iter()
+                .map(|x| try_from_value(&x.to_value()))
+                .collect::<Option<_>>()?,
+        )),
         Value::Match(m) => Some(StoredValue::Match {
             groups: m.groups.clone(),
             span_start: m.span_start,
             span_end: m.span_end,
         }),
-        // We don't persist functions/modules across CLI calls yet.
-        Value::UserFunc(_) | Value::Callable(_) | Value::Module(_) => None,
+        Value::Module(m) => Some(StoredValue::ModuleRef(m.name.clone())),
+        // We don't persist functions across CLI calls yet.
+        Value::UserFunc(_) | Value::Callable(_) => None,
+        // `...` is only a placeholder; there is nothing worth persisting.
+        Value::Ellipsis => None,
     }
 }
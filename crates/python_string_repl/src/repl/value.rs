@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 
 use crate::error::ReplError;
@@ -6,12 +6,17 @@ use crate::error::ReplError;
 #[derive(Clone, PartialEq)]
 pub enum Value {
     None,
+    Ellipsis,
     Bool(bool),
     Int(i64),
+    Float(f64),
     Str(String),
     Bytes(Vec<u8>),
+    Bytearray(Vec<u8>),
     List(Vec<Value>),
+    Tuple(Vec<Value>),
     Dict(BTreeMap<String, Value>),
+    Set(BTreeSet<SetItem>),
     Match(MatchObject),
     UserFunc(UserFunc),
     Callable(Callable),
@@ -22,12 +27,17 @@ impl fmt::Debug for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Value::None => write!(f, "None"),
+            Value::Ellipsis => write!(f, "Ellipsis"),
             Value::Bool(v) => write!(f, "Bool({v})"),
             Value::Int(v) => write!(f, "Int({v})"),
+            Value::Float(v) => write!(f, "Float({v})"),
             Value::Str(v) => write!(f, "Str({:?})", v),
             Value::Bytes(v) => write!(f, "Bytes(len={})", v.len()),
+            Value::Bytearray(v) => write!(f, "Bytearray(len={})", v.len()),
             Value::List(v) => write!(f, "List(len={})", v.len()),
+            Value::Tuple(v) => write!(f, "Tuple(len={})", v.len()),
             Value::Dict(v) => write!(f, "Dict(len={})", v.len()),
+            Value::Set(v) => write!(f, "Set(len={})", v.len()),
             Value::Match(_) => write!(f, "Match(...)"),
             Value::UserFunc(u) => write!(f, "UserFunc({})", u.name),
             Value::Callable(c) => write!(f, "Callable({:?})", c),
@@ -40,12 +50,17 @@ impl Value {
     pub fn type_name(&self) -> &'static str {
         match self {
             Value::None => "None",
+            Value::Ellipsis => "ellipsis",
             Value::Bool(_) => "bool",
             Value::Int(_) => "int",
+            Value::Float(_) => "float",
             Value::Str(_) => "str",
             Value::Bytes(_) => "bytes",
+            Value::Bytearray(_) => "bytearray",
             Value::List(_) => "list",
+            Value::Tuple(_) => "tuple",
             Value::Dict(_) => "dict",
+            Value::Set(_) => "set",
             Value::Match(_) => "match",
             Value::UserFunc(_) => "function",
             Value::Callable(_) => "callable",
@@ -65,7 +80,7 @@ impl Value {
 
     pub fn as_bytes(&self) -> Result<&[u8], ReplError> {
         match self {
-            Value::Bytes(b) => Ok(b.as_slice()),
+            Value::Bytes(b) | Value::Bytearray(b) => Ok(b.as_slice()),
             _ => Err(ReplError::TypeError(format!(
                 "expected bytes, got {}",
                 self.type_name()
@@ -76,12 +91,15 @@ impl Value {
     pub fn to_bool(&self) -> bool {
         match self {
             Value::None => false,
+            Value::Ellipsis => true,
             Value::Bool(b) => *b,
             Value::Int(i) => *i != 0,
+            Value::Float(x) => *x != 0.0,
             Value::Str(s) => !s.is_empty(),
-            Value::Bytes(b) => !b.is_empty(),
-            Value::List(v) => !v.is_empty(),
+            Value::Bytes(b) | Value::Bytearray(b) => !b.is_empty(),
+            Value::List(v) | Value::Tuple(v) => !v.is_empty(),
             Value::Dict(m) => !m.is_empty(),
+            Value::Set(m) => !m.is_empty(),
             Value::Match(_) => true,
             Value::UserFunc(_) => true,
             Value::Callable(_) => true,
@@ -90,6 +108,43 @@ impl Value {
     }
 }
 
+/// A set element. Only these immutable kinds are hashable in this subset; the derived order
+/// (None, bools, ints, strs, bytes) is the iteration order, so output is deterministic.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SetItem {
+    None,
+    Bool(bool),
+    Int(i64),
+    Str(String),
+    Bytes(Vec<u8>),
+}
+
+impl SetItem {
+    pub fn from_value(v: &Value) -> Result<Self, ReplError> {
+        match v {
+            Value::None => Ok(SetItem::None),
+            Value::Bool(b) => Ok(SetItem::Bool(*b)),
+            Value::Int(i) => Ok(SetItem::Int(*i)),
+            Value::Str(s) => Ok(SetItem::Str(s.clone())),
+            Value::Bytes(b) => Ok(SetItem::Bytes(b.clone())),
+            other => Err(ReplError::TypeError(format!(
+                "unhashable type: '{}'",
+                other.type_name()
+            ))),
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            SetItem::None => Value::None,
+            SetItem::Bool(b) => Value::Bool(*b),
+            SetItem::Int(i) => Value::Int(*i),
+            SetItem::Str(s) => Value::Str(s.clone()),
+            SetItem::Bytes(b) => Value::Bytes(b.clone()),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct MatchObject {
     pub groups: Vec<String>, // group(0) is the full match
@@ -113,11 +168,19 @@ pub struct Module {
 pub enum Callable {
     Module { module: String, attr: String },
     BytesDecode { bytes: Vec<u8> },
+    BytesJoin { bytes: Vec<u8> },
     StrStrip { s: String },
+    StrLStrip { s: String },
+    StrRStrip { s: String },
     StrLower { s: String },
+    StrUpper { s: String },
     StrFind { s: String },
     StrReplace { s: String },
     StrSplit { s: String },
     StrStartsWith { s: String },
+    StrEndsWith { s: String },
+    StrCount { s: String },
+    StrFormat { s: String },
+    StrJoin { s: String },
     MatchGroup { m: MatchObject },
 }
@@ -0,0 +1,250 @@
+//! Text helpers shared by the REPL builtins and the retrieval fallback: caseless matching
+//! (`str.casefold()`, `rank_documents`, lexical scoring) and snippet boundary snapping, so
+//! "same text" and "clean snippet" mean the same thing everywhere.
+
+use std::collections::HashMap;
+
+/// Python-style `casefold`: lowercase plus the full foldings that lowercasing misses
+/// (`ß` -> `ss`, final sigma, long s, Latin ligatures).
+pub fn casefold(s: &str) -> String {
+    casefold_with_offsets(s).0
+}
+
+/// `casefold`, plus for every byte of the folded text the byte offset of the source char
+/// in `s`. Folding can change lengths, so matches must be mapped back through this.
+pub fn casefold_with_offsets(s: &str) -> (String, Vec<usize>) {
+    let mut out = String::with_capacity(s.len());
+    let mut offsets = Vec::with_capacity(s.len());
+    for (i, c) in s.char_indices() {
+        let before = out.len();
+        match fold_special(c) {
+            Some(folded) => out.push_str(folded),
+            None => out.extend(c.to_lowercase()),
+        }
+        offsets.extend(std::iter::repeat_n(i, out.len() - before));
+    }
+    (out, offsets)
+}
+
+/// Map a match at `folded_start..folded_start + folded_len` in folded text back to a byte
+/// range of the original `s`, widened to whole source chars.
+pub fn original_range(
+    s: &str,
+    offsets: &[usize],
+    folded_start: usize,
+    folded_len: usize,
+) -> (usize, usize) {
+    let start = offsets[folded_start];
+    let last = offsets[folded_start + folded_len.max(1) - 1];
+    let end = last + s[last..].chars().next().map_or(0, char::len_utf8);
+    (start, end)
+}
+
+/// Memoized casefolding and term lookups behind `rank_documents`, so ranking the same
+/// documents again (typically with the same query) skips re-folding and re-scanning them.
+/// Documents are keyed by a hash of their text; results match the uncached scan exactly.
+#[derive(Debug, Default)]
+pub struct TermIndex {
+    queries: HashMap<String, Vec<String>>,
+    docs: HashMap<u64, FoldedDoc>,
+    cached_bytes: usize,
+    folds: usize,
+    scans: usize,
+}
+
+#[derive(Debug)]
+struct FoldedDoc {
+    text: String,
+    folded: String,
+    offsets: Vec<usize>,
+    /// Folded-text position of the first occurrence of each term looked up so far.
+    first_match: HashMap<String, Option<usize>>,
+}
+
+/// Past this much cached document text the index starts over, bounding its memory.
+const TERM_INDEX_MAX_BYTES: usize = 16 << 20;
+
+impl TermIndex {
+    /// The casefolded alphanumeric runs of `query` that are at least two bytes long.
+    pub fn query_terms(&mut self, query: &str) -> Vec<String> {
+        self.queries
+            .entry(query.to_string())
+            .or_insert_with(|| {
+                casefold(query)
+                    .split(|c: char| !c.is_alphanumeric())
+                    .filter(|s| s.len() >= 2)
+                    .map(str::to_string)
+                    .collect()
+            })
+            .clone()
+    }
+
+    /// How many of `terms` occur in `text` (caselessly), and the byte range in `text` of
+    /// the first term in `terms` order that does.
+    pub fn match_terms(&mut self, text: &str, terms: &[String]) -> (usize, Option<(usize, usize)>) {
+        let doc = self.folded(text);
+        let mut count = 0;
+        let mut first = None;
+        let mut scans = 0;
+        for t in terms {
+            let pos = match doc.first_match.get(t) {
+                Some(pos) => *pos,
+                None => {
+                    scans += 1;
+                    let pos = doc.folded.find(t.as_str());
+                    doc.first_match.insert(t.clone(), pos);
+                    pos
+                }
+            };
+            if let Some(i) = pos {
+                count += 1;
+                if first.is_none() {
+                    first = Some(original_range(text, &doc.offsets, i, t.len()));
+                }
+            }
+        }
+        self.scans += scans;
+        (count, first)
+    }
+
+    /// Documents casefolded so far; repeated texts are served from the index.
+    pub fn folds(&self) -> usize {
+        self.folds
+    }
+
+    /// Term searches run over folded text so far; repeated lookups are served from the index.
+    pub fn scans(&self) -> usize {
+        self.scans
+    }
+
+    fn folded(&mut self, text: &str) -> &mut FoldedDoc {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        text.hash(&mut hasher);
+        let key = hasher.finish();
+        // A hash collision replaces the entry rather than returning another text's terms.
+        if self.docs.get(&key).is_some_and(|d| d.text != text) {
+            self.docs.remove(&key);
+        }
+        if !self.docs.contains_key(&key) {
+            if self.cached_bytes + text.len() > TERM_INDEX_MAX_BYTES {
+                self.docs.clear();
+                self.cached_bytes = 0;
+            }
+            self.folds += 1;
+            self.cached_bytes += text.len();
+            let (folded, offsets) = casefold_with_offsets(text);
+            self.docs.insert(
+                key,
+                FoldedDoc {
+                    text: text.to_string(),
+                    folded,
+                    offsets,
+                    first_match: HashMap::new(),
+                },
+            );
+        }
+        self.docs.get_mut(&key).expect("inserted above")
+    }
+}
+
+fn fold_special(c: char) -> Option<&'static str> {
+    Some(match c {
+        'ß' | 'ẞ' => "ss",
+        'ſ' => "s",
+        'ς' => "σ",
+        'ﬀ' => "ff",
+        'ﬁ' => "fi",
+        'ﬂ' => "fl",
+        'ﬃ' => "ffi",
+        'ﬄ' => "ffl",
+        'ﬅ' | 'ﬆ' => "st",
+        _ => return None,
+    })
+}
+
+/// Where a snippet window may start and end.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Boundary {
+    /// Any char: windows are cut exactly at the requested size.
+    #[default]
+    Char,
+    /// Don't split words; leading/trailing whitespace is trimmed.
+    Word,
+    /// Prefer whole lines, falling back to word boundaries on a side with no line break.
+    Line,
+}
+
+impl Boundary {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "char" => Some(Self::Char),
+            "word" => Some(Self::Word),
+            "line" => Some(Self::Line),
+            _ => None,
+        }
+    }
+}
+
+/// Shrink the byte window `start..end` of `s` to `boundary`: the start never moves past
+/// `keep_start` and the end never before `keep_end` (the match the window was built
+/// around; pass `keep_start = end, keep_end = start` when there is none).
+pub fn snap_window(
+    s: &str,
+    start: usize,
+    end: usize,
+    keep_start: usize,
+    keep_end: usize,
+    boundary: Boundary,
+) -> (usize, usize) {
+    let keep_start = keep_start.clamp(start, end);
+    let keep_end = keep_end.clamp(start, end);
+    let is_line = |c: char| c == '\n';
+    let (mut a, mut b) = match boundary {
+        Boundary::Char => return (start, end),
+        Boundary::Word => (
+            snap_start(s, start, keep_start, char::is_whitespace).unwrap_or(start),
+            snap_end(s, end, keep_end, char::is_whitespace).unwrap_or(end),
+        ),
+        Boundary::Line => (
+            snap_start(s, start, keep_start, is_line)
+                .or_else(|| snap_start(s, start, keep_start, char::is_whitespace))
+                .unwrap_or(start),
+            snap_end(s, end, keep_end, is_line)
+                .or_else(|| snap_end(s, end, keep_end, char::is_whitespace))
+                .unwrap_or(end),
+        ),
+    };
+    while a < keep_start && s[a..].starts_with(char::is_whitespace) {
+        a += s[a..].chars().next().map_or(1, char::len_utf8);
+    }
+    while b > keep_end && s[..b].ends_with(char::is_whitespace) {
+        b -= s[..b].chars().next_back().map_or(1, char::len_utf8);
+    }
+    if a > b {
+        return (start, end);
+    }
+    (a, b)
+}
+
+/// First position at or after `start` (but not past `limit`) that begins a unit.
+fn snap_start(s: &str, start: usize, limit: usize, is_sep: impl Fn(char) -> bool) -> Option<usize> {
+    if start == 0 || s[..start].ends_with(&is_sep) || s[start..].starts_with(&is_sep) {
+        return Some(start);
+    }
+    let (i, c) = s[start..limit].char_indices().find(|&(_, c)| is_sep(c))?;
+    Some(start + i + c.len_utf8())
+}
+
+/// Last position at or before `end` (but not before `limit`) that ends a unit.
+fn snap_end(s: &str, end: usize, limit: usize, is_sep: impl Fn(char) -> bool) -> Option<usize> {
+    if end == s.len() || s[end..].starts_with(&is_sep) || s[..end].ends_with(&is_sep) {
+        return Some(end);
+    }
+    let (i, _) = s[limit..end]
+        .char_indices()
+        .rev()
+        .find(|&(_, c)| is_sep(c))?;
+    Some(limit + i)
+}
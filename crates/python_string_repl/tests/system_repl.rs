@@ -1,4 +1,27 @@
-use python_string_repl::repl::{ExecRequest, ReplConfig, ReplEngine};
+use python_string_repl::error::ReplError;
+use python_string_repl::repl::state::ReplState;
+use python_string_repl::repl::{
+    ErrorLocation, ExecRequest, ExecResponse, ImportPolicy, ReplConfig, ReplEngine,
+    BUILTIN_FUNCTIONS, STR_METHODS,
+};
+use python_string_repl::text::TermIndex;
+
+fn exec(code: &str) -> ExecResponse {
+    exec_with(&ReplConfig::default(), code, None)
+}
+
+/// Like `exec`, under a non-default config and/or continuing from an earlier call's state.
+fn exec_with(cfg: &ReplConfig, code: &str, state: Option<ReplState>) -> ExecResponse {
+    ReplEngine::new(cfg.clone()).exec(ExecRequest {
+        context: String::new(),
+        query: String::new(),
+        code: code.to_string(),
+        max_output_chars: None,
+        max_print_state_chars: None,
+        state,
+        seed: None,
+    })
+}
 
 fn run(code: &str, context: &str, query: &str) -> (bool, String, Option<String>) {
     let engine = ReplEngine::new(ReplConfig::default());
@@ -7,7 +30,9 @@ fn run(code: &str, context: &str, query: &str) -> (bool, String, Option<String>)
         query: query.to_string(),
         code: code.to_string(),
         max_output_chars: None,
+        max_print_state_chars: None,
         state: None,
+        seed: None,
     });
     (resp.ok, resp.output, resp.error)
 }
@@ -26,6 +51,38 @@ fn sys_echo_last_expr_name() {
     assert_eq!(out, "hello");
 }
 
+#[test]
+fn sys_echo_last_expr_is_decided_on_the_ast() {
+    let (ok, out, err) = run("x = 1\ny = 1\nprint('p')\nx == y", "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "p\nTrue");
+
+    let (ok, out, err) = run("data_for = [1, 2]\ndata_for", "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "[1, 2]");
+
+    // Calls, even nested ones, are not re-run for the echo.
+    let (ok, out, err) = run("xs = [1]\nxs.append(2)\n[len(xs)][0]", "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "Code executed successfully (no output)");
+}
+
+#[test]
+fn sys_trailing_semicolon_suppresses_last_expr_echo() {
+    let (ok, out, err) = run("xs = [1, 2]\nprint('p')\nxs;", "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "p");
+
+    let (ok, out, err) = run("xs = [1, 2]\nxs;  # quiet", "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "Code executed successfully (no output)");
+
+    // Only a `;` after the last statement counts.
+    let (ok, out, err) = run("x = 1; x", "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "1");
+}
+
 #[test]
 fn sys_import_is_ignored_and_preprovided_modules_work() {
     let code = r#"
@@ -102,6 +159,18 @@ print(ans)
     assert_eq!(out, "345938494");
 }
 
+#[test]
+fn sys_oversized_regex_pattern_is_a_clean_value_error() {
+    let resp = exec(r"re.findall(r'\w{1000}', context)");
+    assert!(!resp.ok);
+    assert_eq!(resp.error_kind.as_deref(), Some("ValueError"));
+    assert!(resp.error.unwrap().contains("regex pattern too complex"));
+
+    let (ok, out, err) = run(r"print(len(re.findall(r'\w{3}', context)))", "abcdef", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "2");
+}
+
 #[test]
 fn sys_regex_findall_and_len() {
     let code = r#"
@@ -124,6 +193,25 @@ print(obj["a"], obj["b"][0])
     assert_eq!(out, "1 2");
 }
 
+#[test]
+fn sys_json_loads_lenient_mode_accepts_trailing_commas_and_single_quotes() {
+    let code = r#"
+obj = json.loads("{'a': 'it\\'s \"x\"', 'b': [1, 2,],}")
+print(obj["a"], obj["b"][1])
+"#;
+    let cfg = ReplConfig {
+        lenient_json: true,
+        ..ReplConfig::default()
+    };
+    let resp = exec_with(&cfg, code, None);
+    assert!(resp.ok, "err={:?}", resp.error);
+    assert_eq!(resp.output, "it's \"x\" 2");
+
+    let resp = exec(code);
+    assert!(!resp.ok);
+    assert_eq!(resp.error_kind.as_deref(), Some("ValueError"));
+}
+
 #[test]
 fn sys_import_json_and_from_import_loads() {
     let code = r#"
@@ -149,6 +237,49 @@ print(idx)
     assert_eq!(out, "6");
 }
 
+#[test]
+fn sys_string_count() {
+    let code = r#"
+print("banana".count("a"), "aaa".count("aa"), "abc".count(""), "日本日本".count("日"))
+hits = context.lower().count
+print(hits(query))
+"#;
+    let (ok, out, err) = run(code, "Rust rust RUST", "rust");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "3 1 4 2\n3");
+}
+
+#[test]
+fn sys_string_upper_endswith() {
+    let code = r#"
+s = query.strip()
+print(context.upper().endswith(s.upper()), context.endswith(s))
+shout = context.upper
+print(shout(), context.endswith(""))
+"#;
+    let (ok, out, err) = run(code, "Hello WORLD", "  world  ");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "True False\nHELLO WORLD True");
+
+    let (ok, _, err) = run("context.endswith(suffix='x')", "abc", "");
+    assert!(!ok);
+    assert!(err.unwrap().contains("keyword args"));
+}
+
+#[test]
+fn sys_multibyte_context_slicing_and_find_use_char_indices() {
+    let code = r#"
+print(context[:5])
+i = context.find("検索")
+print(i, context[i:i + 2], context[-1])
+ranked = rank_documents("検索", [{"id": "d1", "text": context}], 1)
+print(len(ranked))
+"#;
+    let (ok, out, err) = run(code, "🦀😀 日本語の検索テキスト 🎉", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "🦀😀 日本\n7 検索 🎉\n1");
+}
+
 #[test]
 fn sys_slice_context_prefix() {
     let code = r#"
@@ -276,6 +407,30 @@ print(d[0], d[1])
     assert_eq!(out, "1 2");
 }
 
+#[test]
+fn sys_dict_int_indexing_can_be_disabled() {
+    let cfg = ReplConfig {
+        dict_int_index: false,
+        ..ReplConfig::default()
+    };
+    for code in [
+        "d = json.loads('{\"a\": 1}')\nprint(d[0])\n",
+        "d = json.loads('{\"a\": 1}')\nprint(d.get(0))\n",
+    ] {
+        let resp = exec_with(&cfg, code, None);
+        assert!(!resp.ok, "code={code:?}");
+        assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+    }
+
+    let resp = exec_with(
+        &cfg,
+        "d = json.loads('{\"a\": 1}')\nprint(d[\"a\"])\n",
+        None,
+    );
+    assert!(resp.ok, "err={:?}", resp.error);
+    assert_eq!(resp.output, "1");
+}
+
 #[test]
 fn sys_in_compare_and_boolop() {
     let code = r#"
@@ -333,6 +488,146 @@ print(json.dumps({"a": 1}))
     assert_eq!(out, "2 a b\n1 9\nhello you\nhello\nTrue\n{\"a\":1}");
 }
 
+#[test]
+fn sys_list_remove_and_clear_in_place() {
+    let code = r#"
+xs = ["a", "b", "a"]
+xs.remove("a")
+print(len(xs), xs[0], xs[1])
+xs.clear()
+print(len(xs))
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "2 b a\n0");
+
+    let resp = exec("xs = [1]\nxs.remove(2)");
+    assert!(!resp.ok);
+    assert_eq!(resp.error_kind.as_deref(), Some("ValueError"));
+}
+
+#[test]
+fn sys_reversed_builtin_and_in_place_reverse() {
+    let code = r#"
+ys = reversed([1, 2, 3])
+print(ys[0], ys[1], ys[2])
+cs = reversed("abc")
+print(len(cs), cs[0])
+xs = ["a", "b", "c"]
+xs.reverse()
+print(xs[0], xs[2])
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "3 2 1\n3 c\nc a");
+}
+
+#[test]
+fn sys_pluck_ids_from_documents_and_uniform_dict_get() {
+    let code = r#"
+documents = json.loads('[{"id": "d1", "text": "a"}, {"id": "d2"}]')
+print(pluck(documents, "id"), pluck(documents, "text"))
+d = documents[0]
+print(d.get("id"), d.get("nope", "x"), d.get(1), d.get(9, "x"), d["text"])
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "['d1', 'd2'] ['a', None]\nd1 x a x a");
+}
+
+#[test]
+fn sys_aligned_table_with_repetition_padding_and_print_sep() {
+    let code = r#"
+rows = [["doc1", "12"], ["doc22", "3"]]
+print("id".ljust(6), "score".rjust(5), sep="|")
+print("-" * 6, "-" * 5, sep="+")
+for r in rows:
+    print(r[0].ljust(6), r[1].rjust(5), sep="|")
+print("end".center(10, "*"), "ab".center(5, "*"), "abc".center(6, "*"), sep="|")
+print(3 * "ab", [0] * 3, 2 * 21)
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(
+        out,
+        "id    |score\n------+-----\ndoc1  |   12\ndoc22 |    3\n***end****|**ab*|*abc**\nababab [0, 0, 0] 42"
+    );
+
+    let resp = exec("s = 'x' * 10000000");
+    assert_eq!(resp.error_kind.as_deref(), Some("ResourceLimitExceeded"));
+}
+
+#[test]
+fn sys_format_builtin_applies_format_spec() {
+    let code = r##"
+print(format(255, "x"), format(255, "#X"), format(5, "08b"), format(-42, "+06d"))
+print(format(1234567, ","), format(7, "*^7d"), format("ab", ">4"))
+print(format(2, ".1%"), format("abcdef", ".3"), format(None), format(True, "d"))
+print(format(3.14159, ".2f"), format(-2.5, "+08.3f"), format(1234567.891, ",.1f"))
+print(format(12345.678, ".3e"), format(0.0001234, "g"), format(1e20, "G"), format(0.256, ".1%"))
+print(format(1 / 3), format(123.0, ".3"), format(2.0, "010,.1f"))
+x = 2.75
+print(f"{x:.1f}|{x:>7.2f}", "{:.2f}".format(1 / 3))
+"##;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(
+        out,
+        "ff 0XFF 00000101 -00042\n1,234,567 ***7***   ab\n200.0% abc None 1\n\
+         3.14 -002.500 1,234,567.9\n1.235e+04 0.0001234 1E+20 25.6%\n\
+         0.3333333333333333 1.23e+02 0,000,002.0\n2.8|   2.75 0.33"
+    );
+
+    let resp = exec("format(1, '.2d')");
+    assert_eq!(resp.error_kind.as_deref(), Some("ValueError"));
+}
+
+#[test]
+fn sys_dict_builtin_from_kwargs_and_pairs() {
+    let code = r#"
+a = dict(x=1, y="two")
+b = dict([("k", 1), ["j", 2]], k=3)
+print(a["x"], a["y"], len(a), b["j"], b["k"], len(dict()))
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "1 two 2 2 3 0");
+
+    let resp = exec("dict([(1, 2)])");
+    assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+}
+
+#[test]
+fn sys_list_builtin_materializes_iterables() {
+    let code = r#"
+print(list("abc"), list(b"hi"), list(range(3)), list())
+xs = [1, 2]
+ys = list(xs)
+ys.append(3)
+print(len(xs), len(ys))
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "['a', 'b', 'c'] [104, 105] [0, 1, 2] []\n2 3");
+}
+
+#[test]
+fn sys_list_index_and_count() {
+    let code = r#"
+xs = ["a", "b", "a", "c"]
+print(xs.index("a"), xs.index("a", 1), xs.index("c", -2), xs.count("a"), xs.count("z"))
+print((1, 2, 2).count(2))
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "0 2 3 2 0\n2");
+
+    let resp = exec("xs = ['a', 'b']\nxs.index('b', 0, 1)");
+    assert!(!resp.ok);
+    assert_eq!(resp.error_kind.as_deref(), Some("ValueError"));
+    assert!(resp.error.unwrap().contains("'b' is not in list"));
+}
+
 #[test]
 fn sys_rank_documents_helper() {
     let code = r#"
@@ -345,6 +640,48 @@ print(len(hits), hits[0]["doc_id"])
     assert_eq!(out, "1 d2");
 }
 
+#[test]
+fn sys_rank_documents_repeated_calls_are_stable() {
+    let code = r#"
+docs = json.loads('[{"id":"d1","text":"brown bear"},{"id":"d2","text":"the quick brown fox"}]')
+first = rank_documents(query, docs, 2)
+same = True
+for i in range(50):
+    if rank_documents(query, docs, 2) != first:
+        same = False
+print(same, [h["doc_id"] for h in first])
+docs.append(json.loads('{"id":"d0","text":"brown fox"}'))
+print([h["doc_id"] for h in rank_documents(query, docs, 2)])
+print([h["doc_id"] for h in rank_documents(query, docs, 1)])
+"#;
+    let (ok, out, err) = run(code, "", "brown fox");
+    assert!(ok, "err={err:?}");
+    // Changed inputs (documents or top_k) still rank from scratch where they differ.
+    assert_eq!(out, "True ['d2', 'd1']\n['d0', 'd2']\n['d0']");
+}
+
+#[test]
+fn sys_term_index_serves_repeated_lookups_from_cache() {
+    let mut index = TermIndex::default();
+    let texts = ["the quick brown fox", "Straße und Brown"];
+    let terms = index.query_terms("Brown FOX strasse");
+    assert_eq!(terms, ["brown", "fox", "strasse"]);
+
+    let first: Vec<_> = texts.iter().map(|t| index.match_terms(t, &terms)).collect();
+    assert_eq!((index.folds(), index.scans()), (2, 6));
+    assert_eq!(first[0], (2, Some((10, 15))));
+    assert_eq!(first[1], (2, Some((12, 17))));
+
+    // Same texts and terms again: no folding or scanning, identical answers.
+    let again: Vec<_> = texts.iter().map(|t| index.match_terms(t, &terms)).collect();
+    assert_eq!(again, first);
+    assert_eq!((index.folds(), index.scans()), (2, 6));
+
+    // A new term scans the already folded document without folding it again.
+    index.match_terms(texts[0], &["quick".to_string()]);
+    assert_eq!((index.folds(), index.scans()), (2, 7));
+}
+
 #[test]
 fn sys_zlib_output_limit_is_enforced() {
     // Decompressing this should exceed the default cap (1_000_000) if not enforced.
@@ -404,6 +741,49 @@ print(out)
     assert_eq!(out, "ab");
 }
 
+#[test]
+fn sys_while_break_continue_and_else() {
+    let code = r#"
+out = ""
+i = 0
+while i < len(query):
+    ch = query[i]
+    i += 1
+    if ch == "b":
+        continue
+    out = out + ch
+    if len(out) == 2:
+        break
+else:
+    out = out + "!"
+print(out)
+n = 0
+while n < 3:
+    n += 1
+else:
+    print("done", n)
+"#;
+    let (ok, out, err) = run(code, "", "abcd");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "ac\ndone 3");
+}
+
+#[test]
+fn sys_while_iterations_are_capped() {
+    let cfg = ReplConfig {
+        max_loop_iterations: 50,
+        ..ReplConfig::default()
+    };
+    let resp = exec_with(&cfg, "n = 0\nwhile True:\n    n += 1", None);
+    assert!(!resp.ok);
+    assert_eq!(resp.error_kind.as_deref(), Some("ResourceLimitExceeded"));
+    assert!(resp.error.unwrap().contains("exceed limit (50)"));
+
+    let resp = exec("def f():\n    while True:\n        return 7\nprint(f())");
+    assert!(resp.ok, "err={:?}", resp.error);
+    assert_eq!(resp.output, "7");
+}
+
 #[test]
 fn sys_percent_formatting() {
     let code = r#"
@@ -426,3 +806,1385 @@ print(m.group(0) if m else "no")
     assert!(ok, "err={err:?}");
     assert_eq!(out, "abc");
 }
+
+#[test]
+fn sys_regex_findall_without_groups_returns_whole_matches() {
+    let code = r#"
+print(re.findall(r'\d+', context))
+"#;
+    let (ok, out, err) = run(code, "a1 b22 c333", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "['1', '22', '333']");
+}
+
+#[test]
+fn sys_regex_findall_with_one_group_returns_group_text() {
+    let code = r#"
+print(re.findall(r'key=(\w+)', context))
+"#;
+    let (ok, out, err) = run(code, "key=a x key=bc", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "['a', 'bc']");
+}
+
+#[test]
+fn sys_regex_findall_with_two_groups_returns_group_tuples() {
+    let code = r#"
+pairs = re.findall(r'(\w+)=(\d+)', context)
+print(len(pairs), pairs[1][0], pairs[1][1])
+"#;
+    let (ok, out, err) = run(code, "a=1, b=22", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "2 b 22");
+}
+
+#[test]
+fn sys_int_parses_underscores_sign_and_whitespace() {
+    let code = r#"
+print(int("1_000") + 1)
+print(int("  -42 "), int("+7"))
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "1001\n-42 7");
+}
+
+#[test]
+fn sys_int_rejects_comma_grouping_with_hint() {
+    let code = r#"
+print(int("1,000"))
+"#;
+    let (ok, _out, err) = run(code, "", "");
+    assert!(!ok);
+    let msg = err.unwrap_or_default();
+    assert!(msg.contains("value error"), "unexpected err: {msg}");
+    assert!(msg.contains("replace(','"), "missing hint: {msg}");
+}
+
+#[test]
+fn sys_int_rejects_misplaced_underscores() {
+    let (ok, _out, err) = run(r#"print(int("1__000"))"#, "", "");
+    assert!(!ok);
+    assert!(err.unwrap_or_default().contains("invalid literal"));
+}
+
+#[test]
+fn sys_random_module_is_reproducible_for_a_seed() {
+    let code = r#"
+print(random.randint(1, 1000), random.randrange(10), random.choice(["a", "b", "c"]))
+"#;
+    let engine = ReplEngine::new(ReplConfig::default());
+    let exec = |seed| {
+        engine.exec(ExecRequest {
+            context: String::new(),
+            query: String::new(),
+            code: code.to_string(),
+            max_output_chars: None,
+            max_print_state_chars: None,
+            state: None,
+            seed: Some(seed),
+        })
+    };
+    let a = exec(42);
+    let b = exec(42);
+    assert!(a.ok, "err={:?}", a.error);
+    assert_eq!(a.output, b.output);
+    assert_ne!(a.output, exec(7).output);
+}
+
+#[test]
+fn sys_random_seed_resets_the_generator() {
+    let code = r#"
+import random
+random.seed(3)
+a = random.randint(0, 1000000)
+random.seed(3)
+print(a == random.randint(0, 1000000))
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "True");
+}
+
+#[test]
+fn sys_random_continues_across_chained_state() {
+    let engine = ReplEngine::new(ReplConfig::default());
+    let exec = |state| {
+        engine.exec(ExecRequest {
+            context: String::new(),
+            query: String::new(),
+            code: "print(random.randint(0, 1000000))".to_string(),
+            max_output_chars: None,
+            max_print_state_chars: None,
+            state,
+            seed: Some(42),
+        })
+    };
+    let first = exec(None);
+    let second = exec(first.state.clone());
+    assert!(second.ok, "err={:?}", second.error);
+    // Same seed on every call, but the second call picks up where the first left off.
+    assert_ne!(first.output, second.output);
+    assert_eq!(exec(first.state).output, second.output);
+}
+
+#[test]
+fn sys_random_range_bounds_do_not_overflow() {
+    let resp = exec("random.randrange(-9223372036854775807 - 1)");
+    assert_eq!(resp.error_kind.as_deref(), Some("ValueError"));
+    assert!(resp.error.unwrap().contains("empty range"));
+    let (ok, out, err) = run(
+        "x = random.randrange(-9223372036854775807 - 1, -9223372036854775807)\nprint(x)",
+        "",
+        "",
+    );
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "-9223372036854775808");
+}
+
+#[test]
+fn sys_in_dict_is_key_membership() {
+    let code = r#"
+d = {"a": "x", "b": "y"}
+print("a" in d, "x" in d, "c" not in d)
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "True False True");
+}
+
+#[test]
+fn sys_in_dict_keys_and_values_views() {
+    let code = r#"
+d = json.loads('{"a": 1, "b": [1, 2], "c": null, "d": {"k": "v"}}')
+print("b" in d.keys(), 1 in d.values(), "a" in d.values())
+print([1, 2] in d.values(), None in d.values(), {"k": "v"} in d.values())
+print(d.items()[0])
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "True True False\nTrue True True\n('a', 1)");
+}
+
+#[test]
+fn sys_error_kind_is_reported_for_forbidden_name() {
+    let resp = exec("x = __import__");
+    assert!(!resp.ok);
+    assert_eq!(resp.error_kind.as_deref(), Some("ForbiddenName"));
+    assert!(resp.error.unwrap_or_default().contains("forbidden name"));
+}
+
+#[test]
+fn sys_error_kind_and_location_for_parse_error() {
+    let resp = exec("x = 1\ny = 2 +\nz = 3");
+    assert!(!resp.ok);
+    assert_eq!(resp.error_kind.as_deref(), Some("ParseError"));
+    assert_eq!(resp.location, Some(ErrorLocation { line: 2, col: 8 }));
+}
+
+#[test]
+fn sys_error_kind_is_absent_on_success() {
+    let resp = exec("print(1)");
+    assert!(resp.ok);
+    assert_eq!(resp.error_kind, None);
+    assert_eq!(resp.location, None::<ErrorLocation>);
+}
+
+#[test]
+fn sys_deeply_nested_input_is_rejected_cleanly() {
+    let code = format!("x = {}1{}", "(1 + ".repeat(5000), ")".repeat(5000));
+    let resp = exec(&code);
+    assert!(!resp.ok);
+    assert_eq!(resp.error_kind.as_deref(), Some("ForbiddenSyntax"));
+    assert!(resp.error.unwrap().contains("nesting too deep"));
+
+    let code = format!("x = {}1{}", "[".repeat(5000), "]".repeat(5000));
+    let resp = exec(&code);
+    assert_eq!(resp.error_kind.as_deref(), Some("ForbiddenSyntax"));
+
+    // Runaway recursion trips the same guard at evaluation time.
+    let resp = exec("def f(n):\n    return f(n + 1)\nf(0)");
+    assert!(!resp.ok);
+    assert!(resp.error.unwrap().contains("nesting too deep"));
+}
+
+#[test]
+fn sys_long_flat_chains_and_moderate_recursion_fit_the_depth_limit() {
+    // A flat `+` chain is a left-leaning tree, but its length isn't nesting.
+    let code = format!("print({})", vec!["1"; 200].join(" + "));
+    let (ok, out, err) = run(&code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "200");
+
+    let code = r#"
+def depth(n):
+    if n == 0:
+        return 0
+    return depth(n - 1) + 1
+print(depth(24))
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "24");
+}
+
+#[test]
+fn sys_validate_many_reports_each_snippet_without_executing() {
+    let engine = ReplEngine::new(ReplConfig::default());
+    let results = engine.validate_many(&[
+        "x = undefined_name + 1",
+        "with x:\n    pass",
+        "y = (",
+        "print(__import__)",
+    ]);
+    assert_eq!(results.len(), 4);
+    // Validation never runs code, so unknown names are fine.
+    assert_eq!(results[0], Ok(()));
+    assert!(matches!(results[1], Err(ReplError::ForbiddenSyntax(_))));
+    assert!(matches!(results[2], Err(ReplError::ParseError(_))));
+    assert_eq!(
+        results[3],
+        Err(ReplError::ForbiddenName("__import__".to_string()))
+    );
+}
+
+#[test]
+fn sys_module_alias_persists_across_state() {
+    let engine = ReplEngine::new(ReplConfig::default());
+    let first = engine.exec(ExecRequest {
+        context: "id=42".to_string(),
+        query: String::new(),
+        code: "import re as r\n".to_string(),
+        max_output_chars: None,
+        max_print_state_chars: None,
+        state: None,
+        seed: None,
+    });
+    assert!(first.ok, "err={:?}", first.error);
+
+    let second = engine.exec(ExecRequest {
+        context: "id=42".to_string(),
+        query: String::new(),
+        code: "print(r.search(r'id=(\\d+)', context).group(1))\n".to_string(),
+        max_output_chars: None,
+        max_print_state_chars: None,
+        state: first.state,
+        seed: None,
+    });
+    assert!(second.ok, "err={:?}", second.error);
+    assert_eq!(second.output, "42");
+}
+
+#[test]
+fn sys_bytearray_item_assignment_and_append() {
+    let code = r#"
+buf = bytearray(b"abc")
+buf[0] = 65
+buf[-1] = 255
+buf.append(10)
+print(buf, len(buf), buf[0], buf == b"ABc")
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "bytearray(b'Ab\\xff\\n') 4 65 False");
+
+    let resp = exec("buf = bytearray(2)\nbuf[1] = 256\n");
+    assert!(!resp.ok);
+    assert_eq!(resp.error_kind.as_deref(), Some("ValueError"));
+
+    let resp = exec("s = b'ab'\ns[0] = 1\n");
+    assert!(!resp.ok);
+    assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+}
+
+#[test]
+fn sys_item_assignment_on_lists_and_dicts() {
+    let code = r#"
+xs = [1, 2, 3]
+xs[-1] = "z"
+d = {"a": 1}
+d["a"] = 2
+d["b"] = xs
+print(xs, d)
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "[1, 2, 'z'] {'a': 2, 'b': [1, 2, 'z']}");
+
+    // The target's type is checked before the key.
+    let resp = exec("s = 'ab'\ns['k'] = 'x'\n");
+    assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+    assert!(resp
+        .error
+        .unwrap()
+        .contains("'str' object does not support item assignment"));
+    let resp = exec("xs = [1]\nxs['k'] = 2\n");
+    assert!(resp
+        .error
+        .unwrap()
+        .contains("list indices must be integers, not str"));
+    let resp = exec("d = {}\nd[1] = 2\n");
+    assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+}
+
+#[test]
+fn sys_bytearray_persists_across_state() {
+    let cfg = ReplConfig::default();
+    let first = exec_with(&cfg, "buf = bytearray([1, 2, 3])\nbuf[1] = 0\n", None);
+    assert!(first.ok, "err={:?}", first.error);
+    let second = exec_with(&cfg, "buf.append(4)\nprint(list(buf))\n", first.state);
+    assert!(second.ok, "err={:?}", second.error);
+    assert_eq!(second.output, "[1, 0, 3, 4]");
+}
+
+#[test]
+fn sys_percent_and_ratio_bps_are_integer_and_zero_safe() {
+    let code = r#"
+print(percent(1, 3), percent(3, 3), percent(5, 0))
+print(ratio_bps(1, 3), ratio_bps(7, 0), ratio_bps(9, 4))
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "33 100 0\n3333 0 10000");
+
+    let resp = exec("print(percent('1', 2))");
+    assert!(!resp.ok);
+    assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+}
+
+#[test]
+fn sys_print_state_leaks_into_next_call_without_print() {
+    let engine = ReplEngine::new(ReplConfig::default());
+    let req = |code: &str, state, cap| ExecRequest {
+        context: String::new(),
+        query: String::new(),
+        code: code.to_string(),
+        max_output_chars: None,
+        max_print_state_chars: cap,
+        state,
+        seed: None,
+    };
+
+    let first = engine.exec(req("print('hello')\nx = 1\n", None, None));
+    assert!(first.ok, "err={:?}", first.error);
+    assert_eq!(first.output, "hello");
+
+    // No print in this call: the previous collector leaks (upstream emulation).
+    let second = engine.exec(req("y = 2\n", first.state.clone(), None));
+    assert!(second.ok, "err={:?}", second.error);
+    assert_eq!(second.output, "hello");
+
+    // A print resets the collector, so only the new output shows.
+    let third = engine.exec(req("print('bye')\n", second.state, None));
+    assert_eq!(third.output, "bye");
+
+    // The persisted copy is capped independently of max_output_chars.
+    let capped = engine.exec(req("print('abcdef')\n", None, Some(3)));
+    assert_eq!(capped.output, "abcdef");
+    let leaked = engine.exec(req("y = 2\n", capped.state, None));
+    assert_eq!(leaked.output, "abc");
+
+    let none = engine.exec(req("print('abcdef')\n", None, Some(0)));
+    let leaked = engine.exec(req("y = 2\n", none.state, None));
+    assert_eq!(leaked.output, "Code executed successfully (no output)");
+}
+
+#[test]
+fn sys_method_on_none_match_is_attribute_error() {
+    let resp = exec("m = re.search(r'zzz', 'abc')\nprint(m.group(0))\n");
+    assert!(!resp.ok);
+    assert_eq!(resp.error_kind.as_deref(), Some("AttributeError"));
+    assert!(resp
+        .error
+        .unwrap()
+        .contains("'NoneType' object has no attribute 'group'"));
+
+    // The guarded form keeps working.
+    let (ok, out, err) = run(
+        "m = re.search(r'zzz', 'abc')\nprint(m.group(0) if m else 'miss')\n",
+        "",
+        "",
+    );
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "miss");
+}
+
+#[test]
+fn sys_import_policy_forbid_unknown_rejects_os_but_allows_re() {
+    let engine = ReplEngine::new(ReplConfig {
+        import_policy: ImportPolicy::ForbidUnknown,
+        ..ReplConfig::default()
+    });
+    let run_strict = |code: &str| {
+        engine.exec(ExecRequest {
+            context: "id=7".to_string(),
+            query: String::new(),
+            code: code.to_string(),
+            max_output_chars: None,
+            max_print_state_chars: None,
+            state: None,
+            seed: None,
+        })
+    };
+
+    for code in [
+        "import os\n",
+        "from os import path\n",
+        "from re import nope\n",
+    ] {
+        let resp = run_strict(code);
+        assert!(!resp.ok, "code={code:?}");
+        assert_eq!(resp.error_kind.as_deref(), Some("ForbiddenName"));
+    }
+    assert_eq!(
+        engine.validate("import os").unwrap_err(),
+        ReplError::ForbiddenName("os".into())
+    );
+
+    let resp = run_strict(
+        "import re as r\nfrom re import search\nprint(search(r'\\d', context).group(0))\n",
+    );
+    assert!(resp.ok, "err={:?}", resp.error);
+    assert_eq!(resp.output, "7");
+
+    // The default stays lenient: unknown imports are ignored.
+    let (ok, _, err) = run("import os\nprint(1)\n", "", "");
+    assert!(ok, "err={err:?}");
+}
+
+#[test]
+fn sys_import_policy_ignore_skips_alias_binding() {
+    let cfg = ReplConfig {
+        import_policy: ImportPolicy::Ignore,
+        ..ReplConfig::default()
+    };
+    let resp = exec_with(&cfg, "import re as r\nprint(r)\n", None);
+    assert!(!resp.ok);
+    assert_eq!(resp.error_kind.as_deref(), Some("NameError"));
+}
+
+#[test]
+fn sys_exit_reports_its_message() {
+    let resp = exec("import sys\nprint('working')\nsys.exit('done')\nprint('unreachable')\n");
+    assert!(!resp.ok);
+    assert_eq!(resp.error_kind.as_deref(), Some("SystemExit"));
+    assert_eq!(resp.error.as_deref(), Some("SystemExit: done"));
+
+    // Bare exits keep the plain form, and try/except doesn't swallow them.
+    let resp = exec("try:\n    raise SystemExit\nexcept Exception:\n    print('caught')\n");
+    assert_eq!(resp.error.as_deref(), Some("SystemExit"));
+    let resp = exec("sys.exit(3)");
+    assert_eq!(resp.error.as_deref(), Some("SystemExit: 3"));
+}
+
+#[test]
+fn sys_casefold_matches_sharp_s_in_str_and_ranking() {
+    let code = r#"
+print("Straße".casefold() == "STRASSE".casefold(), "Straße".lower() == "strasse")
+docs = json.loads('[{"id":"d1","text":"Wohnung in der Hauptstraße 5"},{"id":"d2","text":"kein Treffer"}]')
+hits = rank_documents(query, docs, 2)
+print(len(hits), hits[0]["doc_id"], hits[0]["snippet"])
+"#;
+    let (ok, out, err) = run(code, "", "hauptstrasse");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "True False\n1 d1 Wohnung in der Hauptstraße 5");
+}
+
+#[test]
+fn sys_ordering_none_names_operand_types() {
+    let resp = exec("x = {}.get('n')\nprint(x < 1)\n");
+    assert!(!resp.ok);
+    assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+    let err = resp.error.unwrap();
+    assert!(
+        err.contains("'<' not supported between instances of 'NoneType' and 'int'"),
+        "err={err}"
+    );
+}
+
+#[test]
+fn sys_str_len_index_and_slice_agree_on_code_points() {
+    // 7 code points, 22 UTF-8 bytes: CJK is 3 bytes each, the emoji 4.
+    let code = r#"
+s = "日本語😀テスト"
+print(len(s), len(s.encode()))
+print(s[3], s[-1], s[len(s) - 1] == s[-1])
+print(s[2:4], s[-3:], s[:len(s)] == s)
+i = s.find("😀")
+print(i, s[i], s[i:i + 1] == s[i], len(s[:i]) == i)
+print(len([c for c in s]) == len(s))
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(
+        out,
+        "7 22\n😀 ト True\n語😀 テスト True\n3 😀 True True\nTrue"
+    );
+
+    let (ok, _, err) = run("s = '😀'\nprint(s[1])", "", "");
+    assert!(!ok);
+    assert!(err.unwrap().contains("index out of range"));
+}
+
+#[test]
+fn sys_break_outside_loop_is_rejected() {
+    let resp = exec("print('before')\nbreak\n");
+    assert!(!resp.ok);
+    assert_eq!(resp.error_kind.as_deref(), Some("ParseError"));
+    assert!(resp.error.unwrap().contains("'break' outside loop"));
+    // Rejected up front: nothing before the `break` ran.
+    assert_eq!(resp.output, "");
+
+    // A loop around a function definition doesn't make `continue` in its body legal.
+    let resp = exec("for i in [1]:\n    def f():\n        continue\n");
+    assert!(resp
+        .error
+        .unwrap()
+        .contains("'continue' not properly in loop"));
+
+    let (ok, out, err) = run(
+        "for i in [1, 2, 3]:\n    if i == 2:\n        continue\n    if i == 3:\n        break\n    print(i)\n",
+        "",
+        "",
+    );
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "1");
+}
+
+#[test]
+fn sys_findall_match_cap_engages() {
+    let ctx = "a ".repeat(20_000);
+    let (ok, _, err) = run(
+        "hits = re.findall(r'a', context)\nprint(len(hits))",
+        &ctx,
+        "",
+    );
+    assert!(!ok);
+    assert!(
+        err.as_deref()
+            .unwrap()
+            .contains("re.findall matches exceed limit (10000)"),
+        "err={err:?}"
+    );
+
+    let engine = ReplEngine::new(ReplConfig {
+        max_regex_matches: 3,
+        ..ReplConfig::default()
+    });
+    let exec_ctx = |code: &str| {
+        engine.exec(ExecRequest {
+            context: "a1 a2 a3 a4".to_string(),
+            query: String::new(),
+            code: code.to_string(),
+            max_output_chars: None,
+            max_print_state_chars: None,
+            state: None,
+            seed: None,
+        })
+    };
+    let resp = exec_ctx("print(re.findall(r'a(\\d)', context[:8]))");
+    assert!(resp.ok, "err={:?}", resp.error);
+    assert_eq!(resp.output, "['1', '2', '3']");
+    let resp = exec_ctx("print(re.findall(r'a\\d', context))");
+    assert_eq!(resp.error_kind.as_deref(), Some("ResourceLimitExceeded"));
+}
+
+#[test]
+fn sys_rank_documents_snaps_snippets_to_words() {
+    let words = ["alpha", "bravo", "charlie", "delta", "echo", "foxtrot"];
+    let filler = words.repeat(10).join(" ");
+    let text = format!("{filler} target {filler}");
+    let code = format!(
+        "docs = [{{\"id\": \"d1\", \"text\": \"{text}\"}}]\n\
+         print(rank_documents(query, docs, 1)[0][\"snippet\"])\n\
+         print(rank_documents(query, docs, 1, boundary=\"word\")[0][\"snippet\"])"
+    );
+    let (ok, out, err) = run(&code, "", "target");
+    assert!(ok, "err={err:?}");
+    let (raw, snapped) = out.split_once('\n').unwrap();
+    // The plain 80-byte window lands mid-word here; snapping trims to whole words.
+    assert!(raw.split(' ').any(|w| w != "target" && !words.contains(&w)));
+    assert!(snapped.contains("target"));
+    assert!(
+        snapped
+            .split(' ')
+            .all(|w| w == "target" || words.contains(&w)),
+        "snapped={snapped:?}"
+    );
+
+    let (ok, _, err) = run(
+        "rank_documents(query, [], 1, boundary='sentence')",
+        "",
+        "target",
+    );
+    assert!(!ok);
+    assert!(err.unwrap().contains("boundary must be"));
+}
+
+#[test]
+fn sys_runtime_error_reports_traceback_lines() {
+    let code = "def f(x):\n    y = x + 1\n    return y + missing\n\nprint('start')\nf(1)\n";
+    let resp = exec(code);
+    assert!(!resp.ok);
+    assert_eq!(resp.error_kind.as_deref(), Some("NameError"));
+    let err = resp.error.unwrap();
+    assert!(
+        err.starts_with(
+            "Traceback (most recent call last):\n  line 6, in <module>\n  line 3, in f\nname error: missing"
+        ),
+        "err={err}"
+    );
+    assert_eq!(resp.location, Some(ErrorLocation { line: 3, col: 5 }));
+
+    // A caught error leaves no stale frames behind for the next one.
+    let resp = exec("try:\n    f()\nexcept Exception:\n    pass\nx = 1\nprint(x + 'a')\n");
+    let err = resp.error.unwrap();
+    assert!(
+        err.starts_with("Traceback (most recent call last):\n  line 6, in <module>\ntype error"),
+        "err={err}"
+    );
+}
+
+#[test]
+fn sys_starred_unpacking_assignment() {
+    let code = r#"
+a, *rest = [1, 2, 3, 4]
+print(a, rest)
+*init, last = [1, 2, 3]
+print(init, last)
+first, *mid, end = "abcd"
+print(first, mid, end)
+x, *none = [7]
+print(x, none)
+p, q = q0, p0 = [5, 6]
+p, q = q, p
+print(p, q)
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "1 [2, 3, 4]\n[1, 2] 3\na ['b', 'c'] d\n7 []\n6 5");
+
+    let (ok, _, err) = run("a, b, *c = [1]", "", "");
+    assert!(!ok);
+    assert!(err
+        .unwrap()
+        .contains("not enough values to unpack (expected at least 2, got 1)"));
+    let (ok, _, err) = run("a, b = [1, 2, 3]", "", "");
+    assert!(!ok);
+    assert!(err
+        .unwrap()
+        .contains("too many values to unpack (expected 2)"));
+
+    let resp = exec("*a, *b = [1, 2]");
+    assert_eq!(resp.error_kind.as_deref(), Some("ParseError"));
+}
+
+#[test]
+fn sys_dict_membership_in_hot_loop_does_not_copy_the_dict() {
+    // 20k keys probed 4k times: copying the dict per lookup would be ~10^8 entry clones.
+    let dict: std::collections::BTreeMap<String, i64> =
+        (0..20_000).map(|i| (format!("k{i}"), i)).collect();
+    let ctx = serde_json::to_string(&dict).unwrap();
+    let code = r#"
+d = json.loads(context)
+ks = d.keys()
+hits = 0
+total = 0
+for i in range(4000):
+    k = ks[i * 5]
+    if k in d:
+        hits += 1
+        total += d[k]
+    if d.get("missing" + k) is None:
+        hits += 1
+print(hits, total)
+"#;
+    let started = std::time::Instant::now();
+    let (ok, out, err) = run(code, &ctx, "");
+    let elapsed = started.elapsed();
+    assert!(ok, "err={err:?}");
+    let expected: i64 = dict.values().step_by(5).take(4000).sum();
+    assert_eq!(out, format!("8000 {expected}"));
+    assert!(
+        elapsed < std::time::Duration::from_secs(5),
+        "took {elapsed:?}"
+    );
+}
+
+#[test]
+fn sys_bytes_join_and_accumulation() {
+    let code = r#"
+chunks = [b"ab", b"", b"cd", "é".encode()]
+joined = b"".join(chunks)
+print(joined, len(joined), b"-".join([b"x", b"y"]))
+sep = b", "
+j = sep.join
+print(j([b"1", b"2"]).decode())
+out = b""
+for i in range(3000):
+    out += b"xy"
+print(len(out), out[:4])
+s = ""
+for i in range(3000):
+    s += "z"
+print(len(s))
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "b'abcd\\xc3\\xa9' 6 b'x-y'\n1, 2\n6000 b'xyxy'\n3000");
+
+    let (ok, _, err) = run(r#"b"".join([b"a", "b"])"#, "", "");
+    assert!(!ok);
+    assert!(err
+        .unwrap()
+        .contains("sequence item 1: expected a bytes-like object, str found"));
+}
+
+#[test]
+fn sys_repr_quotes_while_str_renders_like_print() {
+    let code = r#"
+print(repr("a"), str("a"), len(repr("a")), len(str("a")))
+print(repr(b"x\n"), str(3) + "!", repr([1, "b"]), repr(None))
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "'a' a 3 1\nb'x\\n' 3! [1, 'b'] None");
+}
+
+#[test]
+fn sys_conditional_import_idiom_runs_and_except_import_error_is_skipped() {
+    let code = r#"
+try:
+    import ujson as json
+except ImportError:
+    import json
+print(json.loads('{"a": 1}')["a"])
+try:
+    x = 1 + "a"
+except ImportError:
+    print("import")
+except Exception:
+    print("caught")
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "1\ncaught");
+
+    // A lone `except ImportError` doesn't swallow unrelated errors.
+    let resp = exec("try:\n    x = 1 + 'a'\nexcept ImportError:\n    x = 0");
+    assert!(!resp.ok);
+    assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+}
+
+#[test]
+fn sys_float_literals_division_and_mixed_arithmetic() {
+    let code = r#"
+print(7 / 2, 6 / 3, 1 + 0.5, 2 - 0.5, 3 * 0.5, -7.5 % 2, 7 % -2.0)
+print(0.1 + 0.2, 1e16, 1.5e-5, 0.0001, -0.0, 1 / 3)
+print(1 == 1.0, 2 > 1.5, 0.5 <= 0, int(2.9), int(-2.9), repr(2.0), json.dumps([0.25]))
+x = 0
+x += 0.5
+print(x, json.loads('{"a": 1.5}')["a"] * 2)
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(
+        out,
+        "3.5 2.0 1.5 1.5 1.5 0.5 -1.0\n\
+         0.30000000000000004 1e+16 1.5e-05 0.0001 -0.0 0.3333333333333333\n\
+         True True False 2 -2 2.0 [0.25]\n\
+         0.5 3.0"
+    );
+
+    let resp = exec("x = 1 / 0");
+    assert!(!resp.ok);
+    assert!(resp.error.unwrap().contains("division by zero"));
+
+    // Int and float `%` agree on sign; int overflow is an error, never a wrap or panic.
+    let (ok, out, err) = run("print(-7 % 3, -7.0 % 3)", "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "2 2.0");
+    for code in [
+        "x = 9223372036854775807\nx + 1",
+        "x = 9223372036854775807\nx += 1",
+        "-9223372036854775807 - 2",
+        "-(-9223372036854775807 - 1)",
+    ] {
+        let resp = exec(code);
+        assert_eq!(resp.error_kind.as_deref(), Some("ValueError"), "{code}");
+        assert!(resp.error.unwrap().contains("integer overflow"), "{code}");
+    }
+    let (ok, out, err) = run(
+        "print(list(range(9223372036854775800, 9223372036854775807, 5)))",
+        "",
+        "",
+    );
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "[9223372036854775800, 9223372036854775805]");
+}
+
+#[test]
+fn sys_float_state_round_trips() {
+    let cfg = ReplConfig::default();
+    let first = exec_with(&cfg, "ratio = 3 / 4\nxs = [0.5, 1]", None);
+    assert!(first.ok, "err={:?}", first.error);
+    let state = first.state.unwrap();
+    let json = serde_json::to_string(&state).unwrap();
+    let state = serde_json::from_str(&json).unwrap();
+    let second = exec_with(&cfg, "print(ratio, xs[0] + ratio)", Some(state));
+    assert!(second.ok, "err={:?}", second.error);
+    assert_eq!(second.output, "0.75 1.25");
+}
+
+#[test]
+fn sys_dumped_state_is_ordered_and_identical_across_runs() {
+    let code = (0..30)
+        .map(|i| format!("v{i} = {{'k{i}': {i}, 'a': [{i}]}}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let dumps: Vec<String> = (0..2)
+        .map(|_| serde_json::to_string(&exec(&code).state.unwrap()).unwrap())
+        .collect();
+    assert_eq!(dumps[0], dumps[1]);
+    let state = exec(&code).state.unwrap();
+    let keys: Vec<&String> = state.keys().collect();
+    assert!(keys.windows(2).all(|w| w[0] < w[1]));
+}
+
+#[test]
+fn sys_sum_builtin() {
+    let code = r#"
+print(sum([1, 2, 3]), sum(range(5)), sum([], 10), sum([1, 2], 5))
+print(sum([0.5, 1]), sum([x > 1 for x in [1, 2, 3]]))
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "6 10 10 8\n1.5 2");
+
+    let resp = exec("sum([1, 'a'])");
+    assert!(!resp.ok);
+    assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+    assert!(resp
+        .error
+        .unwrap()
+        .contains("unsupported operand type(s) for +: 'int' and 'str'"));
+}
+
+#[test]
+fn sys_echo_all_expressions_mode_echoes_each_bare_expression() {
+    let code = "x = 2\nx + 1\nprint('p')\n'a' * 2\nNone\nlen([1, 2])";
+    let cfg = ReplConfig {
+        echo_all_expressions: true,
+        ..ReplConfig::default()
+    };
+    let resp = exec_with(&cfg, code, None);
+    assert!(resp.ok, "err={:?}", resp.error);
+    assert_eq!(resp.output, "3\np\n'aa'\n2");
+
+    // Default: only the last expression echoes.
+    let (ok, out, err) = run("x = 2\nx + 1\nx + 2", "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "4");
+}
+
+#[test]
+fn sys_max_min_over_iterables_and_arguments() {
+    let code = r#"
+print(max([3, 1, 2]), max(1, 2, 3), min(4, 2, 8), min([2.5, 3]), max(range(4)))
+print(min("zba"), max(["pear", "apple"]), "a" < "b", "b" <= "a")
+print(max([1, 1.0]), min(b"xa"))
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "3 3 2 2.5 3\na pear True False\n1 97");
+
+    let resp = exec("max([])");
+    assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+    assert!(resp
+        .error
+        .unwrap()
+        .contains("max() arg is an empty sequence"));
+    let resp = exec("min([1, 'a'])");
+    assert!(resp
+        .error
+        .unwrap()
+        .contains("'<' not supported between instances of 'str' and 'int'"));
+}
+
+#[test]
+fn sys_sorted_with_reverse() {
+    let code = r#"
+xs = [3, 1, 2]
+print(sorted(xs), xs, sorted(["b", "a"], reverse=True), sorted("cab"))
+print(sorted([2, 0.5, 1]), sorted([], reverse=True), sorted(range(3), reverse=True))
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(
+        out,
+        "[1, 2, 3] [3, 1, 2] ['b', 'a'] ['a', 'b', 'c']\n[0.5, 1, 2] [] [2, 1, 0]"
+    );
+
+    let resp = exec("sorted([1, 'a'])");
+    assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+    let resp = exec("sorted([1], key=1)");
+    assert_eq!(resp.error_kind.as_deref(), Some("ForbiddenSyntax"));
+}
+
+#[test]
+fn sys_ellipsis_placeholder_body_and_complex_literal() {
+    let code = r#"
+def todo():
+    ...
+
+def f(x):
+    """Not done yet."""
+    ...
+    return x
+
+x = ...
+print(todo(), f(3), x, repr(...), "yes" if ... else "no")
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "None 3 Ellipsis Ellipsis yes");
+
+    let resp = exec("x = 1j");
+    assert!(!resp.ok);
+    assert_eq!(resp.error_kind.as_deref(), Some("ForbiddenSyntax"));
+    assert!(resp.error.unwrap().contains("complex number literals"));
+}
+
+#[test]
+fn sys_zip_pairs_up_to_the_shortest_iterable() {
+    let code = r#"
+for a, b in zip([1, 2], [3, 4]):
+    print(a, b)
+print(zip(["x", "y", "z"], "ab", range(5)))
+print(zip([], [1]))
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "1 3\n2 4\n[('x', 'a', 0), ('y', 'b', 1)]\n[]");
+
+    let resp = exec("zip([1, 2])");
+    assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+    let resp = exec("zip([1], 2)");
+    assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+}
+
+#[test]
+fn sys_dict_merge_with_or_and_or_assign() {
+    let code = r#"
+merged = {"a": 1, "b": 2}
+merged |= {"b": 20, "c": 30}
+print(merged)
+print({"x": 1} | {"x": 2, "y": 3})
+n = 4
+n |= 1
+n -= 2
+print(n)
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "{'a': 1, 'b': 20, 'c': 30}\n{'x': 2, 'y': 3}\n3");
+
+    let resp = exec("d = {}\nd |= [1]");
+    assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+}
+
+#[test]
+fn sys_str_int_bool_conversions() {
+    let code = r#"
+print(int("42") + 1, int("ff", 16), int("0x1F", base=16), int("-0b101", 0), int("z", 36))
+print(str(123) + "!", int(7.9), bool(0), bool("x"), bool([]), bool())
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "43 255 31 -5 35\n123! 7 False True False False");
+
+    let resp = exec(r#"int("12", 2)"#);
+    assert_eq!(resp.error_kind.as_deref(), Some("ValueError"));
+    assert!(resp.error.unwrap().contains("with base 2: '12'"));
+    let resp = exec(r#"int("1", 1)"#);
+    assert_eq!(resp.error_kind.as_deref(), Some("ValueError"));
+    let resp = exec("int(5, 16)");
+    assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+    // Floats beyond i64 are rejected rather than clamped.
+    for code in ["int(2.5e19)", "int(-2.5e19)", "int(9223372036854775808.0)"] {
+        let resp = exec(code);
+        assert_eq!(resp.error_kind.as_deref(), Some("ValueError"), "{code}");
+    }
+    let (ok, out, err) = run("print(int(-9223372036854775808.0), int(-1e18))", "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "-9223372036854775808 -1000000000000000000");
+
+    // The i64 range is asymmetric: its minimum parses, one past its maximum doesn't.
+    let (ok, out, err) = run(
+        "print(int('-9223372036854775808'), int('-0x8000000000000000', 16))",
+        "",
+        "",
+    );
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "-9223372036854775808 -9223372036854775808");
+    for code in ["int('9223372036854775808')", "int('-9223372036854775809')"] {
+        let resp = exec(code);
+        assert_eq!(resp.error_kind.as_deref(), Some("ValueError"), "{code}");
+    }
+}
+
+#[test]
+fn sys_any_and_all() {
+    let code = r#"
+text = "the brown fox"
+print(any([0, 0, 1]), all([1, 1, 0]), any([]), all([]))
+print(any([t in text for t in ["cat", "fox"]]), all("abc"))
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "True False False True\nTrue True");
+
+    let resp = exec("any(3)");
+    assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+}
+
+#[test]
+fn sys_parse_csv_handles_quoted_fields() {
+    let code = r#"
+rows = parse_csv('name,note\r\n"Smith, J","said ""hi""\nthen left"\n')
+print(len(rows), rows[0])
+print(rows[1][0])
+print(rows[1][1])
+print(parse_csv("a;b;;", delimiter=";"))
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(
+        out,
+        "2 ['name', 'note']\nSmith, J\nsaid \"hi\"\nthen left\n[['a', 'b', '', '']]"
+    );
+
+    let resp = exec(r#"parse_csv('a,"b')"#);
+    assert_eq!(resp.error_kind.as_deref(), Some("ValueError"));
+    let resp = exec(r#"parse_csv("a", delimiter="::")"#);
+    assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+}
+
+#[test]
+fn sys_sets_dedupe_and_combine() {
+    let code = r#"
+terms = set(["fox", "dog", "fox"])
+print(len(set([1, 1, 2])), terms, "fox" in terms, "cat" in terms)
+a = {1, 2, 3}
+b = {3, 4}
+print(a | b, a & b, a - b, a ^ b, set(), sorted({"b", "a"}))
+a |= {9}
+print(a, len(a))
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(
+        out,
+        "2 {'dog', 'fox'} True False\n{1, 2, 3, 4} {3} {1, 2} {1, 2, 4} set() ['a', 'b']\n{1, 2, 3, 9} 4"
+    );
+
+    let resp = exec("s = {[1]}");
+    assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+    assert!(resp.error.unwrap().contains("unhashable type: 'list'"));
+}
+
+#[test]
+fn sys_sets_persist_in_state() {
+    let cfg = ReplConfig::default();
+    let first = exec_with(&cfg, "seen = {'b', 'a'}\n", None);
+    assert!(first.ok, "err={:?}", first.error);
+
+    let second = exec_with(&cfg, "seen |= {'c'}\nprint(seen)\n", first.state);
+    assert!(second.ok, "err={:?}", second.error);
+    assert_eq!(second.output, "{'a', 'b', 'c'}");
+}
+
+#[test]
+fn sys_tuples_print_and_format_like_python() {
+    let code = r#"
+pair = (1, 2)
+print(pair, (3,), (), len(pair), pair[0], pair[1:], pair == [1, 2])
+print("%s-%s" % pair, "%s" % [1, 2])
+a, b = pair
+print(a + b, 2 in pair, pair.index(2))
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "(1, 2) (3,) () 2 1 (2,) False\n1-2 [1, 2]\n3 True 1");
+}
+
+#[test]
+fn sys_f_strings_interpolate_with_conversions_and_specs() {
+    let code = r#"
+name = "fox"
+n = 7
+w = 4
+print(f"{1+1} items")
+print(f"{name!r} {name!s} {[1, 2]} {{literal}}")
+print(f"[{n:>4}] [{n:d}] [{name:s}] [{n:>{w}}] [{n:03}]")
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(
+        out,
+        "2 items\n'fox' fox [1, 2] {literal}\n[   7] [7] [fox] [   7] [007]"
+    );
+
+    let resp = exec("x = f\"{undefined}\"");
+    assert_eq!(resp.error_kind.as_deref(), Some("NameError"));
+}
+
+#[test]
+fn sys_dict_comprehension_builds_str_keyed_dicts() {
+    let code = r#"
+print({k: 1 for k in ["a", "b"]})
+docs = [{"id": "d1", "text": "alpha"}, {"id": "d2", "text": "beta"}]
+by_id = {d["id"]: len(d["text"]) for d in docs if d["id"] != "d0"}
+print(by_id, k if False else "scoped")
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "{'a': 1, 'b': 1}\n{'d1': 5, 'd2': 4} scoped");
+
+    let resp = exec("d = {n: n for n in [1, 2]}");
+    assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+}
+
+#[test]
+fn sys_mixed_int_float_compare_and_sort_numerically() {
+    let code = r#"
+print(2 < 2.5, 3 >= 2.5, 1 == 1.0, 1 < 1.5 < 2)
+scores = [3, 0.5, 2, 1.5]
+print(sorted(scores), sorted(scores, reverse=True), max(scores), min(2, 1.5))
+print(1.0 in [1, 2], [1, 2.0] == [1.0, 2], (1, 2) == (1.0, 2), {"a": 1} == {"a": 1.0})
+print([1, 2, 3].index(2.0), [1, 1.0, 2].count(1))
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(
+        out,
+        "True True True True\n[0.5, 1.5, 2, 3] [3, 2, 1.5, 0.5] 3 1.5\nTrue True True True\n1 2"
+    );
+}
+
+#[test]
+fn sys_caesar_decode_with_ord_chr_and_modulo() {
+    let code = r#"
+cipher = "khoorzruog"
+shift = 3
+base = ord("a")
+plain = ""
+for c in cipher:
+    plain += chr((ord(c) - base - shift) % 26 + base)
+print(plain)
+print([chr((ord(c) - base + shift) % 26 + base) for c in "xyz"])
+print("".join([chr((ord(c) - base - shift) % 26 + base) for c in "abc"]))
+print(ord("A"), chr(955), ord(b"z"))
+print(-7 % 3, 7 % -3, -7 % -3, (-9223372036854775807 - 1) % -1)
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    // Decoding wraps below 'a', so `%` must take the divisor's sign like Python.
+    assert_eq!(out, "helloworld\n['a', 'b', 'c']\nxyz\n65 λ 122\n2 -2 -1 0");
+
+    let resp = exec("7 % 0");
+    assert_eq!(resp.error_kind.as_deref(), Some("ValueError"));
+
+    let resp = exec("ord('ab')");
+    assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+    let resp = exec("chr(-1)");
+    assert_eq!(resp.error_kind.as_deref(), Some("ValueError"));
+}
+
+#[test]
+fn sys_listcomp_with_nested_generators_flattens() {
+    let code = r#"
+rows = [[1, 2], [3]]
+print([x for row in rows for x in row])
+print([(i, c) for i in range(3) if i != 1 for c in "ab" if c != str(i)])
+x = "outer"
+flat = [x for row in rows for x in row]
+print(x, len(flat))
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(
+        out,
+        "[1, 2, 3]\n[(0, 'a'), (0, 'b'), (2, 'a'), (2, 'b')]\nouter 3"
+    );
+}
+
+#[test]
+fn sys_attribute_allowlist_rejects_other_methods_at_validation() {
+    let engine = ReplEngine::new(ReplConfig {
+        allowed_attributes: Some(vec!["lower".into(), "split".into()]),
+        ..ReplConfig::default()
+    });
+    let exec_code = |code: &str| {
+        engine.exec(ExecRequest {
+            context: "Alpha Beta".into(),
+            query: String::new(),
+            code: code.to_string(),
+            max_output_chars: None,
+            max_print_state_chars: None,
+            state: None,
+            seed: None,
+        })
+    };
+
+    let resp = exec_code("print(context.lower().split())");
+    assert!(resp.ok, "err={:?}", resp.error);
+    assert_eq!(resp.output, "['alpha', 'beta']");
+
+    // Rejected before anything runs, so the first print never happens.
+    let resp = exec_code("print('start')\nprint(context.replace('A', 'a'))");
+    assert!(!resp.ok);
+    assert_eq!(resp.error_kind.as_deref(), Some("ForbiddenName"));
+    assert_eq!(resp.output, "");
+
+    // The default config stays permissive.
+    let (ok, out, err) = run("print(context.replace('A', 'a'))", "Alpha", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "alpha");
+}
+
+#[test]
+fn sys_str_join_direct_and_bound() {
+    let code = r#"
+print("-".join(["a", "b", "c"]))
+words = context.split()
+sep = ", "
+glue = sep.join
+print(glue(words), "".join(("x", "y")), repr(" ".join([])))
+"#;
+    let (ok, out, err) = run(code, "alpha beta", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "a-b-c\nalpha, beta xy ''");
+
+    let resp = exec("'-'.join(['a', 1])");
+    assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+    assert!(resp
+        .error
+        .unwrap()
+        .contains("sequence item 1: expected str instance, int found"));
+}
+
+#[test]
+fn sys_advertised_builtins_and_str_methods_are_dispatched() {
+    // Argument errors are fine; a NameError means the table lists something unregistered.
+    for name in BUILTIN_FUNCTIONS {
+        let resp = exec(&format!("{name}()"));
+        assert_ne!(resp.error_kind.as_deref(), Some("NameError"), "{name}");
+    }
+    for method in STR_METHODS {
+        let resp = exec(&format!("'abc'.{method}(1, 2, 3, 4)"));
+        assert_ne!(resp.error_kind.as_deref(), Some("NameError"), "{method}");
+    }
+    let resp = exec("'abc'.no_such_method()");
+    assert_eq!(resp.error_kind.as_deref(), Some("NameError"));
+}
+
+#[test]
+fn sys_get_in_returns_default_on_missing_levels() {
+    let code = r#"
+obj = json.loads('{"a": {"b": [{"c": "deep"}, {"c": "last"}]}, "n": null}')
+print(get_in(obj, ["a", "b", 0, "c"]), get_in(obj, ("a", "b", -1, "c")))
+print(get_in(obj, ["a", "x", "c"], "missing"), get_in(obj, ["a", "b", 5], default=0))
+print(get_in(obj, ["n", "c"]), get_in(obj, ["a", "b", "c"], -1), get_in(obj, []) == obj)
+print(get_in(obj, ["a", 0], "no such key"), obj["a"][0] == obj["a"]["b"])
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    // An int step on a dict is a missing key, even though `d[0]` picks by position.
+    assert_eq!(out, "deep last\nmissing 0\nNone -1 True\nno such key True");
+
+    let resp = exec("get_in({}, 'a')");
+    assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+}
+
+#[test]
+fn sys_split_with_maxsplit() {
+    let code = r#"
+print("a:b:c".split(":", 1), "a:b:c".split(":", 0), "a:b:c".split(":", -1))
+key, value = "title: a: b".split(": ", 1)
+print(key, "|", value)
+print("  x  y z ".split(None, 1), " x y ".split(None, 0), "   ".split(None, 1), "x y".split())
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(
+        out,
+        "['a', 'b:c'] ['a:b:c'] ['a', 'b', 'c']\ntitle | a: b\n['x', 'y z '] ['x y '] [] ['x', 'y']"
+    );
+
+    let resp = exec("'a b'.split(' ', '1')");
+    assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+    let resp = exec("'a b'.split('')");
+    assert_eq!(resp.error_kind.as_deref(), Some("ValueError"));
+}
+
+#[test]
+fn sys_strip_variants_with_chars() {
+    let code = r#"
+print(repr("xxabcxx".strip("x")), repr(" a ".lstrip()), repr(" a ".rstrip()))
+print(repr("<<b>>".strip("<>")), repr("abcba".lstrip("ba")), repr("line\n".rstrip("\n")))
+trim = "..x..".rstrip
+print(repr(trim(".")), repr(" y ".strip(None)), repr("xyx".strip("")))
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "'abc' 'a ' ' a'\n'b' 'cba' 'line'\n'..x' 'y' 'xyx'");
+
+    let resp = exec("' a '.strip(1)");
+    assert_eq!(resp.error_kind.as_deref(), Some("TypeError"));
+}
+
+#[test]
+fn sys_str_format_positional_and_keyword_fields() {
+    let code = r#"
+print("{} and {}".format("a", "b"), "{x}".format(x=3))
+print("{1}-{0}-{1}".format("a", "b"), "{{{}}}".format(7), "{name!r}: {score:03d}".format(name="d1", score=7))
+line = "{:>4}|{}".format
+print(line(12, [1, None]))
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "a and b 3\nb-a-b {7} 'd1': 007\n  12|[1, None]");
+
+    for bad in [
+        "'{} {}'.format(1)",
+        "'{x}'.format(y=1)",
+        "'{} {0}'.format(1)",
+        "'{'.format()",
+    ] {
+        let resp = exec(bad);
+        assert_eq!(resp.error_kind.as_deref(), Some("ValueError"), "{bad}");
+    }
+}
+
+#[test]
+fn sys_list_extend_and_pop_in_place() {
+    let code = r#"
+xs = [1]
+xs.append(2)
+xs.extend([3, 4])
+xs.extend(("t",))
+print(xs)
+last = xs.pop()
+first = xs.pop(0)
+print(last, first, xs, xs.pop(-2), xs)
+"#;
+    let (ok, out, err) = run(code, "", "");
+    assert!(ok, "err={err:?}");
+    assert_eq!(out, "[1, 2, 3, 4, 't']\nt 1 [2, 3, 4] 3 [2, 4]");
+
+    for bad in ["xs = []\nxs.pop()", "xs = [1]\nxs.pop(5)"] {
+        let resp = exec(bad);
+        assert_eq!(resp.error_kind.as_deref(), Some("ValueError"), "{bad}");
+    }
+}
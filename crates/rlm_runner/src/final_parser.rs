@@ -1,34 +1,85 @@
 use regex::Regex;
 
+// Keep this small and deterministic; by default we only support the patterns the baseline uses.
+const FINAL_PATTERNS: [&str; 4] = [
+    // Triple-quoted (DOTALL).
+    "(?s)FINAL\\s*\\(\\s*\\\"\\\"\\\"(.*)\\\"\\\"\\\"",
+    "(?s)FINAL\\s*\\(\\s*'''(.*)'''",
+    // Single-line quoted.
+    "FINAL\\s*\\(\\s*\\\"([^\\\"]*)\\\"",
+    "FINAL\\s*\\(\\s*'([^']*)'",
+];
+
+const FINAL_VAR_PATTERN: &str = r#"FINAL_VAR\s*\(\s*(\w+)\s*\)"#;
+
+/// The textual markers that end the loop: an answer marker (`FINAL("...")`) whose first
+/// capture group is the answer, and a variable marker (`FINAL_VAR(name)`) naming a REPL
+/// variable. Embedders add patterns for models that prefer e.g. `<answer>...</answer>`.
+#[derive(Debug, Clone)]
+pub struct FinalMarkers {
+    answer: Vec<Regex>,
+    var: Vec<Regex>,
+}
+
+impl Default for FinalMarkers {
+    fn default() -> Self {
+        Self {
+            answer: FINAL_PATTERNS
+                .iter()
+                .filter_map(|p| Regex::new(p).ok())
+                .collect(),
+            var: Regex::new(FINAL_VAR_PATTERN).into_iter().collect(),
+        }
+    }
+}
+
+impl FinalMarkers {
+    /// Also accept answers matching `pattern`; capture group 1 is the answer text.
+    /// Patterns are tried in the order they were added, after the defaults.
+    pub fn with_answer_pattern(mut self, pattern: &str) -> Result<Self, regex::Error> {
+        self.answer.push(Regex::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Also accept variable references matching `pattern`; capture group 1 is the name.
+    pub fn with_var_pattern(mut self, pattern: &str) -> Result<Self, regex::Error> {
+        self.var.push(Regex::new(pattern)?);
+        Ok(self)
+    }
+
+    pub fn extract_answer(&self, response: &str) -> Option<String> {
+        self.answer.iter().find_map(|re| {
+            let c = re.captures(response)?;
+            Some(c.get(1)?.as_str().trim().to_string())
+        })
+    }
+
+    pub fn extract_var_name(&self, response: &str) -> Option<String> {
+        self.var.iter().find_map(|re| {
+            let c = re.captures(response)?;
+            Some(c.get(1)?.as_str().to_string())
+        })
+    }
+
+    /// Remove every marker occurrence, leaving only what could be REPL code.
+    pub fn strip(&self, content: &str) -> String {
+        let mut out = content.to_string();
+        for re in self.answer.iter().chain(&self.var) {
+            out = re.replace_all(&out, "").into_owned();
+        }
+        out
+    }
+}
+
 /// Extract FINAL("...") / FINAL('...') / triple-quoted variants.
 /// Matches the unofficial implementation: FINAL is "not a function", just a textual marker.
 pub fn extract_final(response: &str) -> Option<String> {
-    // Keep this small and deterministic; we only support the patterns the baseline uses.
-    let patterns = [
-        // Triple-quoted (DOTALL).
-        "(?s)FINAL\\s*\\(\\s*\\\"\\\"\\\"(.*)\\\"\\\"\\\"",
-        "(?s)FINAL\\s*\\(\\s*'''(.*)'''",
-        // Single-line quoted.
-        "FINAL\\s*\\(\\s*\\\"([^\\\"]*)\\\"",
-        "FINAL\\s*\\(\\s*'([^']*)'",
-    ];
-    for pat in patterns {
-        let re = match Regex::new(pat) {
-            Ok(r) => r,
-            Err(_) => continue,
-        };
-        if let Some(c) = re.captures(response) {
-            return Some(c.get(1)?.as_str().trim().to_string());
-        }
-    }
-    None
+    FinalMarkers::default().extract_answer(response)
 }
 
 /// Extract FINAL_VAR(name) and return the variable name.
 pub fn extract_final_var_name(response: &str) -> Option<String> {
-    let re = Regex::new(r#"FINAL_VAR\s*\(\s*(\w+)\s*\)"#).ok()?;
-    let cap = re.captures(response)?;
-    Some(cap.get(1)?.as_str().to_string())
+    FinalMarkers::default().extract_var_name(response)
 }
 
 pub fn is_final(response: &str) -> bool {
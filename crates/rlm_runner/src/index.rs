@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use python_string_repl::repl::state::StoredValue;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::retrieve::{documents_value, Document};
+
+/// Documents uploaded once through `/v1/index`, kept together with their REPL form so
+/// later retrieves skip both the upload and the JSON -> `StoredValue` conversion.
+#[derive(Debug)]
+pub struct IndexedCorpus {
+    pub documents: Vec<Document>,
+    pub repl_documents: StoredValue,
+    bytes: usize,
+}
+
+impl IndexedCorpus {
+    pub fn new(documents: Vec<Document>) -> Self {
+        let repl_documents = documents_value(&documents);
+        // Rough footprint: every string lives twice (request form and REPL form).
+        let bytes = documents
+            .iter()
+            .map(|d| {
+                let meta = d.metadata.as_ref().map_or(0, |m| m.to_string().len());
+                2 * (d.id.len() + d.text.len() + meta)
+            })
+            .sum();
+        Self {
+            documents,
+            repl_documents,
+            bytes,
+        }
+    }
+
+    /// Approximate memory held by this corpus, as counted against the store budget.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum IndexError {
+    #[error("index too large: {size} bytes exceeds budget of {budget} bytes")]
+    TooLarge { size: usize, budget: usize },
+}
+
+/// In-memory corpora keyed by index id. Entries idle longer than `ttl` are dropped, and the
+/// least recently used ones are evicted to keep the total under `max_bytes`.
+#[derive(Clone)]
+pub struct IndexStore {
+    inner: Arc<Mutex<HashMap<String, Entry>>>,
+    max_bytes: usize,
+    ttl: Duration,
+}
+
+struct Entry {
+    corpus: Arc<IndexedCorpus>,
+    last_used: Instant,
+}
+
+impl IndexStore {
+    pub fn new(max_bytes: usize, ttl: Duration) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(HashMap::new())),
+            max_bytes,
+            ttl,
+        }
+    }
+
+    pub fn insert(&self, corpus: IndexedCorpus) -> Result<String, IndexError> {
+        if corpus.bytes > self.max_bytes {
+            return Err(IndexError::TooLarge {
+                size: corpus.bytes,
+                budget: self.max_bytes,
+            });
+        }
+        let mut entries = self.lock();
+        let now = Instant::now();
+        self.evict_expired(&mut entries, now);
+        let mut used: usize = entries.values().map(|e| e.corpus.bytes).sum();
+        while used + corpus.bytes > self.max_bytes {
+            let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, e)| e.last_used)
+                .map(|(id, _)| id.clone())
+            else {
+                break;
+            };
+            if let Some(e) = entries.remove(&oldest) {
+                used -= e.corpus.bytes;
+            }
+        }
+        let id = Uuid::new_v4().to_string();
+        entries.insert(
+            id.clone(),
+            Entry {
+                corpus: Arc::new(corpus),
+                last_used: now,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Look up a corpus and refresh its TTL.
+    pub fn get(&self, id: &str) -> Option<Arc<IndexedCorpus>> {
+        let mut entries = self.lock();
+        let now = Instant::now();
+        self.evict_expired(&mut entries, now);
+        let entry = entries.get_mut(id)?;
+        entry.last_used = now;
+        Some(entry.corpus.clone())
+    }
+
+    fn evict_expired(&self, entries: &mut HashMap<String, Entry>, now: Instant) {
+        entries.retain(|_, e| now.duration_since(e.last_used) < self.ttl);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Entry>> {
+        // A panic while holding the lock can't leave the map half-updated, so keep serving.
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -1,4 +1,5 @@
 pub mod final_parser;
+pub mod index;
 pub mod llm_client;
 pub mod prompts;
 pub mod retrieve;
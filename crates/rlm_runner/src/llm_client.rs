@@ -1,10 +1,11 @@
 use std::collections::VecDeque;
+use std::sync::Arc;
 use std::time::Duration;
 
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Semaphore};
 
 #[derive(Debug, Clone, Serialize)]
 pub struct LlmMessage {
@@ -16,11 +17,19 @@ pub struct LlmMessage {
 pub struct LlmRequest {
     pub messages: Vec<LlmMessage>,
     pub timeout: Duration,
+    pub seed: Option<u64>,
+    /// Model to ask instead of the client's configured one (see `RlmLoopConfig::model_chain`).
+    pub model: Option<String>,
+    /// Sampling temperature; the loop raises it on retries (see
+    /// `RlmLoopConfig::retry_temperature_step`).
+    pub temperature: f32,
 }
 
 #[derive(Debug, Clone)]
 pub struct LlmResponse {
     pub content: String,
+    /// Prompt plus completion tokens, when the provider reports usage.
+    pub total_tokens: Option<u64>,
 }
 
 #[derive(Debug, Error)]
@@ -31,46 +40,195 @@ pub enum LlmError {
     Http(String),
     #[error("openai error: {0}")]
     OpenAi(String),
+    /// HTTP 429. `retry_after` is the wait the provider asked for, when it said.
+    #[error("rate limited: {message}")]
+    RateLimited {
+        message: String,
+        retry_after: Option<Duration>,
+    },
     #[error("empty response")]
     EmptyResponse,
     #[error("mock responses exhausted")]
     MockExhausted,
 }
 
+impl LlmError {
+    /// How long the provider asked us to wait before retrying, if it did.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            LlmError::RateLimited { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+/// The wait a 429 response asks for: OpenAI's `retry-after-ms`, else the standard
+/// `Retry-After` (seconds), else the later of the `x-ratelimit-reset-*` windows.
+fn retry_after_from_headers(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+    };
+    let non_negative = |x: f64| (x.is_finite() && x >= 0.0).then_some(x);
+    if let Some(ms) = header("retry-after-ms").and_then(|v| v.parse().ok().and_then(non_negative)) {
+        return Some(saturating_secs(ms / 1000.0));
+    }
+    if let Some(secs) = header("retry-after").and_then(|v| v.parse().ok().and_then(non_negative)) {
+        return Some(saturating_secs(secs));
+    }
+    ["x-ratelimit-reset-requests", "x-ratelimit-reset-tokens"]
+        .into_iter()
+        .filter_map(|name| header(name).and_then(parse_reset_duration))
+        .max()
+}
+
+/// OpenAI's reset format: unit-suffixed parts such as `20ms`, `1s`, `6m0s` or `1h2m3.5s`.
+fn parse_reset_duration(s: &str) -> Option<Duration> {
+    let mut total = 0.0f64;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let num_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let value: f64 = rest[..num_len].parse().ok()?;
+        rest = &rest[num_len..];
+        let (unit_secs, unit_len) = if rest.starts_with("ms") {
+            (0.001, 2)
+        } else if rest.starts_with('h') {
+            (3600.0, 1)
+        } else if rest.starts_with('m') {
+            (60.0, 1)
+        } else if rest.starts_with('s') {
+            (1.0, 1)
+        } else {
+            return None;
+        };
+        total += value * unit_secs;
+        rest = &rest[unit_len..];
+    }
+    (total.is_finite() && !s.is_empty()).then(|| saturating_secs(total))
+}
+
+/// `Duration::from_secs_f64` for a header value, which may be absurdly large: anything past
+/// `Duration::MAX` becomes `Duration::MAX` (callers cap the wait) instead of panicking.
+fn saturating_secs(secs: f64) -> Duration {
+    Duration::try_from_secs_f64(secs).unwrap_or(Duration::MAX)
+}
+
+const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// Connection pooling for the OpenAI HTTP client. The defaults are reqwest's own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpClientConfig {
+    /// Idle keep-alive connections kept open per host.
+    pub pool_max_idle_per_host: usize,
+    /// How long an idle pooled connection is kept; `None` keeps it until the server closes it.
+    pub pool_idle_timeout: Option<Duration>,
+    /// Limit on establishing a connection; `None` leaves it to the overall request timeout.
+    pub connect_timeout: Option<Duration>,
+    /// TCP keep-alive probe interval for open connections; `None` sends no probes.
+    pub tcp_keepalive: Option<Duration>,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        Self {
+            pool_max_idle_per_host: usize::MAX,
+            pool_idle_timeout: Some(Duration::from_secs(90)),
+            connect_timeout: None,
+            tcp_keepalive: None,
+        }
+    }
+}
+
+impl HttpClientConfig {
+    /// Defaults overridden by `RUSTRLM_HTTP_POOL_MAX_IDLE_PER_HOST`,
+    /// `RUSTRLM_HTTP_POOL_IDLE_TIMEOUT_SECS`, `RUSTRLM_HTTP_CONNECT_TIMEOUT_SECS` and
+    /// `RUSTRLM_HTTP_TCP_KEEPALIVE_SECS` when set and valid.
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str) -> Option<T> {
+            std::env::var(name).ok().and_then(|v| v.trim().parse().ok())
+        }
+        let secs = |name: &str| var(name).map(Duration::from_secs);
+        let d = Self::default();
+        Self {
+            pool_max_idle_per_host: var("RUSTRLM_HTTP_POOL_MAX_IDLE_PER_HOST")
+                .unwrap_or(d.pool_max_idle_per_host),
+            pool_idle_timeout: secs("RUSTRLM_HTTP_POOL_IDLE_TIMEOUT_SECS").or(d.pool_idle_timeout),
+            connect_timeout: secs("RUSTRLM_HTTP_CONNECT_TIMEOUT_SECS").or(d.connect_timeout),
+            tcp_keepalive: secs("RUSTRLM_HTTP_TCP_KEEPALIVE_SECS").or(d.tcp_keepalive),
+        }
+    }
+}
+
 pub struct OpenAiClient {
     api_key: String,
     model: String,
+    base_url: String,
     client: Client,
 }
 
 impl OpenAiClient {
     pub fn new(api_key: String, model: String) -> Result<Self, LlmError> {
-        let client = Client::builder()
+        Self::new_with_http_config(api_key, model, HttpClientConfig::default())
+    }
+
+    /// Like `new`, with explicit connection pooling. The one `reqwest::Client` (and its pool)
+    /// is shared by every request this client makes.
+    pub fn new_with_http_config(
+        api_key: String,
+        model: String,
+        http: HttpClientConfig,
+    ) -> Result<Self, LlmError> {
+        let mut builder = Client::builder()
             .timeout(Duration::from_secs(60))
-            .build()
-            .map_err(|e| LlmError::Http(e.to_string()))?;
+            .pool_max_idle_per_host(http.pool_max_idle_per_host)
+            .pool_idle_timeout(http.pool_idle_timeout)
+            .tcp_keepalive(http.tcp_keepalive);
+        if let Some(connect_timeout) = http.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        let client = builder.build().map_err(|e| LlmError::Http(e.to_string()))?;
         Ok(Self {
             api_key,
             model,
+            base_url: OPENAI_BASE_URL.to_string(),
             client,
         })
     }
 
+    /// Point the client at an OpenAI-compatible endpoint (e.g. a proxy or a local test server).
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
     pub async fn complete(&self, req: LlmRequest) -> Result<LlmResponse, LlmError> {
         let body = OpenAiRequest {
-            model: self.model.clone(),
+            model: req.model.unwrap_or_else(|| self.model.clone()),
             messages: req.messages,
-            temperature: 0.0,
+            temperature: req.temperature,
+            seed: req.seed,
         };
         let resp = self
             .client
-            .post("https://api.openai.com/v1/chat/completions")
+            .post(format!("{}/chat/completions", self.base_url))
             .bearer_auth(&self.api_key)
             .json(&body)
             .timeout(req.timeout)
             .send()
             .await
             .map_err(|e| LlmError::Http(e.to_string()))?;
+        if resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = retry_after_from_headers(resp.headers());
+            let text = resp.text().await.unwrap_or_default();
+            return Err(LlmError::RateLimited {
+                message: format!("429 {text}"),
+                retry_after,
+            });
+        }
         if !resp.status().is_success() {
             let status = resp.status();
             let text = resp.text().await.unwrap_or_default();
@@ -86,25 +244,82 @@ impl OpenAiClient {
             .next()
             .and_then(|c| c.message.content)
             .ok_or(LlmError::EmptyResponse)?;
-        Ok(LlmResponse { content })
+        Ok(LlmResponse {
+            content,
+            total_tokens: parsed.usage.map(|u| u.total_tokens),
+        })
     }
 }
 
 pub struct MockLlm {
     responses: Mutex<VecDeque<String>>,
+    delay: Duration,
+    gate: Option<Arc<Semaphore>>,
+    failing_models: Vec<String>,
+    requests: std::sync::Mutex<Vec<LlmRequest>>,
+    tokens_per_response: Option<u64>,
 }
 
 impl MockLlm {
     pub fn new(responses: Vec<String>) -> Self {
         Self {
             responses: Mutex::new(responses.into()),
+            delay: Duration::ZERO,
+            gate: None,
+            failing_models: Vec::new(),
+            requests: std::sync::Mutex::default(),
+            tokens_per_response: None,
         }
     }
 
-    pub async fn complete(&self, _req: LlmRequest) -> Result<LlmResponse, LlmError> {
+    /// Fail every request addressed to one of `models` (without consuming a response),
+    /// to simulate an unavailable model in a fallback chain.
+    pub fn with_failing_models(mut self, models: Vec<String>) -> Self {
+        self.failing_models = models;
+        self
+    }
+
+    /// Sleep before answering each request, to simulate a slow provider.
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Hold each request until `gate` has a permit (returned right after), so a test can
+    /// keep requests in flight for as long as it needs: open it with `add_permits(1)`.
+    pub fn with_gate(mut self, gate: Arc<Semaphore>) -> Self {
+        self.gate = Some(gate);
+        self
+    }
+
+    /// Report `tokens` of usage with every response, to exercise token budgets.
+    pub fn with_tokens_per_response(mut self, tokens: u64) -> Self {
+        self.tokens_per_response = Some(tokens);
+        self
+    }
+
+    /// Every request received so far, including ones that failed.
+    pub fn requests(&self) -> Vec<LlmRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub async fn complete(&self, req: LlmRequest) -> Result<LlmResponse, LlmError> {
+        self.requests.lock().unwrap().push(req.clone());
+        if let Some(gate) = &self.gate {
+            drop(gate.acquire().await);
+        }
+        if !self.delay.is_zero() {
+            tokio::time::sleep(self.delay).await;
+        }
+        if let Some(model) = req.model.filter(|m| self.failing_models.contains(m)) {
+            return Err(LlmError::Http(format!("mock model unavailable: {model}")));
+        }
         let mut guard = self.responses.lock().await;
         let content = guard.pop_front().ok_or(LlmError::MockExhausted)?;
-        Ok(LlmResponse { content })
+        Ok(LlmResponse {
+            content,
+            total_tokens: self.tokens_per_response,
+        })
     }
 }
 
@@ -127,11 +342,20 @@ struct OpenAiRequest {
     model: String,
     messages: Vec<LlmMessage>,
     temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
 }
 
 #[derive(Debug, Deserialize)]
 struct OpenAiResponse {
     choices: Vec<OpenAiChoice>,
+    #[serde(default)]
+    usage: Option<OpenAiUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAiUsage {
+    total_tokens: u64,
 }
 
 #[derive(Debug, Deserialize)]
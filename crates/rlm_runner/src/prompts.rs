@@ -1,4 +1,7 @@
+use python_string_repl::repl::{BUILTIN_FUNCTIONS, MODULE_NAMES, STR_METHODS};
+
 pub fn retrieve_system_prompt() -> String {
+    let capabilities = capabilities_prompt();
     [
         "Start in Phase 1.",
         "Phase 1 response MUST be Python code only (no FINAL/FINAL_VAR).",
@@ -9,6 +12,8 @@ pub fn retrieve_system_prompt() -> String {
         "You MUST execute Python code in the REPL to inspect documents before answering.",
         "Use the REPL variables: query, documents, top_k, max_chunk_chars, min_score.",
         "documents is a list of dicts with id/text/metadata.",
+        "Read fields with d[\"text\"] or d.get(\"id\", default); pluck(documents, \"id\") returns one field from every dict.",
+        "For optional keys prefer one lookup: v = d.get(k) then check v is None, instead of if k in d: v = d[k].",
         "",
         "Two-phase protocol (avoid conflicting instructions):",
         "- Phase 1 (before any REPL_OUTPUT): respond with ONLY Python code to run in the REPL. Do NOT output FINAL/FINAL_VAR yet.",
@@ -17,10 +22,12 @@ pub fn retrieve_system_prompt() -> String {
         "- rank_documents(...) prints a list of dicts containing BOTH keys: id and doc_id (they are the same). Use the documents' id values.",
         "",
         "Rules:",
-        "- Do NOT use: import (optional/no-op), type(), while, with, class, lambda, globals/locals/vars/getattr, dunder names.",
+        "- Do NOT use: import (optional/no-op), type(), with, class, lambda, globals/locals/vars/getattr, dunder names.",
         "- Do NOT use dict literals like {\"a\":1} or {}. Use json.loads(...) if you need dict/list literals.",
         "- Prefer: assignments, if, for-loops over lists/strings, try/except Exception, list literals, list comprehension (simple), len/print/max, rank_documents(query, documents, top_k).",
-        "- Avoid floats and division (/). Use integer heuristics.",
+        "- / divides into a float (x / 0 is an error); percent(part, whole) and ratio_bps(part, whole) return integer ratios (0 when whole is 0).",
+        "",
+        &capabilities,
         "",
         "If you get a REPL_ERROR, your next assistant message must be ONLY corrected Python code (no markdown fences, no explanations).",
         "If you return FINAL before using the REPL, the response will be rejected; switch back to Phase 1.",
@@ -33,6 +40,18 @@ pub fn retrieve_system_prompt() -> String {
     .join("\n")
 }
 
+/// The REPL surface as the interpreter registers it (builtins, `str` methods, pre-imported
+/// modules), so the prompt can't advertise something the REPL lacks or omit a new builtin.
+pub fn capabilities_prompt() -> String {
+    [
+        "Available in the REPL:".to_string(),
+        format!("- builtins: {}", BUILTIN_FUNCTIONS.join(", ")),
+        format!("- str methods: {}", STR_METHODS.join(", ")),
+        format!("- modules (already imported): {}", MODULE_NAMES.join(", ")),
+    ]
+    .join("\n")
+}
+
 pub fn retrieve_user_prompt(query: &str) -> String {
     format!(
         "query: {query}\nPHASE 1: output ONLY Python code (no FINAL). Use REPL to inspect documents."
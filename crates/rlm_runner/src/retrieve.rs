@@ -1,22 +1,36 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 
 use python_string_repl::repl::state::{ReplState, StoredValue};
 use python_string_repl::repl::{ReplConfig, ReplEngine};
+use python_string_repl::text::{
+    casefold, casefold_with_offsets, original_range, snap_window, Boundary,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
 use uuid::Uuid;
 
+use crate::index::IndexedCorpus;
 use crate::llm_client::{LlmClient, LlmError};
 use crate::prompts::{repair_json_prompt, retrieve_system_prompt, retrieve_user_prompt};
-use crate::rlm_loop::{run_rlm_loop, RlmLoopConfig};
+use crate::rlm_loop::{run_rlm_loop, RlmLoopConfig, RlmLoopResult, TranscriptEntry};
 
 #[derive(Debug, Deserialize)]
 pub struct RetrieveRequest {
     pub query: String,
+    /// Inline documents; leave empty when querying a corpus uploaded via `/v1/index`.
+    #[serde(default)]
     pub documents: Vec<Document>,
+    /// Id returned by `/v1/index`, used instead of inline `documents`.
+    #[serde(default)]
+    pub index_id: Option<String>,
     #[serde(default)]
     pub options: Option<RetrieveOptions>,
+    /// Returned as the response `trace_id`; a random one is generated when omitted. Set it
+    /// (with a fixed seed) when identical requests must produce byte-identical responses.
+    #[serde(default)]
+    pub trace_id: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -31,12 +45,66 @@ pub struct Document {
 pub struct RetrieveOptions {
     pub top_k: Option<usize>,
     pub max_chunk_chars: Option<usize>,
+    // Limit for a located model snippet; defaults to `max_chunk_chars`, which still
+    // bounds the document fallback when the snippet isn't found.
+    #[serde(default)]
+    pub max_snippet_chars: Option<usize>,
     pub min_score: Option<f64>,
     pub include_spans: Option<bool>,
     // When LLM is enabled, the default is false (so failures are visible).
-    // When LLM is disabled, we always use deterministic retrieval.
+    // When LLM is disabled, the default is true (deterministic retrieval).
     #[serde(default)]
     pub use_fallback: Option<bool>,
+    // Attach the per-iteration model/REPL transcript (capped by `max_transcript_chars`).
+    #[serde(default)]
+    pub include_transcript: Option<bool>,
+    // Also score each result with the deterministic fallback scorer, for auditing the model.
+    #[serde(default)]
+    pub include_lexical_score: Option<bool>,
+    // Report `status` so clients can tell "no results" apart from "pipeline failed".
+    #[serde(default)]
+    pub include_status: Option<bool>,
+    // Per-request loop budget; clamped to `RetrieveContext::max_iterations_ceiling`.
+    #[serde(default)]
+    pub max_iterations: Option<usize>,
+    // Stop the loop once the LLM's reported usage reaches this many tokens.
+    #[serde(default)]
+    pub max_total_tokens: Option<u64>,
+    #[serde(default)]
+    pub mode: Option<RetrieveMode>,
+    // Rescale returned scores across the result set (after `min_score` filtering).
+    #[serde(default)]
+    pub normalize: Option<ScoreNormalization>,
+    // Snap lexical snippets to whole words/lines (`"char"` keeps exact char windows).
+    #[serde(default)]
+    pub snippet_boundary: Option<Boundary>,
+    // Drop results whose text repeats a higher-scoring result's (case/whitespace-insensitive).
+    #[serde(default)]
+    pub dedup_snippets: Option<bool>,
+    // Expose only the N lexically best-matching documents to the REPL (fallback still
+    // scores every document).
+    #[serde(default)]
+    pub max_repl_documents: Option<usize>,
+    // Attach the variables left in the REPL when the loop ended (without `documents`),
+    // within the same character budget as the transcript.
+    #[serde(default)]
+    pub include_state: Option<bool>,
+    // Match query terms with exact case in lexical scoring and snippets (default: caseless).
+    #[serde(default)]
+    pub case_sensitive: Option<bool>,
+}
+
+/// Which pipeline a request runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RetrieveMode {
+    /// The LLM loop only: lexical fallback is off unless `use_fallback` turns it on.
+    Llm,
+    /// Skip the LLM loop and score documents lexically (no model calls).
+    Lexical,
+    /// The LLM loop, falling back to lexical scoring when no LLM is configured.
+    #[default]
+    Auto,
 }
 
 #[derive(Debug, Serialize)]
@@ -44,6 +112,108 @@ pub struct RetrieveResponse {
     pub trace_id: String,
     pub results: Vec<RetrieveResult>,
     pub warnings: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<RetrieveStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transcript: Option<Vec<TranscriptEntry>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<ReplState>,
+}
+
+/// How `score` values are rescaled relative to each other before being returned.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScoreNormalization {
+    /// Scores as produced (clamped to [0, 1]).
+    #[default]
+    None,
+    /// Divide by the top score, so the best result is 1.0.
+    Max,
+    /// Softmax over the result set, so scores sum to 1.0.
+    Softmax,
+}
+
+/// Outcome of the LLM pipeline, reported when `include_status` is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetrieveStatus {
+    Ok,
+    /// The model produced no usable FINAL payload and fallback was disabled.
+    LlmFailed,
+}
+
+/// Last line of an NDJSON response: everything in `RetrieveResponse` except the results.
+#[derive(Debug, Serialize)]
+struct NdjsonTrailer<'a> {
+    trace_id: &'a str,
+    warnings: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<RetrieveStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transcript: Option<&'a [TranscriptEntry]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state: Option<&'a ReplState>,
+}
+
+impl RetrieveResponse {
+    /// Shrink the response until its JSON form fits in `max_bytes`: drop the transcript and
+    /// state, then cut every result `text` to a common length, then drop trailing results. Adds a
+    /// `response_truncated` warning when anything was removed.
+    pub fn fit_to_size(&mut self, max_bytes: usize) {
+        if self.json_len() <= max_bytes {
+            return;
+        }
+        self.warnings.push("response_truncated".to_string());
+        self.transcript = None;
+        self.state = None;
+        if self.json_len() <= max_bytes {
+            return;
+        }
+        let full: Vec<String> = self.results.iter().map(|r| r.text.clone()).collect();
+        let longest = full.iter().map(|t| t.chars().count()).max().unwrap_or(0);
+        // Largest common text length that fits (0 when even empty texts are too big).
+        let (mut lo, mut hi) = (0usize, longest);
+        while lo < hi {
+            let mid = (lo + hi).div_ceil(2);
+            self.cut_texts(&full, mid);
+            if self.json_len() <= max_bytes {
+                lo = mid;
+            } else {
+                hi = mid - 1;
+            }
+        }
+        self.cut_texts(&full, lo);
+        while self.json_len() > max_bytes && self.results.pop().is_some() {}
+    }
+
+    fn cut_texts(&mut self, full: &[String], max_chars: usize) {
+        for (r, text) in self.results.iter_mut().zip(full) {
+            r.text = text.chars().take(max_chars).collect();
+        }
+    }
+
+    fn json_len(&self) -> usize {
+        serde_json::to_vec(self).map_or(0, |v| v.len())
+    }
+
+    /// One JSON object per line: each result, then a trailer with trace_id and warnings.
+    pub fn to_ndjson(&self) -> String {
+        let mut out = String::new();
+        for r in &self.results {
+            out.push_str(&serde_json::to_string(r).unwrap_or_default());
+            out.push('\n');
+        }
+        let trailer = NdjsonTrailer {
+            trace_id: &self.trace_id,
+            warnings: &self.warnings,
+            status: self.status,
+            transcript: self.transcript.as_deref(),
+            state: self.state.as_ref(),
+        };
+        out.push_str(&serde_json::to_string(&trailer).unwrap_or_default());
+        out.push('\n');
+        out
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -53,6 +223,8 @@ pub struct RetrieveResult {
     pub text: String,
     pub metadata: Option<serde_json::Value>,
     pub spans: Vec<Span>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lexical_score: Option<f64>,
 }
 
 #[derive(Debug, Serialize)]
@@ -67,6 +239,47 @@ pub struct RetrieveContext {
     pub repl: Arc<ReplEngine>,
     pub rlm: RlmLoopConfig,
     pub max_json_repair: usize,
+    pub max_transcript_chars: usize,
+    /// Upper bound for `RetrieveOptions::max_iterations`; `rlm.max_iterations` is the default.
+    pub max_iterations_ceiling: usize,
+    pub defaults: RetrieveDefaults,
+}
+
+/// Deployment-wide values for `RetrieveOptions` fields a request leaves out.
+#[derive(Debug, Clone)]
+pub struct RetrieveDefaults {
+    pub top_k: usize,
+    pub max_chunk_chars: usize,
+    pub min_score: f64,
+    pub include_spans: bool,
+}
+
+impl Default for RetrieveDefaults {
+    fn default() -> Self {
+        Self {
+            top_k: 5,
+            max_chunk_chars: 800,
+            min_score: 0.0,
+            include_spans: true,
+        }
+    }
+}
+
+impl RetrieveDefaults {
+    /// Defaults overridden by `RUSTRLM_DEFAULT_TOP_K`, `RUSTRLM_DEFAULT_MAX_CHUNK_CHARS`,
+    /// `RUSTRLM_DEFAULT_MIN_SCORE` and `RUSTRLM_DEFAULT_INCLUDE_SPANS` when set and valid.
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str) -> Option<T> {
+            std::env::var(name).ok().and_then(|v| v.trim().parse().ok())
+        }
+        let d = Self::default();
+        Self {
+            top_k: var("RUSTRLM_DEFAULT_TOP_K").unwrap_or(d.top_k),
+            max_chunk_chars: var("RUSTRLM_DEFAULT_MAX_CHUNK_CHARS").unwrap_or(d.max_chunk_chars),
+            min_score: var("RUSTRLM_DEFAULT_MIN_SCORE").unwrap_or(d.min_score),
+            include_spans: var("RUSTRLM_DEFAULT_INCLUDE_SPANS").unwrap_or(d.include_spans),
+        }
+    }
 }
 
 impl RetrieveContext {
@@ -76,52 +289,189 @@ impl RetrieveContext {
             repl: Arc::new(ReplEngine::new(ReplConfig::default())),
             rlm: RlmLoopConfig::default(),
             max_json_repair: 1,
+            max_transcript_chars: 200_000,
+            max_iterations_ceiling: 50,
+            defaults: RetrieveDefaults::default(),
         }
     }
 }
 
+/// Per-request settings resolved from `RetrieveOptions` and their defaults.
+struct RetrieveSettings {
+    top_k: usize,
+    max_chunk_chars: usize,
+    max_snippet_chars: usize,
+    min_score: f64,
+    include_spans: bool,
+    use_fallback: bool,
+    snippet_boundary: Boundary,
+    case_sensitive: bool,
+}
+
 pub async fn retrieve(req: &RetrieveRequest, ctx: &RetrieveContext) -> RetrieveResponse {
-    let trace_id = Uuid::new_v4().to_string();
+    retrieve_over(req, &req.documents, documents_value(&req.documents), ctx).await
+}
+
+/// Like `retrieve`, but over a corpus previously stored with `/v1/index`; `req.documents`
+/// is ignored.
+pub async fn retrieve_indexed(
+    req: &RetrieveRequest,
+    corpus: &IndexedCorpus,
+    ctx: &RetrieveContext,
+) -> RetrieveResponse {
+    retrieve_over(req, &corpus.documents, corpus.repl_documents.clone(), ctx).await
+}
+
+async fn retrieve_over(
+    req: &RetrieveRequest,
+    docs: &[Document],
+    repl_documents: StoredValue,
+    ctx: &RetrieveContext,
+) -> RetrieveResponse {
+    let trace_id = req
+        .trace_id
+        .clone()
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
     let opts = req.options.as_ref();
-    let top_k = opts.and_then(|o| o.top_k).unwrap_or(5);
-    let max_chunk_chars = opts.and_then(|o| o.max_chunk_chars).unwrap_or(800);
-    let min_score = opts.and_then(|o| o.min_score).unwrap_or(0.0);
-    let include_spans = opts.and_then(|o| o.include_spans).unwrap_or(true);
-    let use_fallback_opt = opts.and_then(|o| o.use_fallback);
+    let include_transcript = opts.and_then(|o| o.include_transcript).unwrap_or(false);
+    let llm_enabled = !matches!(ctx.llm.as_ref(), crate::llm_client::LlmClient::Mock(_));
+    let mode = opts.and_then(|o| o.mode).unwrap_or_default();
+    let defaults = &ctx.defaults;
+    let max_chunk_chars = opts
+        .and_then(|o| o.max_chunk_chars)
+        .unwrap_or(defaults.max_chunk_chars);
+    let settings = RetrieveSettings {
+        top_k: opts.and_then(|o| o.top_k).unwrap_or(defaults.top_k),
+        max_chunk_chars,
+        max_snippet_chars: opts
+            .and_then(|o| o.max_snippet_chars)
+            .unwrap_or(max_chunk_chars),
+        min_score: opts.and_then(|o| o.min_score).unwrap_or(defaults.min_score),
+        include_spans: opts
+            .and_then(|o| o.include_spans)
+            .unwrap_or(defaults.include_spans),
+        use_fallback: opts
+            .and_then(|o| o.use_fallback)
+            .unwrap_or(mode != RetrieveMode::Llm && !llm_enabled),
+        snippet_boundary: opts.and_then(|o| o.snippet_boundary).unwrap_or_default(),
+        case_sensitive: opts.and_then(|o| o.case_sensitive).unwrap_or(false),
+    };
 
     let mut warnings = Vec::new();
     if req.query.trim().is_empty() {
         warnings.push("query_empty".to_string());
     }
-    if req.documents.is_empty() {
+    if docs.is_empty() {
         warnings.push("documents_empty".to_string());
     }
 
-    let state = build_repl_state(req, top_k, max_chunk_chars, min_score);
-    let loop_result = run_rlm_loop(
-        ctx.llm.as_ref(),
-        ctx.repl.as_ref(),
-        &retrieve_system_prompt(),
-        &retrieve_user_prompt(&req.query),
-        &req.query,
-        state,
-        &ctx.rlm,
-    )
-    .await;
-    warnings.extend(loop_result.warnings);
-    warnings.push(format!("debug_rlm_iterations: {}", loop_result.iterations));
-    if let Some(err) = loop_result.last_repl_error.as_ref() {
-        warnings.push(format!("debug_last_repl_error: {}", truncate_log(err, 200)));
+    let (mut results, status, loop_transcript, loop_state) = if mode == RetrieveMode::Lexical {
+        let (results, extra) = fallback_retrieve(&req.query, docs, &settings);
+        warnings.push("mode_lexical".to_string());
+        warnings.extend(extra);
+        (results, RetrieveStatus::Ok, Vec::new(), ReplState::new())
+    } else {
+        let repl_documents = match opts.and_then(|o| o.max_repl_documents) {
+            Some(n) if n < docs.len() => {
+                warnings.push(format!("repl_documents_capped: {n} of {}", docs.len()));
+                documents_value(
+                    prerank(&req.query, docs, settings.case_sensitive)
+                        .into_iter()
+                        .take(n),
+                )
+            }
+            _ => repl_documents,
+        };
+        let state = build_repl_state(repl_documents, &settings);
+        let mut rlm = ctx.rlm.clone();
+        if let Some(n) = opts.and_then(|o| o.max_iterations) {
+            rlm.max_iterations = n.clamp(1, ctx.max_iterations_ceiling.max(1));
+        }
+        if let Some(max) = opts.and_then(|o| o.max_total_tokens) {
+            rlm.max_total_tokens = Some(max);
+        }
+        let loop_result = run_rlm_loop(
+            ctx.llm.as_ref(),
+            ctx.repl.as_ref(),
+            &retrieve_system_prompt(),
+            &retrieve_user_prompt(&req.query),
+            &req.query,
+            state,
+            &rlm,
+        )
+        .await;
+        warnings.extend(loop_result.warnings.iter().cloned());
+        warnings.push(format!("debug_rlm_iterations: {}", loop_result.iterations));
+        if loop_result.total_tokens > 0 {
+            warnings.push(format!(
+                "debug_llm_total_tokens: {}",
+                loop_result.total_tokens
+            ));
+        }
+        if let Some(err) = loop_result.last_repl_error.as_ref() {
+            warnings.push(format!("debug_last_repl_error: {}", truncate_log(err, 200)));
+        }
+
+        let (results, status) =
+            llm_results(req, docs, ctx, &settings, &loop_result, &mut warnings).await;
+        (results, status, loop_result.transcript, loop_result.state)
+    };
+    if opts.and_then(|o| o.dedup_snippets).unwrap_or(false) {
+        warnings.extend(dedup_snippets(&mut results));
+    }
+    normalize_scores(
+        &mut results,
+        opts.and_then(|o| o.normalize).unwrap_or_default(),
+    );
+    if opts.and_then(|o| o.include_lexical_score).unwrap_or(false) {
+        add_lexical_scores(&req.query, docs, settings.case_sensitive, &mut results);
     }
 
-    let llm_enabled = !matches!(ctx.llm.as_ref(), crate::llm_client::LlmClient::Mock(_));
-    let use_fallback = if llm_enabled {
-        use_fallback_opt.unwrap_or(false)
+    let transcript = if include_transcript {
+        let (entries, truncated) = cap_transcript(&loop_transcript, ctx.max_transcript_chars);
+        if truncated {
+            warnings.push("transcript_truncated".to_string());
+        }
+        Some(entries)
     } else {
-        true
+        None
     };
 
-    let Some(final_text) = loop_result.final_text else {
+    let state = if opts.and_then(|o| o.include_state).unwrap_or(false) {
+        let (state, truncated) = cap_state(loop_state, ctx.max_transcript_chars);
+        if truncated {
+            warnings.push("state_truncated".to_string());
+        }
+        Some(state)
+    } else {
+        None
+    };
+
+    RetrieveResponse {
+        trace_id,
+        results,
+        warnings,
+        status: opts
+            .and_then(|o| o.include_status)
+            .unwrap_or(false)
+            .then_some(status),
+        transcript,
+        state,
+    }
+}
+
+/// Turn the loop's FINAL payload into results, falling back to lexical retrieval on failure
+/// when `settings.use_fallback` allows it. The status is `LlmFailed` only when the model
+/// failed and nothing stood in for it.
+async fn llm_results(
+    req: &RetrieveRequest,
+    docs: &[Document],
+    ctx: &RetrieveContext,
+    settings: &RetrieveSettings,
+    loop_result: &RlmLoopResult,
+    warnings: &mut Vec<String>,
+) -> (Vec<RetrieveResult>, RetrieveStatus) {
+    let Some(final_text) = loop_result.final_text.as_ref() else {
         if let Some(last) = loop_result.last_response.as_ref() {
             eprintln!(
                 "[retrieve] final_not_found last_response={}",
@@ -132,36 +482,26 @@ pub async fn retrieve(req: &RetrieveRequest, ctx: &RetrieveContext) -> RetrieveR
             warnings.push(format!("debug_last_repl_error: {}", truncate_log(err, 300)));
         }
         warnings.push("llm_failed: final_not_found".to_string());
-        if use_fallback {
-            let (results, extra) =
-                fallback_retrieve(req, top_k, max_chunk_chars, min_score, include_spans);
-            warnings.push("fallback_used: llm_final_not_found".to_string());
-            warnings.extend(extra);
-            return RetrieveResponse {
-                trace_id,
-                results,
-                warnings,
-            };
-        } else {
-            return RetrieveResponse {
-                trace_id,
-                results: Vec::new(),
-                warnings,
-            };
+        if !settings.use_fallback {
+            return (Vec::new(), RetrieveStatus::LlmFailed);
         }
+        let (results, extra) = fallback_retrieve(&req.query, docs, settings);
+        warnings.push("fallback_used: llm_final_not_found".to_string());
+        warnings.extend(extra);
+        return (results, RetrieveStatus::Ok);
     };
 
-    let payload = match parse_llm_payload(&final_text) {
+    let payload = match parse_llm_payload(final_text) {
         Ok(p) => p,
         Err(e) => {
             warnings.push(format!("llm_json_parse_failed: {e}"));
             eprintln!(
                 "[retrieve] parse_failed final_text={}",
-                truncate_log(&final_text, 1200)
+                truncate_log(final_text, 1200)
             );
             let mut repaired = None;
             for _ in 0..ctx.max_json_repair {
-                match repair_json_with_llm(ctx, &final_text).await {
+                match repair_json_with_llm(ctx, final_text).await {
                     Ok(Some(fixed)) => match parse_llm_payload(&fixed) {
                         Ok(p) => {
                             repaired = Some(p);
@@ -177,42 +517,20 @@ pub async fn retrieve(req: &RetrieveRequest, ctx: &RetrieveContext) -> RetrieveR
                 Some(p) => p,
                 None => {
                     warnings.push("llm_failed: json_parse_failed".to_string());
-                    if use_fallback {
-                        let (results, extra) = fallback_retrieve(
-                            req,
-                            top_k,
-                            max_chunk_chars,
-                            min_score,
-                            include_spans,
-                        );
-                        warnings.push("fallback_used: llm_json_parse_failed".to_string());
-                        warnings.extend(extra);
-                        return RetrieveResponse {
-                            trace_id,
-                            results,
-                            warnings,
-                        };
-                    } else {
-                        return RetrieveResponse {
-                            trace_id,
-                            results: Vec::new(),
-                            warnings,
-                        };
+                    if !settings.use_fallback {
+                        return (Vec::new(), RetrieveStatus::LlmFailed);
                     }
+                    let (results, extra) = fallback_retrieve(&req.query, docs, settings);
+                    warnings.push("fallback_used: llm_json_parse_failed".to_string());
+                    warnings.extend(extra);
+                    return (results, RetrieveStatus::Ok);
                 }
             }
         }
     };
 
     warnings.extend(payload.warnings.iter().cloned());
-    let (results, extra) = build_results(
-        &payload.results,
-        &req.documents,
-        top_k,
-        max_chunk_chars,
-        min_score,
-        include_spans,
-    );
+    let (results, extra) = build_results(&payload.results, docs, settings);
     warnings.extend(extra);
 
     if results.is_empty() {
@@ -223,26 +541,78 @@ pub async fn retrieve(req: &RetrieveRequest, ctx: &RetrieveContext) -> RetrieveR
             );
         }
         warnings.push("llm_failed: empty_results".to_string());
-        if use_fallback {
-            let (fb, extra) =
-                fallback_retrieve(req, top_k, max_chunk_chars, min_score, include_spans);
+        if settings.use_fallback {
+            let (fb, extra) = fallback_retrieve(&req.query, docs, settings);
             if !fb.is_empty() {
                 warnings.push("fallback_used: empty_results".to_string());
                 warnings.extend(extra);
-                return RetrieveResponse {
-                    trace_id,
-                    results: fb,
-                    warnings,
-                };
+                return (fb, RetrieveStatus::Ok);
             }
         }
     }
 
-    RetrieveResponse {
-        trace_id,
-        results,
-        warnings,
+    (results, RetrieveStatus::Ok)
+}
+
+/// Bound the transcript returned to clients by total characters across all entries.
+/// Returns the (possibly shortened) entries and whether anything was cut.
+fn cap_transcript(entries: &[TranscriptEntry], max_chars: usize) -> (Vec<TranscriptEntry>, bool) {
+    let mut budget = max_chars;
+    let mut truncated = false;
+    let mut out = Vec::new();
+    for entry in entries {
+        if budget == 0 {
+            truncated = true;
+            break;
+        }
+        let response = take_within_budget(&entry.response, &mut budget, &mut truncated);
+        let code = entry
+            .code
+            .as_deref()
+            .map(|c| take_within_budget(c, &mut budget, &mut truncated));
+        let feedback = entry
+            .feedback
+            .as_deref()
+            .map(|f| take_within_budget(f, &mut budget, &mut truncated));
+        out.push(TranscriptEntry {
+            iteration: entry.iteration,
+            response,
+            code,
+            repl_ok: entry.repl_ok,
+            feedback,
+        });
     }
+    (out, truncated)
+}
+
+/// The final REPL variables for clients, minus the injected `documents` list. Variables are
+/// kept in name order while their JSON fits in `max_chars`; larger ones are left out.
+/// Returns the kept variables and whether any were left out.
+fn cap_state(mut state: ReplState, max_chars: usize) -> (ReplState, bool) {
+    state.remove("documents");
+    let mut budget = max_chars;
+    let mut truncated = false;
+    state.retain(|_, v| {
+        let len = serde_json::to_string(v).map_or(usize::MAX, |j| j.chars().count());
+        if len <= budget {
+            budget -= len;
+            true
+        } else {
+            truncated = true;
+            false
+        }
+    });
+    (state, truncated)
+}
+
+fn take_within_budget(text: &str, budget: &mut usize, truncated: &mut bool) -> String {
+    let kept: String = text.chars().take(*budget).collect();
+    let kept_chars = kept.chars().count();
+    if kept_chars < text.chars().count() {
+        *truncated = true;
+    }
+    *budget -= kept_chars;
+    kept
 }
 
 #[derive(Debug)]
@@ -323,6 +693,9 @@ async fn repair_json_with_llm(
         .complete(crate::llm_client::LlmRequest {
             messages,
             timeout: ctx.rlm.request_timeout,
+            seed: ctx.rlm.seed,
+            model: ctx.rlm.model_chain.first().cloned(),
+            temperature: 0.0,
         })
         .await?;
     Ok(Some(resp.content))
@@ -331,10 +704,7 @@ async fn repair_json_with_llm(
 fn build_results(
     items: &[LlmResult],
     docs: &[Document],
-    top_k: usize,
-    max_chunk_chars: usize,
-    min_score: f64,
-    include_spans: bool,
+    settings: &RetrieveSettings,
 ) -> (Vec<RetrieveResult>, Vec<String>) {
     let mut warnings = Vec::new();
     let mut results = Vec::new();
@@ -343,7 +713,7 @@ fn build_results(
         by_id.insert(doc.id.as_str(), doc);
     }
 
-    for item in items.iter().take(top_k) {
+    for item in items.iter().take(settings.top_k) {
         let Some(doc) = by_id.get(item.doc_id.as_str()) else {
             warnings.push(format!("doc_id_not_found: {}", item.doc_id));
             continue;
@@ -353,16 +723,12 @@ fn build_results(
         if raw_score != score {
             warnings.push(format!("score_clamped: {}", item.doc_id));
         }
-        if score < min_score {
+        if score < settings.min_score {
             continue;
         }
 
-        let (text, spans, span_warn) = text_and_spans(
-            doc.text.as_str(),
-            item.snippet.as_deref(),
-            max_chunk_chars,
-            include_spans,
-        );
+        let (text, spans, span_warn) =
+            text_and_spans(doc.text.as_str(), item.snippet.as_deref(), settings);
         if let Some(w) = span_warn {
             warnings.push(format!("snippet_not_found: {}", w));
         }
@@ -373,6 +739,7 @@ fn build_results(
             text,
             metadata: doc.metadata.clone(),
             spans,
+            lexical_score: None,
         });
     }
 
@@ -382,13 +749,17 @@ fn build_results(
 fn text_and_spans(
     doc_text: &str,
     snippet: Option<&str>,
-    max_chunk_chars: usize,
-    include_spans: bool,
+    settings: &RetrieveSettings,
 ) -> (String, Vec<Span>, Option<String>) {
     if let Some(snippet) = snippet {
-        if doc_text.contains(snippet) {
-            let text = truncate_chars(snippet, max_chunk_chars);
-            let spans = if include_spans && !text.is_empty() {
+        let located = if doc_text.contains(snippet) {
+            Some(snippet)
+        } else {
+            find_whitespace_normalized(doc_text, snippet)
+        };
+        if let Some(located) = located {
+            let text = truncate_chars(located, settings.max_snippet_chars);
+            let spans = if settings.include_spans && !text.is_empty() {
                 vec![Span {
                     start: 0,
                     end: text.chars().count(),
@@ -398,18 +769,100 @@ fn text_and_spans(
             };
             return (text, spans, None);
         }
-        let fallback = truncate_chars(doc_text, max_chunk_chars);
+        let fallback = truncate_chars(doc_text, settings.max_chunk_chars);
         return (fallback, Vec::new(), Some(snippet.to_string()));
     }
-    let fallback = truncate_chars(doc_text, max_chunk_chars);
+    let fallback = truncate_chars(doc_text, settings.max_chunk_chars);
     (fallback, Vec::new(), Some("missing_snippet".to_string()))
 }
 
+/// Find `snippet` in `doc_text` treating any run of whitespace as equal to any other,
+/// so a model that reflowed lines still matches. Returns the original document substring.
+fn find_whitespace_normalized<'a>(doc_text: &'a str, snippet: &str) -> Option<&'a str> {
+    let needle = snippet.split_whitespace().collect::<Vec<_>>().join(" ");
+    if needle.is_empty() {
+        return None;
+    }
+    // Normalized haystack plus, for each of its bytes, the byte offset of the source char.
+    let mut haystack = String::with_capacity(doc_text.len());
+    let mut offsets = Vec::with_capacity(doc_text.len());
+    let mut in_ws = false;
+    for (i, c) in doc_text.char_indices() {
+        if c.is_whitespace() {
+            if !in_ws {
+                haystack.push(' ');
+                offsets.push(i);
+            }
+            in_ws = true;
+            continue;
+        }
+        in_ws = false;
+        haystack.push(c);
+        offsets.extend(std::iter::repeat_n(i, c.len_utf8()));
+    }
+    let pos = haystack.find(&needle)?;
+    let start = offsets[pos];
+    let last = offsets[pos + needle.len() - 1];
+    let end = last + doc_text[last..].chars().next().map_or(0, char::len_utf8);
+    Some(&doc_text[start..end])
+}
+
 fn truncate_chars(text: &str, max_chars: usize) -> String {
     let max_chars = max_chars.max(1);
     text.chars().take(max_chars).collect()
 }
 
+/// Keep one result per normalized text: the highest-scoring one (the earliest on ties),
+/// in its original position. Returns a `snippet_deduplicated` warning per dropped result.
+fn dedup_snippets(results: &mut Vec<RetrieveResult>) -> Vec<String> {
+    let key = |r: &RetrieveResult| {
+        r.text
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase()
+    };
+    let mut best: HashMap<String, usize> = HashMap::new();
+    for (i, r) in results.iter().enumerate() {
+        let slot = best.entry(key(r)).or_insert(i);
+        if r.score > results[*slot].score {
+            *slot = i;
+        }
+    }
+    let mut warnings = Vec::new();
+    let mut i = 0;
+    results.retain(|r| {
+        let keep = best[&key(r)] == i;
+        if !keep {
+            warnings.push(format!("snippet_deduplicated: {}", r.doc_id));
+        }
+        i += 1;
+        keep
+    });
+    warnings
+}
+
+fn normalize_scores(results: &mut [RetrieveResult], how: ScoreNormalization) {
+    let top = results.iter().map(|r| r.score).fold(0.0, f64::max);
+    match how {
+        ScoreNormalization::None => {}
+        // All-zero scores carry no ranking signal; leave them rather than divide by zero.
+        ScoreNormalization::Max if top > 0.0 => {
+            for r in results.iter_mut() {
+                r.score /= top;
+            }
+        }
+        ScoreNormalization::Max => {}
+        ScoreNormalization::Softmax => {
+            // Shift by the top score so `exp` can't overflow.
+            let total: f64 = results.iter().map(|r| (r.score - top).exp()).sum();
+            for r in results.iter_mut() {
+                r.score = (r.score - top).exp() / total;
+            }
+        }
+    }
+}
+
 fn clamp_score(score: f64) -> f64 {
     if score.is_nan() {
         0.0
@@ -431,32 +884,56 @@ fn truncate_log(text: &str, max_chars: usize) -> String {
     out
 }
 
+/// Every document, best lexical match for `query` first (ties by id), including
+/// non-matching ones so a cap can still be filled.
+fn prerank<'a>(query: &str, docs: &'a [Document], case_sensitive: bool) -> Vec<&'a Document> {
+    let terms = tokenize(query, case_sensitive);
+    let mut scored: Vec<(&Document, f64)> = docs
+        .iter()
+        .map(|d| (d, score_doc(&terms, &d.text, case_sensitive)))
+        .collect();
+    scored.sort_by(|a, b| {
+        b.1.partial_cmp(&a.1)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.0.id.cmp(&b.0.id))
+    });
+    scored.into_iter().map(|(d, _)| d).collect()
+}
+
+/// The scoring method behind fallback scores, reported as `fallback_scorer: <name>` so
+/// evaluators know how to read them: `tf` is the raw count of query-term occurrences.
+const FALLBACK_SCORER: &str = "tf";
+
 fn fallback_retrieve(
-    req: &RetrieveRequest,
-    top_k: usize,
-    max_chunk_chars: usize,
-    min_score: f64,
-    include_spans: bool,
+    query: &str,
+    docs: &[Document],
+    settings: &RetrieveSettings,
 ) -> (Vec<RetrieveResult>, Vec<String>) {
-    let terms = tokenize(&req.query);
+    let terms = tokenize(query, settings.case_sensitive);
     let mut scored: Vec<(usize, f64)> = Vec::new();
-    for (i, doc) in req.documents.iter().enumerate() {
-        let score = score_doc(&terms, &doc.text);
-        if score >= min_score && score > 0.0 {
+    for (i, doc) in docs.iter().enumerate() {
+        let score = score_doc(&terms, &doc.text, settings.case_sensitive);
+        if score >= settings.min_score && score > 0.0 {
             scored.push((i, score));
         }
     }
     scored.sort_by(|a, b| {
         b.1.partial_cmp(&a.1)
             .unwrap_or(std::cmp::Ordering::Equal)
-            .then_with(|| req.documents[a.0].id.cmp(&req.documents[b.0].id))
+            .then_with(|| docs[a.0].id.cmp(&docs[b.0].id))
     });
 
     let mut results = Vec::new();
-    for (idx, score) in scored.into_iter().take(top_k) {
-        let doc = &req.documents[idx];
-        let (text, span) = extract_best_span(&terms, &doc.text, max_chunk_chars);
-        let spans = if include_spans {
+    for (idx, score) in scored.into_iter().take(settings.top_k) {
+        let doc = &docs[idx];
+        let (text, span) = extract_best_span(
+            &terms,
+            &doc.text,
+            settings.max_chunk_chars,
+            settings.snippet_boundary,
+            settings.case_sensitive,
+        );
+        let spans = if settings.include_spans {
             span.into_iter()
                 .map(|(s, e)| Span { start: s, end: e })
                 .collect()
@@ -469,30 +946,55 @@ fn fallback_retrieve(
             text,
             metadata: doc.metadata.clone(),
             spans,
+            lexical_score: None,
         });
     }
 
-    let mut warnings = Vec::new();
-    if results.is_empty() && !req.documents.is_empty() && !terms.is_empty() {
+    let mut warnings = vec![format!("fallback_scorer: {FALLBACK_SCORER}")];
+    if results.is_empty() && !docs.is_empty() && !terms.is_empty() {
         warnings.push("fallback_no_matches".to_string());
     }
     (results, warnings)
 }
 
-fn tokenize(query: &str) -> Vec<String> {
+fn add_lexical_scores(
+    query: &str,
+    docs: &[Document],
+    case_sensitive: bool,
+    results: &mut [RetrieveResult],
+) {
+    let terms = tokenize(query, case_sensitive);
+    let by_id: HashMap<&str, &Document> = docs.iter().map(|d| (d.id.as_str(), d)).collect();
+    for r in results {
+        if let Some(doc) = by_id.get(r.doc_id.as_str()) {
+            r.lexical_score = Some(clamp_score(score_doc(&terms, &doc.text, case_sensitive)));
+        }
+    }
+}
+
+/// Query terms (alphanumeric runs of 2+ bytes), casefolded unless `case_sensitive`.
+fn tokenize(query: &str, case_sensitive: bool) -> Vec<String> {
+    let query = if case_sensitive {
+        Cow::Borrowed(query)
+    } else {
+        Cow::Owned(casefold(query))
+    };
     query
-        .to_lowercase()
         .split(|c: char| !c.is_alphanumeric())
         .filter(|s| s.len() >= 2)
         .map(|s| s.to_string())
         .collect()
 }
 
-fn score_doc(terms: &[String], text: &str) -> f64 {
+fn score_doc(terms: &[String], text: &str, case_sensitive: bool) -> f64 {
     if terms.is_empty() {
         return 0.0;
     }
-    let hay = text.to_lowercase();
+    let hay = if case_sensitive {
+        Cow::Borrowed(text)
+    } else {
+        Cow::Owned(casefold(text))
+    };
     let mut score = 0.0;
     for t in terms {
         let mut pos = 0usize;
@@ -511,29 +1013,38 @@ fn extract_best_span(
     terms: &[String],
     text: &str,
     max_chars: usize,
+    boundary: Boundary,
+    case_sensitive: bool,
 ) -> (String, Option<(usize, usize)>) {
     if text.is_empty() {
         return (String::new(), None);
     }
-    let lower = text.to_lowercase();
-    let mut best_pos: Option<(usize, usize)> = None;
-    for t in terms {
-        if let Some(i) = lower.find(t) {
-            let end = i + t.len();
-            best_pos = Some((i, end));
-            break;
-        }
-    }
-    let (slice, span) = if let Some((start, end)) = best_pos {
-        let (chunk, offset) = centered_slice(text, start, max_chars);
-        let span_start = start.saturating_sub(offset);
-        let span_end = span_start + (end - start);
-        (chunk, Some((span_start, span_end)))
+    // Byte range of the first matching term in the original text.
+    let best = if case_sensitive {
+        terms
+            .iter()
+            .find_map(|t| text.find(t.as_str()).map(|i| (i, i + t.len())))
     } else {
-        let (chunk, _) = centered_slice(text, 0, max_chars);
-        (chunk, None)
+        let (folded, offsets) = casefold_with_offsets(text);
+        terms.iter().find_map(|t| {
+            let i = folded.find(t.as_str())?;
+            Some(original_range(text, &offsets, i, t.len()))
+        })
     };
-    (slice, span)
+    // `centered_slice` and spans work in chars of the original; snapping works in bytes.
+    let focus = best.map_or(0, |(start, _)| text[..start].chars().count());
+    let (chunk, offset) = centered_slice(text, focus, max_chars);
+    let chunk_start = text.char_indices().nth(offset).map_or(0, |(i, _)| i);
+    let chunk_end = chunk_start + chunk.len();
+    // Without a match nothing has to stay in the window, so both ends may move inward.
+    let (keep_start, keep_end) = best.unwrap_or((chunk_end, chunk_start));
+    let (a, b) = snap_window(text, chunk_start, chunk_end, keep_start, keep_end, boundary);
+    let offset = offset + text[chunk_start..a].chars().count();
+    let span = best.map(|(start, end)| {
+        let span_start = text[..start].chars().count().saturating_sub(offset);
+        (span_start, span_start + text[start..end].chars().count())
+    });
+    (text[a..b].to_string(), span)
 }
 
 fn centered_slice(text: &str, focus: usize, max_chars: usize) -> (String, usize) {
@@ -557,16 +1068,26 @@ fn centered_slice(text: &str, focus: usize, max_chars: usize) -> (String, usize)
     (slice, start)
 }
 
-fn build_repl_state(
-    req: &RetrieveRequest,
-    top_k: usize,
-    max_chunk_chars: usize,
-    min_score: f64,
-) -> ReplState {
+fn build_repl_state(repl_documents: StoredValue, settings: &RetrieveSettings) -> ReplState {
     let mut state = ReplState::new();
+    state.insert("documents".to_string(), repl_documents);
+    state.insert("top_k".to_string(), StoredValue::Int(settings.top_k as i64));
+    state.insert(
+        "max_chunk_chars".to_string(),
+        StoredValue::Int(settings.max_chunk_chars as i64),
+    );
+    state.insert(
+        "min_score".to_string(),
+        StoredValue::Str(format!("{:.4}", settings.min_score)),
+    );
+    state
+}
+
+/// The REPL `documents` list: one `{"id", "text", "metadata"}` dict per document.
+pub fn documents_value<'a>(documents: impl IntoIterator<Item = &'a Document>) -> StoredValue {
     let mut docs = Vec::new();
-    for doc in &req.documents {
-        let mut m = HashMap::new();
+    for doc in documents {
+        let mut m = BTreeMap::new();
         m.insert("id".to_string(), StoredValue::Str(doc.id.clone()));
         m.insert("text".to_string(), StoredValue::Str(doc.text.clone()));
         let meta = match &doc.metadata {
@@ -576,17 +1097,7 @@ fn build_repl_state(
         m.insert("metadata".to_string(), meta);
         docs.push(StoredValue::Dict(m));
     }
-    state.insert("documents".to_string(), StoredValue::List(docs));
-    state.insert("top_k".to_string(), StoredValue::Int(top_k as i64));
-    state.insert(
-        "max_chunk_chars".to_string(),
-        StoredValue::Int(max_chunk_chars as i64),
-    );
-    state.insert(
-        "min_score".to_string(),
-        StoredValue::Str(format!("{min_score:.4}")),
-    );
-    state
+    StoredValue::List(docs)
 }
 
 fn json_to_stored_value(v: &serde_json::Value) -> StoredValue {
@@ -609,7 +1120,7 @@ fn json_to_stored_value(v: &serde_json::Value) -> StoredValue {
             StoredValue::List(out)
         }
         serde_json::Value::Object(m) => {
-            let mut out = HashMap::new();
+            let mut out = BTreeMap::new();
             for (k, v) in m {
                 out.insert(k.clone(), json_to_stored_value(v));
             }
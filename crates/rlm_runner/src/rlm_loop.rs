@@ -2,15 +2,106 @@ use std::time::Duration;
 
 use python_string_repl::repl::state::{ReplState, StoredValue};
 use python_string_repl::repl::{ExecRequest, ReplEngine};
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
 
-use crate::final_parser::{extract_final, extract_final_var_name};
-use crate::llm_client::{LlmClient, LlmMessage, LlmRequest};
+use crate::final_parser::FinalMarkers;
+use crate::llm_client::{LlmClient, LlmError, LlmMessage, LlmRequest};
 
 #[derive(Debug, Clone)]
 pub struct RlmLoopConfig {
     pub max_iterations: usize,
     pub max_retries: usize,
     pub request_timeout: Duration,
+    /// Forwarded to the LLM (OpenAI `seed`) and the REPL `random` module for reproducible runs.
+    pub seed: Option<u64>,
+    /// Checked every iteration and raced against in-flight LLM calls; the server cancels it
+    /// when the client goes away.
+    pub cancel: Option<CancellationToken>,
+    pub phases: PhaseConfig,
+    /// Answer/variable markers that end the loop (`FINAL(...)` / `FINAL_VAR(...)` by default).
+    pub final_markers: FinalMarkers,
+    /// Models to try in order. When one still fails (or answers blank) after `max_retries`,
+    /// the same iteration is retried with the next, which then serves the rest of the loop.
+    /// Empty means the client's configured model only.
+    pub model_chain: Vec<String>,
+    /// Temperature added on each retry of a failed request (the first attempt, and the
+    /// first attempt on the next model, use 0.0), so a retry isn't a replay of the same
+    /// deterministic failure. 0.0 keeps every attempt at 0.0.
+    pub retry_temperature_step: f32,
+    /// Upper bound for the ramped retry temperature.
+    pub max_retry_temperature: f32,
+    /// Longest wait honored when a rate-limited request says when to retry (`Retry-After`);
+    /// longer requested waits are cut to this. Zero retries immediately.
+    pub max_retry_after: Duration,
+    /// Stop before the next LLM call once reported usage reaches this many tokens
+    /// (`token_budget_exceeded`); the reply that crossed it is still used. `None` is unlimited.
+    pub max_total_tokens: Option<u64>,
+}
+
+/// How strictly the loop enforces "inspect with the REPL first, then answer".
+#[derive(Debug, Clone)]
+pub struct PhaseConfig {
+    /// Reject FINAL/FINAL_VAR (with corrective feedback) until code has run at least once.
+    pub require_repl_before_final: bool,
+    /// Code recommended as the first REPL action in corrective feedback; `None` leaves the
+    /// feedback without a task-specific suggestion.
+    pub starter_code: Option<String>,
+}
+
+impl PhaseConfig {
+    /// The `/v1/retrieve` protocol: rank documents in the REPL before finalizing.
+    pub fn retrieval() -> Self {
+        Self {
+            require_repl_before_final: true,
+            starter_code: Some(
+                "ranked = rank_documents(query, documents, top_k)\nprint(ranked)".to_string(),
+            ),
+        }
+    }
+
+    /// Accept FINAL on any turn (e.g. tasks where the prompt already holds everything needed).
+    pub fn unrestricted() -> Self {
+        Self {
+            require_repl_before_final: false,
+            starter_code: None,
+        }
+    }
+
+    /// Replace the recommended first action, e.g. `print(len(context))` for non-retrieval tasks.
+    pub fn with_starter_code(mut self, code: Option<String>) -> Self {
+        self.starter_code = code;
+        self
+    }
+
+    fn starter_hint(&self) -> Option<String> {
+        self.starter_code
+            .as_deref()
+            .map(|code| format!("- Start by running:\n{code}"))
+    }
+
+    fn repl_required_feedback(&self, marker: &str, forbidden: &str) -> String {
+        let mut lines = vec![
+            "REPL_REQUIRED:".to_string(),
+            format!("- You returned {marker} before any REPL execution. That is invalid."),
+            format!("- Next message MUST be ONLY Python code (no {forbidden}, no explanations, no markdown fences)."),
+        ];
+        lines.extend(self.starter_hint());
+        lines.join("\n")
+    }
+
+    fn no_code_feedback(&self, did_repl: bool) -> String {
+        let mut lines = vec![
+            "NO_CODE:".to_string(),
+            "- Your message contained no Python code to run.".to_string(),
+            "- Next message MUST be Python code, or FINAL/FINAL_VAR once you have the answer."
+                .to_string(),
+        ];
+        if !did_repl && self.require_repl_before_final {
+            lines.extend(self.starter_hint());
+        }
+        lines.join("\n")
+    }
 }
 
 impl Default for RlmLoopConfig {
@@ -20,10 +111,33 @@ impl Default for RlmLoopConfig {
             max_iterations: 20,
             max_retries: 5,
             request_timeout: Duration::from_secs(90),
+            seed: None,
+            cancel: None,
+            phases: PhaseConfig::retrieval(),
+            final_markers: FinalMarkers::default(),
+            model_chain: Vec::new(),
+            retry_temperature_step: 0.0,
+            max_retry_temperature: 1.0,
+            max_retry_after: Duration::from_secs(30),
+            max_total_tokens: None,
         }
     }
 }
 
+/// One model turn of the loop: what the model said, the code we ran (if any),
+/// and the message we sent back.
+#[derive(Debug, Clone, Serialize)]
+pub struct TranscriptEntry {
+    pub iteration: usize,
+    pub response: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repl_ok: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feedback: Option<String>,
+}
+
 #[derive(Debug)]
 pub struct RlmLoopResult {
     pub final_text: Option<String>,
@@ -32,6 +146,9 @@ pub struct RlmLoopResult {
     pub iterations: usize,
     pub warnings: Vec<String>,
     pub state: ReplState,
+    pub transcript: Vec<TranscriptEntry>,
+    /// Tokens the LLM reported using across every call (0 when it reports none).
+    pub total_tokens: u64,
 }
 
 pub async fn run_rlm_loop(
@@ -57,20 +174,134 @@ pub async fn run_rlm_loop(
     let mut last_response = None;
     let mut did_repl = false;
     let mut last_repl_error = None;
+    // The previous turn's resource-limit message, so an identical repeat can end the run.
+    let mut last_limit_error: Option<String> = None;
     let mut iterations = 0usize;
+    let mut transcript = Vec::new();
+    let mut model_idx = 0usize;
+    let mut reported_model: Option<&str> = None;
+    let mut total_tokens = 0u64;
     for _ in 0..cfg.max_iterations {
+        if cfg.cancel.as_ref().is_some_and(|t| t.is_cancelled()) {
+            warnings.push("cancelled".to_string());
+            return RlmLoopResult {
+                final_text: None,
+                last_response,
+                last_repl_error,
+                iterations,
+                warnings,
+                state,
+                transcript,
+                total_tokens,
+            };
+        }
+        if cfg.max_total_tokens.is_some_and(|max| total_tokens >= max) {
+            warnings.push("token_budget_exceeded".to_string());
+            return RlmLoopResult {
+                final_text: None,
+                last_response,
+                last_repl_error,
+                iterations,
+                warnings,
+                state,
+                transcript,
+                total_tokens,
+            };
+        }
         iterations += 1;
         let mut attempt = 0usize;
         let content = loop {
             attempt += 1;
+            let model = cfg.model_chain.get(model_idx).map(String::as_str);
+            let next_model = cfg.model_chain.get(model_idx + 1);
             let req = LlmRequest {
                 messages: messages.clone(),
                 timeout: cfg.request_timeout,
+                seed: cfg.seed,
+                model: model.map(str::to_string),
+                temperature: ((attempt - 1) as f32 * cfg.retry_temperature_step)
+                    .min(cfg.max_retry_temperature),
+            };
+            // Dropping the pending completion aborts the underlying HTTP request.
+            let completion = match &cfg.cancel {
+                Some(token) => tokio::select! {
+                    r = llm.complete(req) => Some(r),
+                    _ = token.cancelled() => None,
+                },
+                None => Some(llm.complete(req).await),
+            };
+            let Some(completion) = completion else {
+                warnings.push("cancelled".to_string());
+                return RlmLoopResult {
+                    final_text: None,
+                    last_response,
+                    last_repl_error,
+                    iterations,
+                    warnings,
+                    state,
+                    transcript,
+                    total_tokens,
+                };
             };
-            match llm.complete(req).await {
-                Ok(resp) => break resp.content,
+            if let Ok(resp) = &completion {
+                total_tokens += resp.total_tokens.unwrap_or(0);
+            }
+            // A blank reply is only a failure when another model can take over; otherwise
+            // it is handled as a turn without code.
+            let completion = completion.and_then(|resp| {
+                if next_model.is_some() && resp.content.trim().is_empty() {
+                    Err(LlmError::EmptyResponse)
+                } else {
+                    Ok(resp)
+                }
+            });
+            match completion {
+                Ok(resp) => {
+                    if let Some(m) = model.filter(|m| reported_model != Some(*m)) {
+                        warnings.push(format!("llm_model: {m}"));
+                        reported_model = Some(m);
+                    }
+                    break resp.content;
+                }
                 Err(e) if attempt <= cfg.max_retries => {
                     warnings.push(format!("llm_error_retry: {e}"));
+                    let wait = e
+                        .retry_after()
+                        .map(|d| d.min(cfg.max_retry_after))
+                        .filter(|d| !d.is_zero());
+                    if let Some(wait) = wait {
+                        warnings.push(format!("llm_retry_after_ms: {}", wait.as_millis()));
+                        let waited = match &cfg.cancel {
+                            Some(token) => tokio::select! {
+                                _ = tokio::time::sleep(wait) => true,
+                                _ = token.cancelled() => false,
+                            },
+                            None => {
+                                tokio::time::sleep(wait).await;
+                                true
+                            }
+                        };
+                        if !waited {
+                            warnings.push("cancelled".to_string());
+                            return RlmLoopResult {
+                                final_text: None,
+                                last_response,
+                                last_repl_error,
+                                iterations,
+                                warnings,
+                                state,
+                                transcript,
+                                total_tokens,
+                            };
+                        }
+                    }
+                    continue;
+                }
+                Err(e) if next_model.is_some() => {
+                    let (from, to) = (model.unwrap_or_default(), next_model.unwrap());
+                    warnings.push(format!("llm_model_fallback: {from} -> {to}: {e}"));
+                    model_idx += 1;
+                    attempt = 0;
                     continue;
                 }
                 Err(e) => {
@@ -82,6 +313,8 @@ pub async fn run_rlm_loop(
                         iterations,
                         warnings,
                         state,
+                        transcript,
+                        total_tokens,
                     };
                 }
             }
@@ -91,34 +324,49 @@ pub async fn run_rlm_loop(
         // If the model mixes FINAL(...) with code, prefer to run code and ignore FINAL.
         // This is more robust than hard-failing, and helps recover from "eager finalization".
         let (code, _had_code_block) = extract_repl_code(&content);
-        let stripped_code = strip_final_lines(&code);
+        let stripped_code = cfg
+            .final_markers
+            .strip(&strip_final_lines(&code))
+            .trim()
+            .to_string();
         let has_executable_code = !stripped_code.trim().is_empty();
 
-        if let Some(final_text) = extract_final(&content) {
-            if !did_repl {
+        let must_repl_first = cfg.phases.require_repl_before_final && !did_repl;
+
+        if let Some(final_text) = cfg.final_markers.extract_answer(&content) {
+            if must_repl_first {
                 warnings.push("final_before_repl".to_string());
                 if has_executable_code {
                     warnings.push("final_mixed_with_code_ignored".to_string());
                 } else {
+                    let feedback = cfg.phases.repl_required_feedback("FINAL", "FINAL");
+                    transcript.push(TranscriptEntry {
+                        iteration: iterations,
+                        response: content.clone(),
+                        code: None,
+                        repl_ok: None,
+                        feedback: Some(feedback.clone()),
+                    });
                     messages.push(LlmMessage {
                         role: "assistant".to_string(),
                         content,
                     });
                     messages.push(LlmMessage {
                         role: "user".to_string(),
-                        content: [
-                            "REPL_REQUIRED:",
-                            "- You returned FINAL before any REPL execution. That is invalid.",
-                            "- Next message MUST be ONLY Python code (no FINAL, no explanations, no markdown fences).",
-                            "- Start by ranking and printing: ranked = rank_documents(query, documents, top_k); print(ranked)",
-                        ]
-                        .join("\n"),
+                        content: feedback,
                     });
                     continue;
                 }
             } else if has_executable_code {
                 warnings.push("final_mixed_with_code_ignored".to_string());
             } else {
+                transcript.push(TranscriptEntry {
+                    iteration: iterations,
+                    response: content,
+                    code: None,
+                    repl_ok: None,
+                    feedback: None,
+                });
                 return RlmLoopResult {
                     final_text: Some(final_text),
                     last_response,
@@ -126,29 +374,35 @@ pub async fn run_rlm_loop(
                     iterations,
                     warnings,
                     state,
+                    transcript,
+                    total_tokens,
                 };
             }
         }
 
-        if let Some(var_name) = extract_final_var_name(&content) {
-            if !did_repl {
+        if let Some(var_name) = cfg.final_markers.extract_var_name(&content) {
+            if must_repl_first {
                 warnings.push("final_var_before_repl".to_string());
                 if has_executable_code {
                     warnings.push("final_var_mixed_with_code_ignored".to_string());
                 } else {
+                    let feedback = cfg
+                        .phases
+                        .repl_required_feedback("FINAL_VAR", "FINAL/FINAL_VAR");
+                    transcript.push(TranscriptEntry {
+                        iteration: iterations,
+                        response: content.clone(),
+                        code: None,
+                        repl_ok: None,
+                        feedback: Some(feedback.clone()),
+                    });
                     messages.push(LlmMessage {
                         role: "assistant".to_string(),
                         content,
                     });
                     messages.push(LlmMessage {
                         role: "user".to_string(),
-                        content: [
-                            "REPL_REQUIRED:",
-                            "- You returned FINAL_VAR before any REPL execution. That is invalid.",
-                            "- Next message MUST be ONLY Python code (no FINAL/FINAL_VAR, no explanations, no markdown fences).",
-                            "- Start by ranking and printing: ranked = rank_documents(query, documents, top_k); print(ranked)",
-                        ]
-                        .join("\n"),
+                        content: feedback,
                     });
                     continue;
                 }
@@ -157,6 +411,13 @@ pub async fn run_rlm_loop(
             } else {
                 match state.get(&var_name) {
                     Some(StoredValue::Str(s)) => {
+                        transcript.push(TranscriptEntry {
+                            iteration: iterations,
+                            response: content,
+                            code: None,
+                            repl_ok: None,
+                            feedback: None,
+                        });
                         return RlmLoopResult {
                             final_text: Some(s.clone()),
                             last_response,
@@ -164,6 +425,8 @@ pub async fn run_rlm_loop(
                             iterations,
                             warnings,
                             state,
+                            transcript,
+                            total_tokens,
                         };
                     }
                     Some(_) => warnings.push(format!("final_var_not_string: {var_name}")),
@@ -172,14 +435,48 @@ pub async fn run_rlm_loop(
             }
         }
 
+        // Blank code isn't a REPL turn: running it would only report "No code to execute" and
+        // would let the model satisfy `require_repl_before_final` without inspecting anything.
+        if !has_executable_code {
+            warnings.push("no_code".to_string());
+            let feedback = cfg.phases.no_code_feedback(did_repl);
+            transcript.push(TranscriptEntry {
+                iteration: iterations,
+                response: content.clone(),
+                code: None,
+                repl_ok: None,
+                feedback: Some(feedback.clone()),
+            });
+            messages.push(LlmMessage {
+                role: "assistant".to_string(),
+                content,
+            });
+            messages.push(LlmMessage {
+                role: "user".to_string(),
+                content: feedback,
+            });
+            continue;
+        }
+
         let exec = repl.exec(ExecRequest {
             context: String::new(),
             query: query.to_string(),
-            code: stripped_code,
+            code: stripped_code.clone(),
             max_output_chars: None,
+            max_print_state_chars: None,
             state: Some(state.clone()),
+            seed: cfg.seed,
         });
-        let feedback = format_repl_feedback(&exec);
+        let limit_error = exec
+            .error
+            .clone()
+            .filter(|_| exec.error_kind.as_deref() == Some("ResourceLimitExceeded"));
+        let limit_repeated = limit_error.is_some() && limit_error == last_limit_error;
+        let mut feedback = format_repl_feedback(&exec);
+        if limit_error.is_some() {
+            feedback.push_str(LIMIT_HIT_FEEDBACK);
+        }
+        last_limit_error = limit_error;
         if !exec.ok {
             if let Some(err) = &exec.error {
                 eprintln!("[rlm_loop] repl_error: {err}");
@@ -191,6 +488,37 @@ pub async fn run_rlm_loop(
         }
         did_repl = true;
         state = exec.state.unwrap_or_default();
+        transcript.push(TranscriptEntry {
+            iteration: iterations,
+            response: content.clone(),
+            code: Some(stripped_code),
+            repl_ok: Some(exec.ok),
+            feedback: Some(feedback.clone()),
+        });
+
+        // An explicit exit can't be fixed by rewriting the code. A limit usually can (a narrower
+        // pattern, fewer items), but hitting the same one twice in a row means the model isn't
+        // adapting, so further retries would just burn iterations.
+        let stop_warning = if exec.error_kind.as_deref() == Some("SystemExit") {
+            Some("repl_unrecoverable: SystemExit".to_string())
+        } else if limit_repeated {
+            Some("repl_limit_repeated: ResourceLimitExceeded".to_string())
+        } else {
+            None
+        };
+        if let Some(warning) = stop_warning {
+            warnings.push(warning);
+            return RlmLoopResult {
+                final_text: None,
+                last_response,
+                last_repl_error,
+                iterations,
+                warnings,
+                state,
+                transcript,
+                total_tokens,
+            };
+        }
 
         messages.push(LlmMessage {
             role: "assistant".to_string(),
@@ -210,9 +538,15 @@ pub async fn run_rlm_loop(
         iterations,
         warnings,
         state,
+        transcript,
+        total_tokens,
     }
 }
 
+const LIMIT_HIT_FEEDBACK: &str = "\nLIMIT_HIT:\n\
+- That code exceeded a REPL resource limit; running it again unchanged will fail the same way.\n\
+- Narrow the pattern, slice the context, or iterate over fewer items.";
+
 fn format_repl_feedback(exec: &python_string_repl::repl::ExecResponse) -> String {
     let mut out = String::new();
     if exec.ok {
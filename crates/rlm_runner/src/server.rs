@@ -1,52 +1,217 @@
-use axum::extract::State;
-use axum::{routing::get, routing::post, Json, Router};
+use axum::error_handling::HandleErrorLayer;
+use axum::extract::{Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{routing::get, routing::post, BoxError, Json, Router};
+use serde::Deserialize;
 use serde_json::json;
 use std::net::SocketAddr;
+use std::time::Duration;
 use tokio::net::TcpListener;
 use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tower::ServiceBuilder;
 
-use crate::llm_client::{LlmClient, LlmError, MockLlm, OpenAiClient};
-use crate::retrieve::{retrieve, RetrieveContext, RetrieveRequest, RetrieveResponse};
+use crate::index::{IndexStore, IndexedCorpus};
+use crate::llm_client::{HttpClientConfig, LlmClient, LlmError, MockLlm, OpenAiClient};
+use crate::retrieve::{
+    retrieve, retrieve_indexed, Document, RetrieveContext, RetrieveDefaults, RetrieveRequest,
+};
+
+/// Default cap on simultaneous `/v1/retrieve` calls; each one may run a full LLM loop.
+const DEFAULT_MAX_CONCURRENT_RETRIEVES: usize = 16;
+/// Default memory budget shared by all corpora uploaded through `/v1/index`.
+const DEFAULT_INDEX_MAX_BYTES: usize = 256 * 1024 * 1024;
+/// Default idle time after which an uploaded corpus is dropped.
+const DEFAULT_INDEX_TTL: Duration = Duration::from_secs(60 * 60);
+/// Default cap on a serialized `/v1/retrieve` response.
+const DEFAULT_MAX_RESPONSE_BYTES: usize = 16 * 1024 * 1024;
 
 #[derive(Clone)]
 pub struct AppState {
     retrieve_ctx: RetrieveContext,
+    max_concurrent_retrieves: usize,
+    index: IndexStore,
+    max_response_bytes: usize,
 }
 
 impl AppState {
     pub fn new_with_llm(llm: LlmClient) -> Self {
         Self {
             retrieve_ctx: RetrieveContext::new(llm),
+            max_concurrent_retrieves: DEFAULT_MAX_CONCURRENT_RETRIEVES,
+            index: IndexStore::new(DEFAULT_INDEX_MAX_BYTES, DEFAULT_INDEX_TTL),
+            max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
         }
     }
 
+    /// Values used for retrieve options a request leaves out.
+    pub fn with_retrieve_defaults(mut self, defaults: RetrieveDefaults) -> Self {
+        self.retrieve_ctx.defaults = defaults;
+        self
+    }
+
+    /// Highest `max_iterations` a request may ask for; larger values are clamped.
+    pub fn with_max_iterations_ceiling(mut self, ceiling: usize) -> Self {
+        self.retrieve_ctx.max_iterations_ceiling = ceiling.max(1);
+        self
+    }
+
+    /// Models the RLM loop tries in order, falling through to the next when one keeps
+    /// failing. The first entry replaces the client's default model.
+    pub fn with_model_chain(mut self, models: Vec<String>) -> Self {
+        self.retrieve_ctx.rlm.model_chain = models;
+        self
+    }
+
+    /// Raise the LLM temperature by `step` on each retry of a failed request, up to `max`.
+    pub fn with_retry_temperature(mut self, step: f32, max: f32) -> Self {
+        self.retrieve_ctx.rlm.retry_temperature_step = step;
+        self.retrieve_ctx.rlm.max_retry_temperature = max;
+        self
+    }
+
+    /// Fixed seed for LLM requests and the REPL `random` module, for reproducible runs.
+    pub fn with_seed(mut self, seed: Option<u64>) -> Self {
+        self.retrieve_ctx.rlm.seed = seed;
+        self
+    }
+
+    /// Cap how long a rate-limited LLM request waits for its `Retry-After` before retrying.
+    pub fn with_max_retry_after(mut self, max: Duration) -> Self {
+        self.retrieve_ctx.rlm.max_retry_after = max;
+        self
+    }
+
+    /// Bound the corpora kept for `/v1/index`: least recently used ones are evicted past
+    /// `max_bytes`, and any corpus unused for `ttl` is dropped.
+    pub fn with_index_budget(mut self, max_bytes: usize, ttl: Duration) -> Self {
+        self.index = IndexStore::new(max_bytes, ttl);
+        self
+    }
+
+    /// Retrieve responses larger than this (as JSON) are cut down to fit, with a
+    /// `response_truncated` warning.
+    pub fn with_max_response_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_response_bytes = max_bytes;
+        self
+    }
+
+    /// Requests beyond this many in-flight retrieves are rejected with 503.
+    pub fn with_max_concurrent_retrieves(mut self, limit: usize) -> Self {
+        self.max_concurrent_retrieves = limit.max(1);
+        self
+    }
+
     pub fn new_default() -> Result<Self, LlmError> {
         dotenvy::dotenv().ok();
         // Allow running without an LLM (deterministic fallback-only mode).
         if std::env::var("RUSTRLM_DISABLE_LLM").ok().as_deref() == Some("1") {
-            return Ok(Self::new_with_llm(LlmClient::Mock(MockLlm::new(vec![]))));
+            return Ok(
+                Self::new_with_llm(LlmClient::Mock(MockLlm::new(vec![]))).with_env_settings()
+            );
         }
 
         let api_key = match std::env::var("OPENAI_API_KEY") {
             Ok(v) => v,
             Err(_) => {
                 // No key -> still serve, but rely on fallback retrieval.
-                return Ok(Self::new_with_llm(LlmClient::Mock(MockLlm::new(vec![]))));
+                return Ok(
+                    Self::new_with_llm(LlmClient::Mock(MockLlm::new(vec![]))).with_env_settings()
+                );
             }
         };
-        let client = OpenAiClient::new(api_key, "gpt-5.2".to_string())?;
-        Ok(Self::new_with_llm(LlmClient::OpenAi(client)))
+        let client = OpenAiClient::new_with_http_config(
+            api_key,
+            "gpt-5.2".to_string(),
+            HttpClientConfig::from_env(),
+        )?;
+        Ok(Self::new_with_llm(LlmClient::OpenAi(client)).with_env_settings())
+    }
+
+    fn with_env_settings(self) -> Self {
+        // Optional fixed seed for reproducible evaluation runs, whichever backend is in use.
+        let seed = std::env::var("RUSTRLM_SEED")
+            .ok()
+            .and_then(|v| v.trim().parse().ok());
+        let mut state = self
+            .with_retrieve_defaults(RetrieveDefaults::from_env())
+            .with_seed(seed);
+        if let Ok(chain) = std::env::var("RUSTRLM_MODEL_CHAIN") {
+            let models = chain
+                .split(',')
+                .map(str::trim)
+                .filter(|m| !m.is_empty())
+                .map(str::to_string)
+                .collect();
+            state = state.with_model_chain(models);
+        }
+        if let Some(step) = std::env::var("RUSTRLM_RETRY_TEMPERATURE_STEP")
+            .ok()
+            .and_then(|v| v.trim().parse::<f32>().ok())
+            .filter(|s| s.is_finite() && *s >= 0.0)
+        {
+            let max = state.retrieve_ctx.rlm.max_retry_temperature;
+            state = state.with_retry_temperature(step, max);
+        }
+        if let Some(secs) = std::env::var("RUSTRLM_MAX_RETRY_AFTER_SECS")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+        {
+            state = state.with_max_retry_after(Duration::from_secs(secs));
+        }
+        let mut state = match std::env::var("RUSTRLM_MAX_CONCURRENT_RETRIEVES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            Some(limit) => state.with_max_concurrent_retrieves(limit),
+            None => state,
+        };
+        if let Some(max_bytes) = std::env::var("RUSTRLM_MAX_RESPONSE_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            state = state.with_max_response_bytes(max_bytes);
+        }
+        let max_bytes = std::env::var("RUSTRLM_INDEX_MAX_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok());
+        let ttl = std::env::var("RUSTRLM_INDEX_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map(Duration::from_secs);
+        if max_bytes.is_none() && ttl.is_none() {
+            return state;
+        }
+        state.with_index_budget(
+            max_bytes.unwrap_or(DEFAULT_INDEX_MAX_BYTES),
+            ttl.unwrap_or(DEFAULT_INDEX_TTL),
+        )
     }
 }
 
 pub fn app(state: AppState) -> Router {
+    // Shed load instead of queueing: a waiting retrieve would still hold the client open
+    // and pile up LLM calls once admitted.
+    let retrieve_limit = ServiceBuilder::new()
+        .layer(HandleErrorLayer::new(overloaded))
+        .load_shed()
+        .concurrency_limit(state.max_concurrent_retrieves);
     Router::new()
         .route("/v1/health", get(health))
         .route("/v1/version", get(version))
-        .route("/v1/retrieve", post(retrieve_handler))
+        .route("/v1/index", post(index_handler))
+        .route("/v1/retrieve", post(retrieve_handler).layer(retrieve_limit))
         .with_state(state)
 }
 
+async fn overloaded(_: BoxError) -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(json!({"error": "server busy: too many concurrent retrieve requests"})),
+    )
+}
+
 async fn health() -> Json<serde_json::Value> {
     Json(json!({"status": "ok", "name": "rustrlm", "version": env!("CARGO_PKG_VERSION")}))
 }
@@ -55,24 +220,105 @@ async fn version() -> Json<serde_json::Value> {
     Json(json!({"name": "rustrlm", "version": env!("CARGO_PKG_VERSION"), "build": "dev"}))
 }
 
+#[derive(Debug, Deserialize)]
+struct IndexRequest {
+    documents: Vec<Document>,
+}
+
+async fn index_handler(
+    State(state): State<AppState>,
+    Json(req): Json<IndexRequest>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let count = req.documents.len();
+    let corpus = IndexedCorpus::new(req.documents);
+    let bytes = corpus.bytes();
+    match state.index.insert(corpus) {
+        Ok(index_id) => (
+            StatusCode::OK,
+            Json(json!({"index_id": index_id, "documents": count, "bytes": bytes})),
+        ),
+        Err(e) => (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(json!({"error": e.to_string()})),
+        ),
+    }
+}
+
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+#[derive(Debug, Deserialize)]
+struct RetrieveParams {
+    /// `ndjson` selects newline-delimited output; anything else keeps the JSON object.
+    #[serde(default)]
+    format: Option<String>,
+}
+
 async fn retrieve_handler(
     State(state): State<AppState>,
+    Query(params): Query<RetrieveParams>,
+    headers: HeaderMap,
     Json(req): Json<RetrieveRequest>,
-) -> Json<RetrieveResponse> {
-    Json(retrieve(&req, &state.retrieve_ctx).await)
+) -> Response {
+    // axum drops this future when the client disconnects; the guard then cancels the loop.
+    let cancel = CancellationToken::new();
+    let _cancel_on_drop = cancel.clone().drop_guard();
+    let mut ctx = state.retrieve_ctx.clone();
+    ctx.rlm.cancel = Some(cancel);
+    let mut resp = match &req.index_id {
+        Some(_) if !req.documents.is_empty() => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "pass either documents or index_id, not both",
+            );
+        }
+        Some(id) => match state.index.get(id) {
+            Some(corpus) => retrieve_indexed(&req, &corpus, &ctx).await,
+            None => return error_response(StatusCode::NOT_FOUND, "unknown or expired index_id"),
+        },
+        None => retrieve(&req, &ctx).await,
+    };
+    resp.fit_to_size(state.max_response_bytes);
+    if wants_ndjson(&params, &headers) {
+        (
+            [(header::CONTENT_TYPE, NDJSON_CONTENT_TYPE)],
+            resp.to_ndjson(),
+        )
+            .into_response()
+    } else {
+        Json(resp).into_response()
+    }
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({"error": message}))).into_response()
+}
+
+fn wants_ndjson(params: &RetrieveParams, headers: &HeaderMap) -> bool {
+    if let Some(format) = &params.format {
+        return format.eq_ignore_ascii_case("ndjson");
+    }
+    headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains(NDJSON_CONTENT_TYPE))
 }
 
 pub async fn serve(addr: SocketAddr) -> std::io::Result<()> {
-    let state =
-        AppState::new_default().map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let state = AppState::new_default().map_err(std::io::Error::other)?;
     let listener = TcpListener::bind(addr).await?;
     axum::serve(listener, app(state)).await
 }
 
 pub async fn spawn_test_server_with_mock(responses: Vec<String>) -> (SocketAddr, JoinHandle<()>) {
+    spawn_test_server_with_state(AppState::new_with_llm(LlmClient::Mock(MockLlm::new(
+        responses,
+    ))))
+    .await
+}
+
+pub async fn spawn_test_server_with_state(state: AppState) -> (SocketAddr, JoinHandle<()>) {
     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
     let addr = listener.local_addr().unwrap();
-    let state = AppState::new_with_llm(LlmClient::Mock(MockLlm::new(responses)));
     let handle = tokio::spawn(async move {
         let _ = axum::serve(listener, app(state)).await;
     });
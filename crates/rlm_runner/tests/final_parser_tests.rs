@@ -1,6 +1,6 @@
 use pretty_assertions::assert_eq;
 
-use rlm_runner::final_parser::{extract_final, extract_final_var_name, is_final};
+use rlm_runner::final_parser::{extract_final, extract_final_var_name, is_final, FinalMarkers};
 
 #[test]
 fn final_literal_double_quotes() {
@@ -29,3 +29,16 @@ fn final_var_name_extract() {
 fn final_non_literal_is_not_extracted() {
     assert_eq!(extract_final("FINAL(ans)"), None);
 }
+
+#[test]
+fn custom_answer_marker_is_extracted() {
+    let markers = FinalMarkers::default()
+        .with_answer_pattern(r"(?s)<answer>(.*?)</answer>")
+        .unwrap();
+    let s = "thinking...\n<answer>\n42\n</answer>";
+    assert_eq!(markers.extract_answer(s), Some("42".into()));
+    // Defaults still apply alongside the custom marker.
+    assert_eq!(markers.extract_answer(r#"FINAL("x")"#), Some("x".into()));
+    assert_eq!(FinalMarkers::default().extract_answer(s), None);
+    assert_eq!(markers.strip(s).trim(), "thinking...");
+}
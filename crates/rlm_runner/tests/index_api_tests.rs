@@ -0,0 +1,102 @@
+use std::time::Duration;
+
+use rlm_runner::llm_client::{LlmClient, MockLlm};
+use rlm_runner::server::{spawn_test_server_with_state, AppState};
+use serde_json::json;
+
+const FINAL_DOC2: &str = r#"FINAL("""{"results":[{"doc_id":"doc2","score":0.8,"snippet":"brown fox"}],"warnings":[]}""")"#;
+
+#[tokio::test]
+async fn index_once_then_retrieve_twice_by_id() {
+    let responses = ["print(len(documents))", FINAL_DOC2]
+        .repeat(2)
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    let (addr, _handle) = rlm_runner::server::spawn_test_server_with_mock(responses).await;
+    let client = reqwest::Client::new();
+
+    let resp = client
+        .post(format!("http://{}/v1/index", addr))
+        .json(&json!({
+            "documents": [
+                {"id": "doc1", "text": "alpha beta gamma"},
+                {"id": "doc2", "text": "the quick brown fox jumps", "metadata": {"lang": "en"}}
+            ]
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["documents"], 2);
+    let index_id = body["index_id"].as_str().unwrap().to_string();
+
+    for _ in 0..2 {
+        let resp = client
+            .post(format!("http://{}/v1/retrieve", addr))
+            .json(&json!({
+                "query": "brown fox",
+                "index_id": index_id,
+                "options": {"top_k": 1, "use_fallback": false}
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert!(resp.status().is_success());
+        let body: serde_json::Value = resp.json().await.unwrap();
+        let results = body["results"].as_array().unwrap();
+        assert_eq!(results.len(), 1, "body={body}");
+        assert_eq!(results[0]["doc_id"], "doc2");
+        assert_eq!(results[0]["text"], "brown fox");
+        assert_eq!(results[0]["metadata"]["lang"], "en");
+    }
+}
+
+#[tokio::test]
+async fn retrieve_rejects_unknown_index_id() {
+    let (addr, _handle) = rlm_runner::server::spawn_test_server().await;
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}/v1/retrieve", addr))
+        .json(&json!({"query": "fox", "index_id": "missing"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn index_over_budget_is_rejected_and_old_entries_evicted() {
+    let state = AppState::new_with_llm(LlmClient::Mock(MockLlm::new(vec![])))
+        .with_index_budget(200, Duration::from_secs(60));
+    let (addr, _handle) = spawn_test_server_with_state(state).await;
+    let client = reqwest::Client::new();
+    let index = |text: String| {
+        client
+            .post(format!("http://{}/v1/index", addr))
+            .json(&json!({"documents": [{"id": "d", "text": text}]}))
+            .send()
+    };
+
+    let resp = index("x".repeat(500)).await.unwrap();
+    assert_eq!(resp.status().as_u16(), 413);
+
+    // Each corpus takes ~122 bytes, so the second upload pushes the first one out.
+    let first: serde_json::Value = index("a".repeat(60)).await.unwrap().json().await.unwrap();
+    let second: serde_json::Value = index("b".repeat(60)).await.unwrap().json().await.unwrap();
+    let status = |id: serde_json::Value| {
+        let client = client.clone();
+        async move {
+            client
+                .post(format!("http://{}/v1/retrieve", addr))
+                .json(&json!({"query": "a", "index_id": id}))
+                .send()
+                .await
+                .unwrap()
+                .status()
+                .as_u16()
+        }
+    };
+    assert_eq!(status(first["index_id"].clone()).await, 404);
+    assert_eq!(status(second["index_id"].clone()).await, 200);
+}
@@ -0,0 +1,189 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::{routing::post, Json, Router};
+use serde_json::json;
+
+use python_string_repl::repl::state::ReplState;
+use python_string_repl::repl::{ReplConfig, ReplEngine};
+use rlm_runner::llm_client::{
+    HttpClientConfig, LlmClient, LlmError, LlmMessage, LlmRequest, OpenAiClient,
+};
+use rlm_runner::rlm_loop::{run_rlm_loop, PhaseConfig, RlmLoopConfig};
+
+type Captured = Arc<Mutex<Vec<serde_json::Value>>>;
+
+/// Minimal OpenAI-compatible chat endpoint that records request bodies.
+async fn spawn_fake_openai() -> (String, Captured) {
+    async fn chat(
+        State(captured): State<Captured>,
+        Json(body): Json<serde_json::Value>,
+    ) -> Json<serde_json::Value> {
+        captured.lock().unwrap().push(body);
+        Json(json!({"choices": [{"message": {"content": "print(1)"}}]}))
+    }
+
+    let captured: Captured = Arc::default();
+    let app = Router::new()
+        .route("/v1/chat/completions", post(chat))
+        .with_state(captured.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let _ = axum::serve(listener, app).await;
+    });
+    (format!("http://{addr}/v1"), captured)
+}
+
+/// Chat endpoint that answers the first request with 429 and `headers`, then `FINAL("ok")`.
+async fn spawn_rate_limited_openai(headers: Vec<(&'static str, &'static str)>) -> String {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let app = Router::new().route(
+        "/v1/chat/completions",
+        post(move || {
+            let calls = calls.clone();
+            let headers = headers.clone();
+            async move {
+                if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                    let mut resp: Response =
+                        (StatusCode::TOO_MANY_REQUESTS, "slow down").into_response();
+                    for (name, value) in headers {
+                        resp.headers_mut().insert(name, value.parse().unwrap());
+                    }
+                    return resp;
+                }
+                Json(json!({"choices": [{"message": {"content": "FINAL(\"ok\")"}}]}))
+                    .into_response()
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let _ = axum::serve(listener, app).await;
+    });
+    format!("http://{addr}/v1")
+}
+
+fn request(seed: Option<u64>) -> LlmRequest {
+    LlmRequest {
+        messages: vec![LlmMessage {
+            role: "user".to_string(),
+            content: "hi".to_string(),
+        }],
+        timeout: Duration::from_secs(5),
+        seed,
+        model: None,
+        temperature: 0.0,
+    }
+}
+
+#[tokio::test]
+async fn openai_body_includes_seed_when_configured() {
+    let (base_url, captured) = spawn_fake_openai().await;
+    let client = OpenAiClient::new("test-key".to_string(), "test-model".to_string())
+        .unwrap()
+        .with_base_url(base_url);
+
+    let resp = client.complete(request(Some(1234))).await.unwrap();
+    assert_eq!(resp.content, "print(1)");
+    client.complete(request(None)).await.unwrap();
+
+    let bodies = captured.lock().unwrap();
+    assert_eq!(bodies[0]["seed"], 1234);
+    assert_eq!(bodies[0]["model"], "test-model");
+    assert!(bodies[1].get("seed").is_none());
+}
+
+#[tokio::test]
+async fn openai_client_with_custom_pool_settings_completes_requests() {
+    let (base_url, captured) = spawn_fake_openai().await;
+    let http = HttpClientConfig {
+        pool_max_idle_per_host: 2,
+        pool_idle_timeout: Some(Duration::from_secs(5)),
+        connect_timeout: Some(Duration::from_secs(2)),
+        tcp_keepalive: Some(Duration::from_secs(30)),
+    };
+    let client =
+        OpenAiClient::new_with_http_config("test-key".to_string(), "test-model".to_string(), http)
+            .unwrap()
+            .with_base_url(base_url);
+
+    // Sequential requests reuse the pooled connection.
+    for _ in 0..3 {
+        let resp = client.complete(request(None)).await.unwrap();
+        assert_eq!(resp.content, "print(1)");
+    }
+    assert_eq!(captured.lock().unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn openai_429_reports_the_requested_retry_delay() {
+    let cases = [
+        (vec![("retry-after", "2")], Some(Duration::from_secs(2))),
+        (
+            vec![("retry-after-ms", "250"), ("retry-after", "2")],
+            Some(Duration::from_millis(250)),
+        ),
+        (
+            vec![
+                ("x-ratelimit-reset-requests", "1m0.5s"),
+                ("x-ratelimit-reset-tokens", "20ms"),
+            ],
+            Some(Duration::from_millis(60_500)),
+        ),
+        // Values past `Duration::MAX` saturate instead of panicking.
+        (vec![("retry-after", "1e30")], Some(Duration::MAX)),
+        (
+            vec![("x-ratelimit-reset-requests", "99999999999999999999h")],
+            Some(Duration::MAX),
+        ),
+        (vec![], None),
+    ];
+    for (headers, expected) in cases {
+        let base_url = spawn_rate_limited_openai(headers).await;
+        let client = OpenAiClient::new("test-key".to_string(), "test-model".to_string())
+            .unwrap()
+            .with_base_url(base_url);
+        let err = client.complete(request(None)).await.unwrap_err();
+        assert!(matches!(err, LlmError::RateLimited { .. }), "{err}");
+        assert_eq!(err.retry_after(), expected);
+    }
+}
+
+#[tokio::test]
+async fn loop_waits_for_retry_after_before_retrying() {
+    let base_url = spawn_rate_limited_openai(vec![("retry-after", "1")]).await;
+    let llm = LlmClient::OpenAi(
+        OpenAiClient::new("test-key".to_string(), "test-model".to_string())
+            .unwrap()
+            .with_base_url(base_url),
+    );
+    let repl = ReplEngine::new(ReplConfig::default());
+    let cfg = RlmLoopConfig {
+        phases: PhaseConfig::unrestricted(),
+        ..RlmLoopConfig::default()
+    };
+
+    let started = Instant::now();
+    let result = run_rlm_loop(
+        &llm,
+        &repl,
+        "system",
+        "user",
+        "query",
+        ReplState::new(),
+        &cfg,
+    )
+    .await;
+    assert!(started.elapsed() >= Duration::from_secs(1));
+    assert_eq!(result.final_text.as_deref(), Some("ok"));
+    assert!(result
+        .warnings
+        .iter()
+        .any(|w| w == "llm_retry_after_ms: 1000"));
+}
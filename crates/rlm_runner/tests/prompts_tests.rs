@@ -0,0 +1,23 @@
+use python_string_repl::repl::{BUILTIN_FUNCTIONS, STR_METHODS};
+use rlm_runner::prompts::{capabilities_prompt, retrieve_system_prompt};
+
+#[test]
+fn system_prompt_lists_registered_builtins_and_methods() {
+    let prompt = retrieve_system_prompt();
+    assert!(prompt.contains(&capabilities_prompt()));
+
+    let builtins = prompt
+        .lines()
+        .find_map(|l| l.strip_prefix("- builtins: "))
+        .expect("builtins line");
+    let listed: Vec<&str> = builtins.split(", ").collect();
+    assert_eq!(listed, BUILTIN_FUNCTIONS);
+    assert!(listed.contains(&"ord") && listed.contains(&"chr"));
+
+    let methods = prompt
+        .lines()
+        .find_map(|l| l.strip_prefix("- str methods: "))
+        .expect("str methods line");
+    assert!(methods.split(", ").any(|m| m == "join"));
+    assert_eq!(methods.split(", ").count(), STR_METHODS.len());
+}
@@ -1,3 +1,6 @@
+use rlm_runner::llm_client::{LlmClient, MockLlm};
+use rlm_runner::retrieve::RetrieveDefaults;
+use rlm_runner::server::{spawn_test_server_with_state, AppState};
 use serde_json::json;
 
 #[tokio::test]
@@ -58,3 +61,564 @@ async fn retrieve_respects_max_chunk_chars() {
     let text = body["results"][0]["text"].as_str().unwrap();
     assert!(text.len() <= 120);
 }
+
+#[tokio::test]
+async fn retrieve_includes_transcript_only_when_requested() {
+    let responses = || {
+        vec![
+            "print(len(documents))".to_string(),
+            r#"FINAL("""{"results":[{"doc_id":"doc1","score":0.5,"snippet":"alpha"}],"warnings":[]}""")"#
+                .to_string(),
+        ]
+    };
+    let client = reqwest::Client::new();
+
+    let (addr, _handle) = rlm_runner::server::spawn_test_server_with_mock(responses()).await;
+    let req = json!({
+        "query": "alpha",
+        "documents": [{"id": "doc1", "text": "alpha beta"}],
+        "options": {"include_transcript": true}
+    });
+    let body: serde_json::Value = client
+        .post(format!("http://{}/v1/retrieve", addr))
+        .json(&req)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let transcript = body["transcript"].as_array().unwrap();
+    assert_eq!(transcript.len(), 2);
+    assert_eq!(transcript[0]["iteration"], 1);
+    assert_eq!(transcript[0]["code"], "print(len(documents))");
+    assert_eq!(transcript[0]["repl_ok"], true);
+    assert!(transcript[0]["feedback"]
+        .as_str()
+        .unwrap()
+        .starts_with("REPL_OUTPUT:\n1"));
+    assert!(transcript[1]["response"]
+        .as_str()
+        .unwrap()
+        .starts_with("FINAL("));
+
+    let (addr, _handle) = rlm_runner::server::spawn_test_server_with_mock(responses()).await;
+    let req = json!({
+        "query": "alpha",
+        "documents": [{"id": "doc1", "text": "alpha beta"}]
+    });
+    let body: serde_json::Value = client
+        .post(format!("http://{}/v1/retrieve", addr))
+        .json(&req)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(body.get("transcript").is_none());
+}
+
+#[tokio::test]
+async fn retrieve_streams_ndjson_when_requested() {
+    let responses = || {
+        vec![
+            "print(len(documents))".to_string(),
+            r#"FINAL("""{"results":[{"doc_id":"doc1","score":0.9,"snippet":"alpha"},{"doc_id":"doc2","score":0.5,"snippet":"alpha gamma"}],"warnings":[]}""")"#.to_string(),
+        ]
+    };
+    let req = json!({
+        "query": "alpha",
+        "documents": [
+            {"id": "doc1", "text": "alpha beta"},
+            {"id": "doc2", "text": "alpha gamma"}
+        ],
+        "options": {"top_k": 2}
+    });
+    let client = reqwest::Client::new();
+
+    for use_header in [false, true] {
+        let (addr, _handle) = rlm_runner::server::spawn_test_server_with_mock(responses()).await;
+        let mut call = client.post(format!("http://{}/v1/retrieve", addr));
+        call = if use_header {
+            call.header("Accept", "application/x-ndjson")
+        } else {
+            call.query(&[("format", "ndjson")])
+        };
+        let resp = call.json(&req).send().await.unwrap();
+        assert!(resp.status().is_success());
+        assert_eq!(resp.headers()["content-type"], "application/x-ndjson");
+        let text = resp.text().await.unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3, "body={text}");
+        assert_eq!(lines[0]["doc_id"], "doc1");
+        assert_eq!(lines[1]["doc_id"], "doc2");
+        assert!(lines[2]["trace_id"].is_string());
+        assert!(lines[2]["warnings"].is_array());
+        assert!(lines[2].get("results").is_none());
+    }
+}
+
+#[tokio::test]
+async fn retrieve_limits_snippets_and_chunks_separately() {
+    let snippet = "the target sentence with plenty of words";
+    let final_json = format!(
+        r#"{{"results":[{{"doc_id":"doc1","score":0.9,"snippet":"{snippet}"}},{{"doc_id":"doc2","score":0.8,"snippet":"not in the text"}}],"warnings":[]}}"#
+    );
+    let responses = vec![
+        "print(len(documents))".to_string(),
+        format!(r#"FINAL("""{final_json}""")"#),
+    ];
+    let (addr, _handle) = rlm_runner::server::spawn_test_server_with_mock(responses).await;
+    let req = json!({
+        "query": "target",
+        "documents": [
+            {"id": "doc1", "text": format!("intro. {snippet}. outro")},
+            {"id": "doc2", "text": "x".repeat(100)}
+        ],
+        "options": {"top_k": 2, "max_chunk_chars": 12, "max_snippet_chars": 20}
+    });
+    let body: serde_json::Value = reqwest::Client::new()
+        .post(format!("http://{}/v1/retrieve", addr))
+        .json(&req)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let results = body["results"].as_array().unwrap();
+    // Located snippet: cut at max_snippet_chars, not max_chunk_chars.
+    assert_eq!(results[0]["text"], "the target sentence ");
+    assert_eq!(results[0]["spans"][0]["end"], 20);
+    // Snippet not found: the document fallback still uses max_chunk_chars.
+    assert_eq!(results[1]["text"], "x".repeat(12));
+}
+
+#[tokio::test]
+async fn retrieve_reports_lexical_score_next_to_model_score() {
+    let responses = || {
+        vec![
+            "print(len(documents))".to_string(),
+            r#"FINAL("""{"results":[{"doc_id":"doc2","score":0.9,"snippet":"gamma"},{"doc_id":"doc1","score":0.3,"snippet":"alpha"}],"warnings":[]}""")"#.to_string(),
+        ]
+    };
+    let req = |include: bool| {
+        json!({
+            "query": "alpha",
+            "documents": [
+                {"id": "doc1", "text": "alpha beta"},
+                {"id": "doc2", "text": "gamma delta"}
+            ],
+            "options": {"top_k": 2, "include_lexical_score": include}
+        })
+    };
+    let client = reqwest::Client::new();
+    let mut runs = Vec::new();
+    for include in [true, true, false] {
+        let (addr, _handle) = rlm_runner::server::spawn_test_server_with_mock(responses()).await;
+        let body: serde_json::Value = client
+            .post(format!("http://{}/v1/retrieve", addr))
+            .json(&req(include))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        runs.push(body["results"].clone());
+    }
+
+    let results = runs[0].as_array().unwrap();
+    assert_eq!(results[0]["doc_id"], "doc2");
+    assert_eq!(results[0]["score"], 0.9);
+    assert_eq!(results[0]["lexical_score"], 0.0);
+    assert_eq!(results[1]["score"], 0.3);
+    assert_eq!(results[1]["lexical_score"], 1.0);
+    assert_eq!(runs[0], runs[1]);
+    assert!(runs[2][0].get("lexical_score").is_none());
+}
+
+#[tokio::test]
+async fn retrieve_matches_snippet_with_reflowed_whitespace() {
+    let responses = vec![
+        "print(len(documents))".to_string(),
+        r#"FINAL("""{"results":[{"doc_id":"doc1","score":0.9,"snippet":"quick   brown fox"}],"warnings":[]}""")"#
+            .to_string(),
+    ];
+    let (addr, _handle) = rlm_runner::server::spawn_test_server_with_mock(responses).await;
+    let req = json!({
+        "query": "brown fox",
+        "documents": [{"id": "doc1", "text": "the quick brown\n  fox jumps"}]
+    });
+    let body: serde_json::Value = reqwest::Client::new()
+        .post(format!("http://{}/v1/retrieve", addr))
+        .json(&req)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let result = &body["results"][0];
+    // The original document text comes back, not the model's reflowed copy.
+    assert_eq!(result["text"], "quick brown\n  fox");
+    assert_eq!(result["spans"][0]["start"], 0);
+    assert_eq!(result["spans"][0]["end"], 17);
+}
+
+#[tokio::test]
+async fn retrieve_lexical_mode_skips_the_llm() {
+    // The only scripted turns belong to the second (auto) request; a lexical request that
+    // touched the mock would leave it answering from an exhausted queue.
+    let responses = vec![
+        "print(len(documents))".to_string(),
+        r#"FINAL("""{"results":[{"doc_id":"doc1","score":0.5,"snippet":"alpha"}],"warnings":[]}""")"#
+            .to_string(),
+    ];
+    let (addr, _handle) = rlm_runner::server::spawn_test_server_with_mock(responses).await;
+    let url = format!("http://{}/v1/retrieve", addr);
+    let client = reqwest::Client::new();
+    let documents = json!([
+        {"id": "doc1", "text": "alpha beta gamma"},
+        {"id": "doc2", "text": "the quick brown fox jumps"}
+    ]);
+
+    let req = json!({
+        "query": "brown fox",
+        "documents": documents,
+        "options": {"top_k": 1, "mode": "lexical", "include_status": true}
+    });
+    let body: serde_json::Value = client
+        .post(&url)
+        .json(&req)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["results"][0]["doc_id"], "doc2");
+    assert_eq!(body["status"], "ok");
+    let warnings = body["warnings"].as_array().unwrap();
+    assert!(warnings.iter().any(|w| w == "mode_lexical"), "{warnings:?}");
+    assert!(!warnings
+        .iter()
+        .any(|w| w.as_str().unwrap_or("").starts_with("debug_rlm_iterations")));
+
+    let req = json!({
+        "query": "alpha",
+        "documents": documents,
+        "options": {"top_k": 1, "mode": "auto", "use_fallback": false}
+    });
+    let body: serde_json::Value = client
+        .post(&url)
+        .json(&req)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["results"][0]["doc_id"], "doc1");
+    assert_eq!(body["results"][0]["text"], "alpha");
+}
+
+#[tokio::test]
+async fn retrieve_normalizes_scores_across_results() {
+    let final_resp = r#"FINAL("""{"results":[{"doc_id":"doc2","score":0.4,"snippet":"brown fox"},{"doc_id":"doc1","score":0.2,"snippet":"alpha"}],"warnings":[]}""")"#;
+    let responses = ["print(len(documents))", final_resp]
+        .repeat(2)
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    let (addr, _handle) = rlm_runner::server::spawn_test_server_with_mock(responses).await;
+    let url = format!("http://{}/v1/retrieve", addr);
+    let client = reqwest::Client::new();
+    let scores = |normalize: &'static str| {
+        let req = json!({
+            "query": "brown fox",
+            "documents": [
+                {"id": "doc1", "text": "alpha beta gamma"},
+                {"id": "doc2", "text": "the quick brown fox jumps"}
+            ],
+            "options": {"top_k": 2, "normalize": normalize}
+        });
+        let call = client.post(&url).json(&req).send();
+        async move {
+            let body: serde_json::Value = call.await.unwrap().json().await.unwrap();
+            body["results"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|r| r["score"].as_f64().unwrap())
+                .collect::<Vec<_>>()
+        }
+    };
+
+    assert_eq!(scores("max").await, vec![1.0, 0.5]);
+
+    let soft = scores("softmax").await;
+    assert!((soft.iter().sum::<f64>() - 1.0).abs() < 1e-9, "{soft:?}");
+    assert!(soft[0] > soft[1], "{soft:?}");
+}
+
+#[tokio::test]
+async fn retrieve_uses_server_default_top_k_when_options_omitted() {
+    let llm = LlmClient::Mock(MockLlm::new(vec![]));
+    let state = AppState::new_with_llm(llm).with_retrieve_defaults(RetrieveDefaults {
+        top_k: 1,
+        ..RetrieveDefaults::default()
+    });
+    let (addr, _handle) = spawn_test_server_with_state(state).await;
+    let req = json!({
+        "query": "fox",
+        "documents": [
+            {"id": "doc1", "text": "a fox"},
+            {"id": "doc2", "text": "fox fox"},
+            {"id": "doc3", "text": "fox fox fox"}
+        ]
+    });
+    let body: serde_json::Value = reqwest::Client::new()
+        .post(format!("http://{}/v1/retrieve", addr))
+        .json(&req)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 1, "body={body}");
+    assert_eq!(results[0]["doc_id"], "doc3");
+}
+
+#[tokio::test]
+async fn retrieve_response_is_cut_to_the_size_cap() {
+    let state =
+        AppState::new_with_llm(LlmClient::Mock(MockLlm::new(vec![]))).with_max_response_bytes(2000);
+    let (addr, _handle) = spawn_test_server_with_state(state).await;
+    let documents: Vec<_> = (0..3)
+        .map(|i| json!({"id": format!("doc{i}"), "text": format!("fox {}", "word ".repeat(1000))}))
+        .collect();
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}/v1/retrieve", addr))
+        .json(&json!({
+            "query": "fox",
+            "documents": documents,
+            "options": {"mode": "lexical", "top_k": 3, "max_chunk_chars": 4000}
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+    let raw = resp.bytes().await.unwrap();
+    assert!(raw.len() <= 2000, "len={}", raw.len());
+    let body: serde_json::Value = serde_json::from_slice(&raw).unwrap();
+    let warnings = body["warnings"].as_array().unwrap();
+    assert!(
+        warnings.contains(&json!("response_truncated")),
+        "body={body}"
+    );
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 3);
+    assert!(results[0]["text"].as_str().unwrap().starts_with("fox"));
+}
+
+#[tokio::test]
+async fn identical_requests_produce_identical_responses() {
+    let final_json = r#"FINAL("""{"results":[{"doc_id":"doc2","score":0.8,"snippet":"brown fox"},{"doc_id":"doc1","score":0.4,"snippet":"alpha"}],"warnings":[]}""")"#;
+    let responses = ["print(documents[1][\"metadata\"])", final_json]
+        .repeat(2)
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    let (addr, _handle) = rlm_runner::server::spawn_test_server_with_mock(responses).await;
+    let req = json!({
+        "query": "brown fox",
+        "trace_id": "eval-run-1",
+        "documents": [
+            {"id": "doc1", "text": "alpha beta gamma"},
+            {"id": "doc2", "text": "the quick brown fox", "metadata": {"z": 1, "a": [2, 3], "m": {"y": 0, "b": 1}}}
+        ],
+        "options": {"top_k": 2, "include_transcript": true, "include_lexical_score": true}
+    });
+    let client = reqwest::Client::new();
+    let mut bodies = Vec::new();
+    for _ in 0..2 {
+        let resp = client
+            .post(format!("http://{}/v1/retrieve", addr))
+            .json(&req)
+            .send()
+            .await
+            .unwrap();
+        assert!(resp.status().is_success());
+        bodies.push(resp.bytes().await.unwrap());
+    }
+    assert_eq!(bodies[0], bodies[1]);
+    let body: serde_json::Value = serde_json::from_slice(&bodies[0]).unwrap();
+    assert_eq!(body["trace_id"], "eval-run-1");
+    assert_eq!(body["results"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn retrieve_dedups_identical_snippets_when_asked() {
+    let final_resp = r#"FINAL("""{"results":[{"doc_id":"doc1","score":0.4,"snippet":"shared  clause"},{"doc_id":"doc2","score":0.9,"snippet":"Shared clause"},{"doc_id":"doc3","score":0.5,"snippet":"other"}],"warnings":[]}""")"#;
+    let turns = ["print(len(documents))", final_resp];
+    let responses = turns.iter().chain(&turns).map(|t| t.to_string()).collect();
+    let (addr, _handle) = rlm_runner::server::spawn_test_server_with_mock(responses).await;
+    let client = reqwest::Client::new();
+    let mut runs = Vec::new();
+    for dedup in [false, true] {
+        let req = json!({
+            "query": "clause",
+            "documents": [
+                {"id": "doc1", "text": "a shared  clause here"},
+                {"id": "doc2", "text": "Shared clause again"},
+                {"id": "doc3", "text": "other text"}
+            ],
+            "options": {"top_k": 3, "dedup_snippets": dedup}
+        });
+        let body: serde_json::Value = client
+            .post(format!("http://{}/v1/retrieve", addr))
+            .json(&req)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        runs.push(body);
+    }
+    assert_eq!(runs[0]["results"].as_array().unwrap().len(), 3);
+
+    let results = runs[1]["results"].as_array().unwrap();
+    let ids: Vec<_> = results
+        .iter()
+        .map(|r| r["doc_id"].as_str().unwrap())
+        .collect();
+    // The higher-scoring copy survives; doc1's text only differs by case and spacing.
+    assert_eq!(ids, ["doc2", "doc3"]);
+    let warnings = runs[1]["warnings"].as_array().unwrap();
+    assert!(warnings.iter().any(|w| w == "snippet_deduplicated: doc1"));
+}
+
+#[tokio::test]
+async fn retrieve_caps_documents_exposed_to_the_repl() {
+    let responses = vec![
+        r#"print([d["id"] for d in documents])"#.to_string(),
+        r#"FINAL("""{"results":[{"doc_id":"doc4","score":0.9,"snippet":"fox"}],"warnings":[]}""")"#
+            .to_string(),
+    ];
+    let (addr, _handle) = rlm_runner::server::spawn_test_server_with_mock(responses).await;
+    let req = json!({
+        "query": "brown fox",
+        "documents": [
+            {"id": "doc1", "text": "nothing relevant"},
+            {"id": "doc2", "text": "a brown dog"},
+            {"id": "doc3", "text": "unrelated"},
+            {"id": "doc4", "text": "the brown fox"}
+        ],
+        "options": {"max_repl_documents": 2, "include_transcript": true}
+    });
+    let body: serde_json::Value = reqwest::Client::new()
+        .post(format!("http://{}/v1/retrieve", addr))
+        .json(&req)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let feedback = body["transcript"][0]["feedback"].as_str().unwrap();
+    assert!(feedback.contains("['doc4', 'doc2']"), "{feedback}");
+    let warnings = body["warnings"].as_array().unwrap();
+    assert!(warnings
+        .iter()
+        .any(|w| w == "repl_documents_capped: 2 of 4"));
+    assert_eq!(body["results"][0]["doc_id"], "doc4");
+}
+
+#[tokio::test]
+async fn retrieve_includes_final_repl_state_when_requested() {
+    let turns = [
+        r#"hits = [d["id"] for d in documents if "fox" in d["text"]]
+print(hits)"#,
+        r#"FINAL("""{"results":[{"doc_id":"doc2","score":0.8,"snippet":"brown fox"}],"warnings":[]}""")"#,
+    ];
+    let responses = turns.iter().chain(&turns).map(|t| t.to_string()).collect();
+    let (addr, _handle) = rlm_runner::server::spawn_test_server_with_mock(responses).await;
+    let client = reqwest::Client::new();
+    let mut bodies = Vec::new();
+    for include_state in [true, false] {
+        let req = json!({
+            "query": "fox",
+            "documents": [
+                {"id": "doc1", "text": "alpha"},
+                {"id": "doc2", "text": "quick brown fox"}
+            ],
+            "options": {"include_state": include_state}
+        });
+        let body: serde_json::Value = client
+            .post(format!("http://{}/v1/retrieve", addr))
+            .json(&req)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        bodies.push(body);
+    }
+    let state = &bodies[0]["state"];
+    assert_eq!(
+        state["hits"],
+        json!({"t": "List", "v": [{"t": "Str", "v": "doc2"}]})
+    );
+    assert_eq!(state["top_k"], json!({"t": "Int", "v": 5}));
+    // The documents list is redacted: clients already have it.
+    assert!(state.get("documents").is_none());
+    assert!(bodies[1].get("state").is_none());
+}
+
+#[tokio::test]
+async fn retrieve_lexical_case_sensitive_matches_exact_case_only() {
+    let (addr, _handle) = rlm_runner::server::spawn_test_server_with_mock(vec![]).await;
+    let client = reqwest::Client::new();
+    let mut ids = Vec::new();
+    for case_sensitive in [false, true] {
+        let req = json!({
+            "query": "AWS",
+            "documents": [
+                {"id": "doc1", "text": "the paws of a cat"},
+                {"id": "doc2", "text": "deployed on AWS today"}
+            ],
+            "options": {"mode": "lexical", "case_sensitive": case_sensitive}
+        });
+        let body: serde_json::Value = client
+            .post(format!("http://{}/v1/retrieve", addr))
+            .json(&req)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let results = body["results"].as_array().unwrap().clone();
+        ids.push(
+            results
+                .iter()
+                .map(|r| r["doc_id"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>(),
+        );
+        if case_sensitive {
+            assert_eq!(results[0]["text"], "deployed on AWS today");
+            assert_eq!(results[0]["spans"][0]["start"], 12);
+        }
+    }
+    assert_eq!(ids[0], ["doc1", "doc2"]);
+    assert_eq!(ids[1], ["doc2"]);
+}
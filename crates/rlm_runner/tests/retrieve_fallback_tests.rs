@@ -35,3 +35,209 @@ async fn retrieve_falls_back_when_llm_never_returns_final() {
         .iter()
         .any(|w| w.as_str().unwrap_or("").contains("fallback_used")));
 }
+
+#[tokio::test]
+async fn retrieve_handles_multibyte_documents_without_panicking() {
+    // The first turn slices `text` in the REPL; the loop then never finalizes, so the
+    // char-based fallback span extraction runs on the same emoji/CJK text.
+    let responses = vec![
+        "print(documents[0][\"text\"][:5])".to_string(),
+        "print(len(documents))".to_string(),
+    ];
+    let (addr, _handle) = rlm_runner::server::spawn_test_server_with_mock(responses).await;
+    let url = format!("http://{}/v1/retrieve", addr);
+
+    let text = format!(
+        "{}🦀 東京 tower 😀 観光 {}",
+        "é".repeat(40),
+        "語".repeat(40)
+    );
+    let req = json!({
+        "query": "tower",
+        "documents": [{"id": "doc1", "text": text}],
+        "options": {"top_k": 1, "max_chunk_chars": 9}
+    });
+
+    let client = reqwest::Client::new();
+    let resp = client.post(url).json(&req).send().await.unwrap();
+    assert!(resp.status().is_success());
+    let body: serde_json::Value = resp.json().await.unwrap();
+
+    let result = &body["results"][0];
+    let chunk = result["text"].as_str().unwrap();
+    assert_eq!(chunk.chars().count(), 9);
+    let span = &result["spans"][0];
+    let (start, end) = (
+        span["start"].as_u64().unwrap() as usize,
+        span["end"].as_u64().unwrap() as usize,
+    );
+    let hit: String = chunk.chars().skip(start).take(end - start).collect();
+    assert_eq!(hit, "tower");
+}
+
+#[tokio::test]
+async fn retrieve_reports_llm_failed_status_without_fallback() {
+    let responses = vec!["print(len(documents))".to_string(); 4];
+    let (addr, _handle) = rlm_runner::server::spawn_test_server_with_mock(responses).await;
+    let url = format!("http://{}/v1/retrieve", addr);
+
+    let req = json!({
+        "query": "brown fox",
+        "documents": [{"id": "doc1", "text": "the quick brown fox jumps"}],
+        "options": {"use_fallback": false, "include_status": true}
+    });
+
+    let client = reqwest::Client::new();
+    let resp = client.post(url).json(&req).send().await.unwrap();
+    assert!(resp.status().is_success());
+    let body: serde_json::Value = resp.json().await.unwrap();
+
+    assert_eq!(body["status"], "llm_failed");
+    assert!(body["results"].as_array().unwrap().is_empty());
+    assert!(body["warnings"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|w| w == "llm_failed: final_not_found"));
+}
+
+#[tokio::test]
+async fn retrieve_fallback_matches_sharp_s_by_casefold() {
+    let responses = vec!["print(len(documents))".to_string(); 4];
+    let (addr, _handle) = rlm_runner::server::spawn_test_server_with_mock(responses).await;
+    let url = format!("http://{}/v1/retrieve", addr);
+
+    let req = json!({
+        "query": "STRASSE",
+        "documents": [
+            {"id": "doc1", "text": "nothing relevant here"},
+            {"id": "doc2", "text": "Die Straße ist lang"}
+        ],
+        "options": {"top_k": 1, "max_chunk_chars": 50}
+    });
+
+    let client = reqwest::Client::new();
+    let resp = client.post(url).json(&req).send().await.unwrap();
+    assert!(resp.status().is_success());
+    let body: serde_json::Value = resp.json().await.unwrap();
+
+    let result = &body["results"][0];
+    assert_eq!(result["doc_id"], "doc2");
+    let text = result["text"].as_str().unwrap();
+    let span = &result["spans"][0];
+    let (start, end) = (
+        span["start"].as_u64().unwrap() as usize,
+        span["end"].as_u64().unwrap() as usize,
+    );
+    let hit: String = text.chars().skip(start).take(end - start).collect();
+    assert_eq!(hit, "Straße");
+}
+
+#[tokio::test]
+async fn retrieve_max_iterations_option_limits_the_loop() {
+    let responses = vec!["print(len(documents))".to_string(); 4];
+    let (addr, _handle) = rlm_runner::server::spawn_test_server_with_mock(responses).await;
+    let url = format!("http://{}/v1/retrieve", addr);
+
+    let req = json!({
+        "query": "brown fox",
+        "documents": [
+            {"id": "doc1", "text": "alpha beta gamma"},
+            {"id": "doc2", "text": "the quick brown fox jumps"}
+        ],
+        "options": {"top_k": 1, "max_iterations": 1}
+    });
+
+    let client = reqwest::Client::new();
+    let resp = client.post(url).json(&req).send().await.unwrap();
+    assert!(resp.status().is_success());
+    let body: serde_json::Value = resp.json().await.unwrap();
+
+    let warnings: Vec<&str> = body["warnings"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|w| w.as_str())
+        .collect();
+    assert!(
+        warnings.contains(&"debug_rlm_iterations: 1"),
+        "{warnings:?}"
+    );
+    assert!(
+        warnings.contains(&"fallback_used: llm_final_not_found"),
+        "{warnings:?}"
+    );
+    assert_eq!(body["results"][0]["doc_id"], "doc2");
+}
+
+#[tokio::test]
+async fn retrieve_lexical_snippets_snap_to_word_boundaries() {
+    let (addr, _handle) = rlm_runner::server::spawn_test_server_with_mock(vec![]).await;
+    let url = format!("http://{}/v1/retrieve", addr);
+    let words = ["alpha", "bravo", "charlie", "delta", "echo", "foxtrot"];
+    let filler = words.repeat(10).join(" ");
+
+    let req = json!({
+        "query": "target",
+        "documents": [{"id": "doc1", "text": format!("{filler} target {filler}")}],
+        "options": {
+            "mode": "lexical",
+            "max_chunk_chars": 41,
+            "snippet_boundary": "word"
+        }
+    });
+    let client = reqwest::Client::new();
+    let body: serde_json::Value = client
+        .post(url)
+        .json(&req)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    let result = &body["results"][0];
+    let text = result["text"].as_str().unwrap();
+    assert!(text.chars().count() <= 41);
+    assert!(
+        text.split(' ').all(|w| w == "target" || words.contains(&w)),
+        "text={text:?}"
+    );
+    let (start, end) = (
+        result["spans"][0]["start"].as_u64().unwrap() as usize,
+        result["spans"][0]["end"].as_u64().unwrap() as usize,
+    );
+    let hit: String = text.chars().skip(start).take(end - start).collect();
+    assert_eq!(hit, "target");
+}
+
+#[tokio::test]
+async fn retrieve_fallback_reports_its_scoring_method() {
+    let responses = vec!["print(len(documents))".to_string(); 2];
+    let (addr, _handle) = rlm_runner::server::spawn_test_server_with_mock(responses).await;
+    let url = format!("http://{}/v1/retrieve", addr);
+
+    let req = json!({
+        "query": "brown fox",
+        "documents": [{"id": "doc1", "text": "the quick brown fox jumps"}],
+        "options": {"top_k": 1, "max_iterations": 1, "use_fallback": true}
+    });
+
+    let client = reqwest::Client::new();
+    let resp = client.post(url).json(&req).send().await.unwrap();
+    assert!(resp.status().is_success());
+    let body: serde_json::Value = resp.json().await.unwrap();
+
+    let warnings: Vec<&str> = body["warnings"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|w| w.as_str())
+        .collect();
+    assert!(
+        warnings.contains(&"fallback_used: llm_final_not_found"),
+        "{warnings:?}"
+    );
+    assert!(warnings.contains(&"fallback_scorer: tf"), "{warnings:?}");
+}
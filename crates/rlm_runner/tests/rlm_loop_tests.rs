@@ -0,0 +1,335 @@
+use std::time::Duration;
+
+use python_string_repl::repl::state::ReplState;
+use python_string_repl::repl::{ReplConfig, ReplEngine};
+
+use rlm_runner::final_parser::FinalMarkers;
+use rlm_runner::llm_client::{LlmClient, MockLlm};
+use rlm_runner::rlm_loop::{run_rlm_loop, PhaseConfig, RlmLoopConfig, RlmLoopResult};
+use tokio_util::sync::CancellationToken;
+
+async fn run_with_mock(responses: &[&str], cfg: &RlmLoopConfig) -> RlmLoopResult {
+    let llm = LlmClient::Mock(MockLlm::new(
+        responses.iter().map(|r| r.to_string()).collect(),
+    ));
+    let repl = ReplEngine::new(ReplConfig::default());
+    run_rlm_loop(
+        &llm,
+        &repl,
+        "system",
+        "user",
+        "query",
+        ReplState::new(),
+        cfg,
+    )
+    .await
+}
+
+#[tokio::test]
+async fn loop_feeds_back_a_resource_limit_once_then_stops_on_repeat() {
+    let result = run_with_mock(
+        &[
+            "xs = range(100000)",
+            "xs = range(100000)",
+            r#"FINAL("done")"#,
+        ],
+        &RlmLoopConfig::default(),
+    )
+    .await;
+    assert_eq!(result.final_text, None);
+    assert_eq!(result.iterations, 2);
+    assert!(result.transcript[0]
+        .feedback
+        .as_deref()
+        .is_some_and(|f| f.contains("LIMIT_HIT:")));
+    assert!(result
+        .warnings
+        .iter()
+        .any(|w| w == "repl_limit_repeated: ResourceLimitExceeded"));
+}
+
+#[tokio::test]
+async fn loop_recovers_after_narrowing_past_a_resource_limit() {
+    let result = run_with_mock(
+        &["xs = range(100000)", "xs = range(10)", r#"FINAL("done")"#],
+        &RlmLoopConfig::default(),
+    )
+    .await;
+    assert_eq!(result.final_text.as_deref(), Some("done"));
+    assert_eq!(result.iterations, 3);
+}
+
+#[tokio::test]
+async fn loop_stops_on_system_exit() {
+    let result = run_with_mock(
+        &["import sys\nsys.exit()", r#"FINAL("done")"#],
+        &RlmLoopConfig::default(),
+    )
+    .await;
+    assert_eq!(result.final_text, None);
+    assert_eq!(result.iterations, 1);
+    assert!(result
+        .warnings
+        .iter()
+        .any(|w| w == "repl_unrecoverable: SystemExit"));
+}
+
+#[tokio::test]
+async fn loop_feeds_back_fixable_errors() {
+    let result = run_with_mock(
+        &["print(undefined_name)", "print(1)", r#"FINAL("done")"#],
+        &RlmLoopConfig::default(),
+    )
+    .await;
+    assert_eq!(result.final_text.as_deref(), Some("done"));
+    assert_eq!(result.iterations, 3);
+}
+
+#[tokio::test]
+async fn loop_stops_when_cancelled_mid_request() {
+    // A model that never finalizes: without cancellation this would run all 20 iterations.
+    let llm = LlmClient::Mock(
+        MockLlm::new(vec!["print(1)".to_string(); 20]).with_delay(Duration::from_millis(50)),
+    );
+    let repl = ReplEngine::new(ReplConfig::default());
+    let cancel = CancellationToken::new();
+    let cfg = RlmLoopConfig {
+        cancel: Some(cancel.clone()),
+        ..RlmLoopConfig::default()
+    };
+    // Simulates the server dropping the request when the client disconnects.
+    let dropper = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        drop(cancel.drop_guard());
+    });
+    let result = run_rlm_loop(
+        &llm,
+        &repl,
+        "system",
+        "user",
+        "query",
+        ReplState::new(),
+        &cfg,
+    )
+    .await;
+    dropper.await.unwrap();
+    assert_eq!(result.final_text, None);
+    assert!(result.iterations < 5, "iterations={}", result.iterations);
+    assert_eq!(
+        result.warnings.last().map(String::as_str),
+        Some("cancelled")
+    );
+}
+
+#[tokio::test]
+async fn loop_with_unrestricted_phases_finalizes_on_first_turn() {
+    let cfg = RlmLoopConfig {
+        phases: PhaseConfig::unrestricted(),
+        ..RlmLoopConfig::default()
+    };
+    let result = run_with_mock(&[r#"FINAL("summary")"#], &cfg).await;
+    assert_eq!(result.final_text.as_deref(), Some("summary"));
+    assert_eq!(result.iterations, 1);
+    assert!(result.warnings.is_empty(), "warnings={:?}", result.warnings);
+}
+
+#[tokio::test]
+async fn loop_sends_custom_starter_code() {
+    let cfg = RlmLoopConfig {
+        phases: PhaseConfig::retrieval().with_starter_code(Some("print(len(context))".to_string())),
+        ..RlmLoopConfig::default()
+    };
+    let result = run_with_mock(&[r#"FINAL("early")"#, "print(1)", r#"FINAL("done")"#], &cfg).await;
+    assert_eq!(result.final_text.as_deref(), Some("done"));
+    let feedback = result.transcript[0].feedback.as_deref().unwrap();
+    assert!(feedback.starts_with("REPL_REQUIRED:"));
+    assert!(feedback.ends_with("- Start by running:\nprint(len(context))"));
+    assert!(!feedback.contains("rank_documents"));
+
+    let cfg = RlmLoopConfig {
+        phases: PhaseConfig::retrieval().with_starter_code(None),
+        ..RlmLoopConfig::default()
+    };
+    let result = run_with_mock(&[r#"FINAL("early")"#, "print(1)", r#"FINAL("done")"#], &cfg).await;
+    let feedback = result.transcript[0].feedback.as_deref().unwrap();
+    assert!(
+        feedback.ends_with("no markdown fences)."),
+        "feedback={feedback}"
+    );
+}
+
+#[tokio::test]
+async fn loop_finalizes_on_configured_custom_marker() {
+    let cfg = RlmLoopConfig {
+        final_markers: FinalMarkers::default()
+            .with_answer_pattern(r"(?s)<answer>(.*?)</answer>")
+            .unwrap(),
+        ..RlmLoopConfig::default()
+    };
+    let result = run_with_mock(&["print(1)", "<answer>done</answer>"], &cfg).await;
+    assert_eq!(result.final_text.as_deref(), Some("done"));
+    assert_eq!(result.iterations, 2);
+}
+
+#[tokio::test]
+async fn loop_blank_code_does_not_count_as_repl_turn() {
+    let result = run_with_mock(
+        &[
+            "   \n\t\n",
+            r#"FINAL("early")"#,
+            "print(1)",
+            r#"FINAL("done")"#,
+        ],
+        &RlmLoopConfig::default(),
+    )
+    .await;
+    assert_eq!(result.final_text.as_deref(), Some("done"));
+    assert_eq!(result.iterations, 4);
+    assert_eq!(result.transcript[0].code, None);
+    assert!(result.transcript[0]
+        .feedback
+        .as_deref()
+        .unwrap()
+        .starts_with("NO_CODE:"));
+    assert!(result.warnings.contains(&"no_code".to_string()));
+    // The FINAL after blank code is still rejected as pre-REPL.
+    assert!(result.warnings.contains(&"final_before_repl".to_string()));
+    assert!(result.transcript[1]
+        .feedback
+        .as_deref()
+        .unwrap()
+        .starts_with("REPL_REQUIRED:"));
+}
+
+#[tokio::test]
+async fn loop_falls_back_to_next_model_in_chain() {
+    let llm = LlmClient::Mock(
+        MockLlm::new(vec!["print(1)".to_string(), r#"FINAL("done")"#.to_string()])
+            .with_failing_models(vec!["primary".to_string()]),
+    );
+    let repl = ReplEngine::new(ReplConfig::default());
+    let cfg = RlmLoopConfig {
+        max_retries: 1,
+        model_chain: vec!["primary".to_string(), "secondary".to_string()],
+        ..RlmLoopConfig::default()
+    };
+    let result = run_rlm_loop(
+        &llm,
+        &repl,
+        "system",
+        "user",
+        "query",
+        ReplState::new(),
+        &cfg,
+    )
+    .await;
+    assert_eq!(result.final_text.as_deref(), Some("done"));
+    assert_eq!(result.iterations, 2);
+    assert_eq!(
+        result.warnings,
+        vec![
+            "llm_error_retry: http error: mock model unavailable: primary".to_string(),
+            "llm_model_fallback: primary -> secondary: http error: mock model unavailable: primary"
+                .to_string(),
+            "llm_model: secondary".to_string(),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn loop_blank_reply_falls_back_only_when_a_next_model_exists() {
+    let cfg = RlmLoopConfig {
+        max_retries: 0,
+        model_chain: vec!["a".to_string(), "b".to_string()],
+        ..RlmLoopConfig::default()
+    };
+    let result = run_with_mock(&["  ", "print(1)", r#"FINAL("done")"#], &cfg).await;
+    assert_eq!(result.final_text.as_deref(), Some("done"));
+    assert_eq!(result.iterations, 2);
+    assert!(result
+        .warnings
+        .contains(&"llm_model_fallback: a -> b: empty response".to_string()));
+    assert!(!result.warnings.contains(&"no_code".to_string()));
+}
+
+#[tokio::test]
+async fn loop_ramps_temperature_across_retries() {
+    let llm = LlmClient::Mock(
+        MockLlm::new(vec!["print(1)".to_string(), r#"FINAL("done")"#.to_string()])
+            .with_failing_models(vec!["flaky".to_string()]),
+    );
+    let repl = ReplEngine::new(ReplConfig::default());
+    let cfg = RlmLoopConfig {
+        max_retries: 3,
+        model_chain: vec!["flaky".to_string(), "stable".to_string()],
+        retry_temperature_step: 0.25,
+        max_retry_temperature: 0.5,
+        ..RlmLoopConfig::default()
+    };
+    let result = run_rlm_loop(
+        &llm,
+        &repl,
+        "system",
+        "user",
+        "query",
+        ReplState::new(),
+        &cfg,
+    )
+    .await;
+    assert_eq!(result.final_text.as_deref(), Some("done"));
+
+    let LlmClient::Mock(mock) = &llm else {
+        unreachable!()
+    };
+    let temps: Vec<(String, f32)> = mock
+        .requests()
+        .into_iter()
+        .map(|r| (r.model.unwrap_or_default(), r.temperature))
+        .collect();
+    let expected = [
+        ("flaky", 0.0),
+        ("flaky", 0.25),
+        ("flaky", 0.5),
+        ("flaky", 0.5),
+        // The next model, and the next iteration, start over at 0.0.
+        ("stable", 0.0),
+        ("stable", 0.0),
+    ];
+    let expected: Vec<(String, f32)> = expected.iter().map(|(m, t)| (m.to_string(), *t)).collect();
+    assert_eq!(temps, expected);
+}
+
+#[tokio::test]
+async fn loop_stops_once_token_budget_is_spent() {
+    let llm = LlmClient::Mock(
+        MockLlm::new(vec!["print(1)".to_string(); 5]).with_tokens_per_response(100),
+    );
+    let repl = ReplEngine::new(ReplConfig::default());
+    let cfg = RlmLoopConfig {
+        max_total_tokens: Some(250),
+        ..RlmLoopConfig::default()
+    };
+    let result = run_rlm_loop(
+        &llm,
+        &repl,
+        "system",
+        "user",
+        "query",
+        ReplState::new(),
+        &cfg,
+    )
+    .await;
+
+    // The third reply crosses 250 and is still run; no fourth request is sent.
+    assert_eq!(result.final_text, None);
+    assert_eq!(result.iterations, 3);
+    assert_eq!(result.total_tokens, 300);
+    assert_eq!(
+        result.warnings.last().map(String::as_str),
+        Some("token_budget_exceeded")
+    );
+    let LlmClient::Mock(mock) = &llm else {
+        unreachable!()
+    };
+    assert_eq!(mock.requests().len(), 3);
+}
@@ -0,0 +1,42 @@
+use std::sync::Arc;
+
+use rlm_runner::llm_client::{LlmClient, MockLlm};
+use rlm_runner::server::{spawn_test_server_with_state, AppState};
+use serde_json::json;
+use tokio::sync::Semaphore;
+
+#[tokio::test]
+async fn retrieve_sheds_requests_over_concurrency_limit() {
+    // Every LLM call waits on the gate, so the first two retrieves hold their slots until
+    // all the others have been turned away.
+    let gate = Arc::new(Semaphore::new(0));
+    let llm = LlmClient::Mock(MockLlm::new(vec![]).with_gate(gate.clone()));
+    let state = AppState::new_with_llm(llm).with_max_concurrent_retrieves(2);
+    let (addr, _handle) = spawn_test_server_with_state(state).await;
+    let url = format!("http://{}/v1/retrieve", addr);
+    let req = json!({
+        "query": "fox",
+        "documents": [{"id": "doc1", "text": "the quick brown fox"}],
+        "options": {"top_k": 1}
+    });
+
+    let client = reqwest::Client::new();
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    for _ in 0..6 {
+        let (call, tx) = (client.post(&url).json(&req).send(), tx.clone());
+        tokio::spawn(async move {
+            let _ = tx.send(call.await.unwrap().status().as_u16());
+        });
+    }
+    let mut statuses = Vec::new();
+    for _ in 0..4 {
+        statuses.push(rx.recv().await.unwrap());
+    }
+    assert_eq!(statuses, [503; 4]);
+
+    gate.add_permits(1);
+    for _ in 0..2 {
+        statuses.push(rx.recv().await.unwrap());
+    }
+    assert_eq!(statuses[4..], [200; 2]);
+}